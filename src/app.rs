@@ -5,27 +5,87 @@
 
 use anyhow::{Context, Result, anyhow};
 
-use log::{error, info};
+use jiff::Zoned;
+use log::{debug, error, info, warn};
 use notify_rust::Notification;
-use rdev::{EventType, Key, listen, simulate};
-use std::collections::HashSet;
-use std::path::PathBuf;
-use std::time::Duration;
-use tokio::sync::mpsc::unbounded_channel;
+use rdev::{EventType, Key, listen};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::{oneshot, watch};
 
-use crate::asr::{Asr, download_model};
-use crate::audio::{Audio, AudioRecorder};
-use crate::config::{Config, Trigger};
-use crate::keyboard::paste;
+use crate::asr::{Transcriber, transcriber};
+use crate::audio::{Audio, AudioRecorder, Stopped};
+use crate::config::{
+    ActivationConfig, Config, InputWarmup, LowConfidence, ModelConfig, OutputMode, Trigger,
+};
+use crate::formatting;
+use crate::history::AudioHistory;
+use crate::hooks::Hooks;
+use crate::keyboard::{Keyboard, System, erase, paste, tap, warm_up};
+use crate::output;
+use crate::recordings::Recordings;
+use crate::recovery::{self, CrashRecovery};
+use crate::status::{Status, StatusLine};
+use crate::window;
+
+/// How often the focused window is checked while VAD listens, with
+/// `blocked_apps`.
+const FOCUS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the config files are checked for changes.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Attempts at loading a config file that changed on disk, as it can be
+/// caught half written.
+const RELOAD_ATTEMPTS: usize = 3;
+
+/// Wait between two attempts at loading a changed config file.
+const RELOAD_RETRY_DELAY: Duration = Duration::from_millis(200);
 
 /// Represents the current state of the application.
 ///
 /// This struct tracks whether the modifier key is pressed and whether
 /// audio recording is currently in progress.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 struct State {
     pressed_keys: HashSet<Key>,
     recording: bool,
+    /// VAD listening paused while a blocked application is focused
+    blocked: bool,
+    /// Push-to-talk keys released, recording stops at this time
+    stop_at: Option<tokio::time::Instant>,
+    /// The `cancel_keys` pressed
+    cancel_pressed: HashSet<Key>,
+    /// Recording was cancelled while the push-to-talk keys were held, they
+    /// must be released before recording again
+    cancelled: bool,
+}
+
+impl State {
+    /// The stream ended the recording at `max_recording_secs`. Push-to-talk
+    /// keys still `held` (e.g. stuck) must be released before recording
+    /// again.
+    fn capped(&mut self, held: bool) {
+        self.recording = false;
+        self.stop_at = None;
+        self.cancelled = held;
+    }
+
+    /// The push-to-talk keys were pressed, cancelling the pending stop (of
+    /// the release grace) if any. Whether there was one.
+    fn pressed(&mut self) -> bool {
+        self.stop_at.take().is_some()
+    }
+
+    /// The push-to-talk keys were released while recording. Whether to stop
+    /// now, otherwise at `stop_at` after `release_grace_ms`.
+    fn released(&mut self, release_grace_ms: u64) -> bool {
+        self.stop_at = release_deadline(tokio::time::Instant::now(), release_grace_ms);
+        self.stop_at.is_none()
+    }
 }
 
 /// Main application struct that coordinates all components.
@@ -38,52 +98,845 @@ pub struct App {
     recorder: AudioRecorder,
     // asr: Asr,
     config: Config,
-    // rx_audio: UnboundedReceiver<PathBuf>,
+    /// Explicit config files, empty means the default location
+    config_paths: Vec<PathBuf>,
+    tx_audio: UnboundedSender<Audio>,
+    /// Shares live config updates with the transcription task
+    tx_config: watch::Sender<Config>,
+    /// Confirm key presses, awaited by the transcription task before autosend
+    tx_keys: UnboundedSender<Key>,
+    /// Resolves when the transcription task is done (after one with `--once`)
+    rx_done: oneshot::Receiver<()>,
+    status: StatusLine,
+    languages: Languages,
+    /// Shares the active language with the transcription task
+    tx_language: watch::Sender<Option<String>>,
+    /// Last time recording started or stopped, for `idle_exit_timeout`
+    last_activity: tokio::time::Instant,
+    /// Runs `[hooks]` when recording starts and stops
+    hooks: Hooks,
 }
 
-async fn handle_audio(asr: &mut Asr, config: &Config, audio: Audio) -> Result<()> {
-    let samples: Option<Vec<f32>> = match audio {
-        Audio::Warm => {
-            asr.load().expect("Load");
-            None
+/// Waits for requests to reload the configuration (SIGHUP on unix).
+struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl ReloadSignal {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .context("Installing SIGHUP handler")?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if self.hangup.recv().await.is_some() {
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+/// Notices edits of the config files by polling their modification times,
+/// which also catches editors saving by replacing the file.
+struct ConfigWatch {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl ConfigWatch {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let modified = modified(&path);
+                    (path, modified)
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether a file was written since the last call. Removing a file isn't
+    /// a change, the config in effect is kept.
+    fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last {
+                changed |= modified.is_some();
+                *last = modified;
+            }
+        }
+        changed
+    }
+}
+
+/// When `path` was last modified, `None` when it doesn't exist.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Loads the config from `paths`, retrying up to `attempts` times on errors.
+async fn load_config(paths: &[PathBuf], attempts: usize) -> Result<Config> {
+    let mut attempt = 1;
+    loop {
+        match Config::load(paths) {
+            Ok(config) => return Ok(config),
+            Err(err) if attempt < attempts => {
+                debug!("Could not load the config ({err:#}), retrying");
+                attempt += 1;
+                tokio::time::sleep(RELOAD_RETRY_DELAY).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether changing the setting `name` (see [`Config::changes`]) recreates
+/// the audio recorder.
+fn recreates_recorder(name: &str) -> bool {
+    name.starts_with("audio.")
+        || name.starts_with("paths.")
+        || name == "activation.trigger"
+        || name == "activation.max_recording_secs"
+}
+
+/// Cycles through the configured transcription languages.
+#[derive(Debug, Default)]
+struct Languages {
+    languages: Vec<String>,
+    current: usize,
+}
+
+impl Languages {
+    fn new(languages: Vec<String>) -> Self {
+        Self {
+            languages,
+            current: 0,
+        }
+    }
+
+    /// The active language, `None` to auto-detect.
+    fn current(&self) -> Option<&str> {
+        self.languages.get(self.current).map(String::as_str)
+    }
+
+    /// Switches to the next language, wrapping around.
+    fn next(&mut self) -> Option<&str> {
+        if !self.languages.is_empty() {
+            self.current = (self.current + 1) % self.languages.len();
+        }
+        self.current()
+    }
+}
+
+/// Suppresses transcripts repeating the previous ones, likely an echo.
+#[derive(Debug, Default)]
+struct DuplicateGuard {
+    /// Latest identical transcripts, oldest first
+    recent: VecDeque<(Instant, String)>,
+}
+
+impl DuplicateGuard {
+    /// Records `text`, returns whether it repeats the previous `limit`
+    /// transcripts from within `window`.
+    fn is_duplicate(&mut self, text: &str, now: Instant, limit: usize, window: Duration) -> bool {
+        if limit == 0 {
+            return false;
+        }
+        self.recent
+            .retain(|(at, previous)| previous == text && now.duration_since(*at) <= window);
+        let duplicate = self.recent.len() >= limit;
+        self.recent.push_back((now, text.to_string()));
+        while self.recent.len() > limit {
+            self.recent.pop_front();
+        }
+        duplicate
+    }
+}
+
+/// Shows the notifications about a transcription, recorded instead in tests.
+trait Notifier {
+    fn notify(&mut self, config: &Config, summary: &str, content: &str);
+}
+
+/// Desktop notifications, shown when `activation.notify` is on.
+struct Desktop;
+
+impl Notifier for Desktop {
+    fn notify(&mut self, config: &Config, summary: &str, content: &str) {
+        config.notify(summary, content);
+    }
+}
+
+/// The optional "Transcribing..." notification.
+///
+/// [`ProcessingNotification::finish`] replaces it with the result, otherwise
+/// it is closed when dropped, e.g. when the transcription failed.
+struct ProcessingNotification {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    handle: Option<notify_rust::NotificationHandle>,
+}
+
+impl ProcessingNotification {
+    fn show(config: &Config) -> Self {
+        let enabled = config.activation.notify && config.activation.notify_processing;
+        #[cfg(all(unix, not(target_os = "macos")))]
+        let handle = enabled
+            .then(|| {
+                Notification::new()
+                    .summary("Transcribing...")
+                    .icon("audio-input-microphone")
+                    .show()
+                    .map_err(|err| error!("Cannot show notification: {err}"))
+                    .ok()
+            })
+            .flatten();
+        #[cfg(not(all(unix, not(target_os = "macos"))))]
+        if enabled {
+            config.notify("Transcribing...", "");
+        }
+        Self {
+            #[cfg(all(unix, not(target_os = "macos")))]
+            handle,
+        }
+    }
+
+    /// Shows the result, updating the processing notification in place when
+    /// the platform allows it.
+    #[cfg_attr(not(all(unix, not(target_os = "macos"))), allow(unused_mut))]
+    fn finish(
+        mut self,
+        config: &Config,
+        notifier: &mut impl Notifier,
+        summary: &str,
+        content: &str,
+    ) {
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if let Some(mut handle) = self.handle.take() {
+            handle.summary(summary).body(content);
+            handle.update();
+            return;
+        }
+        notifier.notify(config, summary, content);
+    }
+}
+
+impl Drop for ProcessingNotification {
+    fn drop(&mut self) {
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if let Some(handle) = self.handle.take() {
+            handle.close();
+        }
+    }
+}
+
+/// When to exit for being idle since `last_activity`, `None` while
+/// recording or when `timeout` is disabled.
+fn idle_deadline(
+    last_activity: tokio::time::Instant,
+    timeout: Option<u64>,
+    recording: bool,
+) -> Option<tokio::time::Instant> {
+    let timeout = timeout.filter(|_| !recording)?;
+    Some(last_activity + Duration::from_secs(timeout))
+}
+
+/// The input simulated at startup: none with `clipboard_only`, which must
+/// start where simulated input fails.
+fn input_warmup(activation: &ActivationConfig) -> InputWarmup {
+    match activation.output_mode {
+        OutputMode::ClipboardOnly => InputWarmup::None,
+        OutputMode::Paste | OutputMode::Type => activation.input_warmup,
+    }
+}
+
+/// When recording stops after the push-to-talk keys were released at `now`,
+/// `None` to stop right away.
+fn release_deadline(
+    now: tokio::time::Instant,
+    release_grace_ms: u64,
+) -> Option<tokio::time::Instant> {
+    (release_grace_ms > 0).then(|| now + Duration::from_millis(release_grace_ms))
+}
+
+/// Sleeps until `deadline`, forever without one.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for `confirm_key`, ignoring presses from before the paste.
+async fn wait_for_confirm(
+    keys: &mut UnboundedReceiver<Key>,
+    confirm_key: Key,
+    timeout: Duration,
+) -> bool {
+    while keys.try_recv().is_ok() {}
+    let confirmed = async {
+        while let Some(key) = keys.recv().await {
+            if key == confirm_key {
+                return true;
+            }
+        }
+        false
+    };
+    tokio::time::timeout(timeout, confirmed)
+        .await
+        .unwrap_or(false)
+}
+
+/// Calls `paste` once `delay` elapsed, letting the target window get ready
+/// for input.
+async fn paste_after(delay: Duration, paste: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !delay.is_zero() {
+        debug!("Waiting {delay:?} before pasting");
+        tokio::time::sleep(delay).await;
+    }
+    paste()
+}
+
+/// Saves the recording with `save_recordings`, in the background.
+fn save_recording(config: &Config, samples: &[f32]) {
+    let Some(recordings) = Recordings::new(config) else {
+        return;
+    };
+    if samples.is_empty() {
+        return;
+    }
+    let samples = samples.to_vec();
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = recordings.save(&samples) {
+            warn!("Could not save the recording: {err:#}");
+        }
+    });
+}
+
+/// Transcribes the audio left over by a crash.
+///
+/// The transcript is logged and shown in a notification rather than pasted,
+/// since there is no telling which window has focus at startup.
+fn recover(asr: &mut dyn Transcriber, config: &Config, recovery: &CrashRecovery) {
+    let result = recovery.take_leftover().and_then(|samples| {
+        let Some(samples) = samples else {
+            return Ok(None);
+        };
+        asr.load()?;
+        asr.run(samples, config).map(Some)
+    });
+    match result {
+        Ok(Some(text)) => {
+            info!("Recovered transcript: {text}");
+            config.notify("Recovered transcript", &text);
+        }
+        Ok(None) => (),
+        Err(err) => error!("Could not recover the pending audio: {err:#}"),
+    }
+}
+
+/// Consumes the audio sent by the recorder.
+trait AudioHandler {
+    async fn handle(&mut self, audio: Audio);
+
+    /// When to call [`AudioHandler::unload`] without new audio, `None` to
+    /// wait for audio only.
+    fn unload_deadline(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+
+    /// Frees the resources kept between audio events.
+    fn unload(&mut self) {}
+}
+
+/// Unloads the model once no audio arrived for `model.unload_after_secs`.
+///
+/// Counting from `Audio::Warm`, a recording held longer than that unloads
+/// the model before its audio arrives, the run then loads it again.
+#[derive(Debug, Default)]
+struct UnloadTimer {
+    /// When the last audio was handled, `None` once unloaded
+    last_audio: Option<tokio::time::Instant>,
+}
+
+impl UnloadTimer {
+    /// Audio was handled at `now`, a pending unload starts over.
+    fn reset(&mut self, now: tokio::time::Instant) {
+        self.last_audio = Some(now);
+    }
+
+    /// When to unload, `None` when disabled or nothing is left to unload.
+    fn deadline(&self, unload_after_secs: Option<u64>) -> Option<tokio::time::Instant> {
+        Some(self.last_audio? + Duration::from_secs(unload_after_secs?))
+    }
+
+    /// The model was unloaded, nothing is pending until the next audio.
+    fn clear(&mut self) {
+        self.last_audio = None;
+    }
+}
+
+/// Hands audio to `handler` until the channel closes.
+///
+/// With `once`, stops after the first transcription (warming the model and
+/// the segments of a recording still going on don't count).
+async fn transcription_loop(
+    rx_audio: &mut UnboundedReceiver<Audio>,
+    once: bool,
+    handler: &mut impl AudioHandler,
+) {
+    loop {
+        let unload_at = handler.unload_deadline();
+        tokio::select! {
+            audio = rx_audio.recv() => {
+                let Some(audio) = audio else {
+                    break;
+                };
+                let transcription = !matches!(audio, Audio::Warm | Audio::Segment(_));
+                handler.handle(audio).await;
+                if once && transcription {
+                    break;
+                }
+            }
+            _ = sleep_until(unload_at) => handler.unload(),
+        }
+    }
+}
+
+/// A transcript pasted in the focused window.
+#[derive(Debug, Clone, PartialEq)]
+struct Pasted {
+    text: String,
+    /// Return was pressed after pasting (autosend)
+    sent: bool,
+}
+
+/// How a re-run transcript replaces the previous paste.
+#[derive(Debug, PartialEq)]
+enum Replace {
+    /// Nothing new to paste
+    Unchanged,
+    /// Erase that many characters before pasting
+    Erase(usize),
+    /// The previous paste was sent, paste after it
+    Paste,
+}
+
+fn replacement(previous: &Pasted, text: &str) -> Replace {
+    if text.is_empty() || text == previous.text {
+        Replace::Unchanged
+    } else if previous.sent {
+        Replace::Paste
+    } else {
+        Replace::Erase(previous.text.chars().count())
+    }
+}
+
+/// Transcriber shared with the blocking thread running it.
+type SharedTranscriber = Arc<Mutex<Box<dyn Transcriber>>>;
+
+fn lock(asr: &SharedTranscriber) -> Result<MutexGuard<'_, Box<dyn Transcriber>>> {
+    asr.lock()
+        .map_err(|_| anyhow!("A transcription panicked, restart to recover"))
+}
+
+/// Transcribes `samples` on a blocking thread, giving up after
+/// `model.run_timeout_secs`.
+///
+/// The model is loaded first when needed: Whisper drops it after each run, so
+/// only the first run of a recording finds it warmed by [`Audio::Warm`].
+///
+/// Returns `None` on timeout: the stuck run keeps its transcriber, `asr` is
+/// replaced by a new one (loaded again on the next recording).
+async fn run_with_timeout(
+    asr: &mut SharedTranscriber,
+    samples: Vec<f32>,
+    config: &Config,
+) -> Result<Option<String>> {
+    let recovery = CrashRecovery::new(config);
+    let run = {
+        let asr = asr.clone();
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || {
+            recovery::protect(recovery.as_ref(), samples, |samples| {
+                let mut asr = lock(&asr)?;
+                asr.load()?;
+                asr.run(samples, &config)
+            })
+        })
+    };
+    let timeout = Duration::from_secs(config.model.run_timeout_secs);
+    match tokio::time::timeout(timeout, run).await {
+        Ok(output) => output?.map(Some),
+        Err(_) => {
+            warn!("Transcription still running after {timeout:?}, abandoning it");
+            *asr = Arc::new(Mutex::new(transcriber(config).await?));
+            Ok(None)
+        }
+    }
+}
+
+/// `config` with the `model.rerun_filename` model, `None` without one.
+fn rerun_config(config: &Config) -> Option<Config> {
+    let mut rerun_config = config.clone();
+    rerun_config.model.filename = config.model.rerun_filename.clone()?;
+    Some(rerun_config)
+}
+
+/// State of the transcription task.
+struct Transcription {
+    asr: SharedTranscriber,
+    /// Larger model for `rerun_key`, `None` when not configured
+    rerun: Option<SharedTranscriber>,
+    /// The last transcriptions, to re-run them
+    history: AudioHistory<Pasted>,
+    /// The previous paste was a segment of the current recording
+    continued: bool,
+    rx_config: watch::Receiver<Config>,
+    rx_keys: UnboundedReceiver<Key>,
+    rx_language: watch::Receiver<Option<String>>,
+    duplicates: DuplicateGuard,
+    send_gap: SendGap,
+    status: StatusLine,
+    /// Also print the transcript to stdout
+    print: bool,
+    unload_timer: UnloadTimer,
+}
+
+impl AudioHandler for Transcription {
+    async fn handle(&mut self, audio: Audio) {
+        let config = self.rx_config.borrow().clone();
+        if let Ok(mut asr) = self.asr.lock() {
+            asr.set_language(self.rx_language.borrow().clone());
+        }
+        let transcribing = !matches!(audio, Audio::Warm);
+        self.status.transcribing(transcribing);
+        let result = match audio {
+            Audio::Rerun => self.rerun(&config).await,
+            audio => self.transcribe(&config, audio).await,
+        };
+        match result {
+            Ok(Some(transcript)) if self.print => println!("{transcript}"),
+            Ok(_) => (),
+            Err(err) => error!("Error handling audio {err:?}"),
+        }
+        if transcribing {
+            self.status.transcribing(false);
+        }
+        // Loading on `Warm` included, cancels the pending unload
+        self.unload_timer.reset(tokio::time::Instant::now());
+    }
+
+    fn unload_deadline(&self) -> Option<tokio::time::Instant> {
+        let unload_after_secs = self.rx_config.borrow().model.unload_after_secs;
+        self.unload_timer.deadline(unload_after_secs)
+    }
+
+    fn unload(&mut self) {
+        match lock(&self.asr) {
+            Ok(mut asr) => asr.unload(),
+            Err(err) => error!("Cannot unload the model: {err:#}"),
+        }
+        if let Some(rerun) = &self.rerun {
+            match lock(rerun) {
+                Ok(mut rerun) => rerun.unload(),
+                Err(err) => error!("Cannot unload the re-run model: {err:#}"),
+            }
+        }
+        self.unload_timer.clear();
+    }
+}
+
+impl Transcription {
+    async fn transcribe(&mut self, config: &Config, audio: Audio) -> Result<Option<String>> {
+        // Only kept when it can be re-run
+        let samples = match (&audio, &self.rerun) {
+            (Audio::Sample(samples), Some(_)) => Some(samples.clone()),
+            _ => None,
+        };
+        if let Audio::Sample(samples) | Audio::Segment(samples) = &audio {
+            save_recording(config, samples);
+        }
+        let segment = matches!(audio, Audio::Segment(_));
+        let continued = self.continued;
+        if !segment {
+            self.continued = false;
+        }
+        let pasted = handle_audio(
+            &mut self.asr,
+            config,
+            audio,
+            continued,
+            &mut self.rx_keys,
+            &mut self.duplicates,
+            &mut self.send_gap,
+            &mut Desktop,
+        )
+        .await?;
+        let Some(pasted) = pasted else {
+            return Ok(None);
+        };
+        if segment {
+            self.continued = true;
+        }
+        let text = pasted.text.clone();
+        if let Some(samples) = samples {
+            self.history.push(samples, pasted, &config.history);
+        }
+        Ok(Some(text))
+    }
+
+    /// Re-transcribes the last audio with the larger model, replacing its
+    /// paste.
+    async fn rerun(&mut self, config: &Config) -> Result<Option<String>> {
+        let (Some(asr), Some(rerun_config)) = (self.rerun.as_mut(), rerun_config(config)) else {
+            return Err(anyhow!("No model.rerun_filename configured"));
+        };
+        let Some(last) = self.history.last_mut() else {
+            info!("Nothing to re-run yet");
+            config.notify("Nothing to re-run", "");
+            return Ok(None);
+        };
+        info!("Re-running the last transcription with the larger model");
+        lock(asr)?.set_language(self.rx_language.borrow().clone());
+        let processing = ProcessingNotification::show(config);
+        let text = run_with_timeout(asr, last.samples.clone(), &rerun_config)
+            .await
+            .context("Error re-running ASR")?;
+        let Some(text) = text else {
+            let timeout = config.model.run_timeout_secs;
+            processing.finish(
+                config,
+                &mut Desktop,
+                "Re-run timed out",
+                &format!("Abandoned after {timeout}s"),
+            );
+            return Ok(None);
+        };
+        info!("Re-transcribed: {text}");
+        let text = formatting::decorate(&text, &config.formatting, &Zoned::now());
+        match replacement(&last.data, &text) {
+            Replace::Unchanged => {
+                processing.finish(config, &mut Desktop, "Same transcript", &text);
+                return Ok(None);
+            }
+            // Nothing was pasted, the clipboard is simply replaced
+            Replace::Erase(_) if config.activation.output_mode == OutputMode::ClipboardOnly => {}
+            Replace::Erase(count) => erase(&mut System::new(&config.activation), count)
+                .context("Erasing the previous paste")?,
+            Replace::Paste => info!("The previous transcript was sent, pasting after it"),
+        }
+        processing.finish(config, &mut Desktop, "Re-transcribed", &text);
+        paste(
+            &mut System::new(&config.activation),
+            text.clone(),
+            config.activation.output_mode,
+            config.activation.verify_paste,
+        )
+        .context("Pasting")?;
+        last.data = Pasted {
+            text: text.clone(),
+            sent: false,
+        };
+        Ok(Some(text))
+    }
+}
+
+/// Spaces the autosend Return presses by `autosend_min_gap_ms`.
+#[derive(Default)]
+struct SendGap {
+    last: Option<Instant>,
+}
+
+impl SendGap {
+    /// Waits until `gap` passed since the previous send, then records this
+    /// one.
+    async fn wait(&mut self, gap: Duration) {
+        if let Some(last) = self.last {
+            let left = gap.saturating_sub(last.elapsed());
+            if !left.is_zero() {
+                debug!("Waiting {left:?} before sending again");
+                tokio::time::sleep(left).await;
+            }
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Presses Return after a paste when `autosend` is set (and confirmed with
+/// `confirm_key`), returns whether it did. Never sends a transcript only
+/// copied to the clipboard.
+async fn autosend(
+    keyboard: &mut impl Keyboard,
+    config: &Config,
+    keys: &mut UnboundedReceiver<Key>,
+    send_gap: &mut SendGap,
+) -> Result<bool> {
+    if config.activation.output_mode == OutputMode::ClipboardOnly {
+        return Ok(false);
+    }
+    let confirmed = match config.activation.confirm_key {
+        Some(confirm_key) if config.activation.autosend => {
+            info!("Press {confirm_key:?} to send");
+            let timeout = Duration::from_millis(config.activation.confirm_timeout_ms);
+            let confirmed = wait_for_confirm(keys, confirm_key, timeout).await;
+            if !confirmed {
+                info!("Send not confirmed, leaving the text for editing");
+            }
+            confirmed
         }
-        Audio::Sample(samples) => Some(samples),
-        Audio::Path(wav_path) => {
-            info!("Transcribing audio...");
-            let samples = asr.samples_from_file(&wav_path).context("Read wav")?;
-            Some(samples)
+        _ => true,
+    };
+    let sent = config.activation.autosend && confirmed;
+    if sent {
+        let gap = Duration::from_millis(config.activation.autosend_min_gap_ms);
+        send_gap.wait(gap).await;
+        std::thread::sleep(Duration::from_millis(2));
+        tap(keyboard, Key::Return)?;
+    }
+    Ok(sent)
+}
+
+/// How to handle a transcript of `confidence`, `None` to paste it.
+fn low_confidence(confidence: Option<f32>, config: &ModelConfig) -> Option<LowConfidence> {
+    let min = config.min_confidence?;
+    confidence
+        .filter(|&confidence| confidence < min)
+        .map(|_| config.low_confidence)
+}
+
+/// Transcribes and pastes the audio. `continued` separates the paste from
+/// the previous segment of the same recording.
+#[allow(clippy::too_many_arguments)]
+async fn handle_audio(
+    asr: &mut SharedTranscriber,
+    config: &Config,
+    audio: Audio,
+    continued: bool,
+    keys: &mut UnboundedReceiver<Key>,
+    duplicates: &mut DuplicateGuard,
+    send_gap: &mut SendGap,
+    notifier: &mut impl Notifier,
+) -> Result<Option<Pasted>> {
+    let (samples, segment): (Option<Vec<f32>>, bool) = match audio {
+        Audio::Warm => {
+            // Keeps the task alive, the next recording tries again
+            if let Err(err) = lock(asr)?.load() {
+                error!("Model loading failed: {err:#}");
+                notifier.notify(config, "Model loading failed", &format!("{err:#}"));
+            }
+            (None, false)
         }
+        Audio::Sample(samples) => (Some(samples), false),
+        Audio::Segment(samples) => (Some(samples), true),
+        Audio::Rerun => (None, false),
     };
     if let Some(samples) = samples {
+        if samples.is_empty() {
+            // Streaming sent everything as segments, only send them
+            if continued {
+                autosend(&mut System::new(&config.activation), config, keys, send_gap).await?;
+            }
+            return Ok(None);
+        }
         info!("Transcribing audio...");
-        let output = asr.run(samples, &config).context("Error running ASR")?;
+        let processing = ProcessingNotification::show(config);
+        let output = run_with_timeout(asr, samples, config)
+            .await
+            .context("Error running ASR")?;
+        let Some(output) = output else {
+            let timeout = config.model.run_timeout_secs;
+            processing.finish(
+                config,
+                notifier,
+                "Transcription timed out",
+                &format!("Abandoned after {timeout}s"),
+            );
+            return Ok(None);
+        };
         if output.is_empty() {
             // Show notification with transcribed text
-            config.notify("No voice detected", &output);
-            return Ok(());
+            processing.finish(config, notifier, "No voice detected", &output);
+            return Ok(None);
+        }
+        let confidence = lock(asr)?.last_confidence();
+        if let Some(action) = low_confidence(confidence, &config.model) {
+            info!("Not pasting the low confidence transcript: {output}");
+            match action {
+                LowConfidence::Prompt => {
+                    processing.finish(config, notifier, "Didn't catch that", "Try again")
+                }
+                // Closes the processing notification
+                LowConfidence::Drop => drop(processing),
+            }
+            return Ok(None);
         }
 
         // let output = "Toto".to_string();
         info!("Transcribed: {output}");
+        let window = Duration::from_millis(config.activation.duplicate_window_ms);
+        let limit = config.activation.duplicate_limit;
+        if duplicates.is_duplicate(&output, Instant::now(), limit, window) {
+            info!("Suppressed a likely duplicate transcript");
+            processing.finish(config, notifier, "Duplicate suppressed", &output);
+            return Ok(None);
+        }
+        if let Err(err) = output::write_srt(lock(asr)?.last_segments(), config) {
+            warn!("Could not write the subtitles: {err:#}");
+        }
+        let output = formatting::decorate(&output, &config.formatting, &Zoned::now());
         let summary = if output.len() > 20 {
             &format!("{}..", &output[..20])
         } else {
             &output
         };
         // Show notification with transcribed text
-        config.notify(summary, &output);
+        processing.finish(config, notifier, summary, &output);
 
-        paste(output).context("Pasting").expect("Pasting");
-        // Always end by pressing Return to submit
-        if config.activation.autosend {
-            std::thread::sleep(Duration::from_millis(2));
-            simulate(&EventType::KeyPress(Key::Return)).expect("simulate");
-            std::thread::sleep(Duration::from_millis(2));
-            simulate(&EventType::KeyRelease(Key::Return)).expect("simulate");
-            std::thread::sleep(Duration::from_millis(2));
+        let delay = Duration::from_millis(config.activation.paste_delay_ms);
+        let text = if continued {
+            format!(" {output}")
+        } else {
+            output.clone()
+        };
+        let pasted = paste_after(delay, || {
+            paste(
+                &mut System::new(&config.activation),
+                text,
+                config.activation.output_mode,
+                config.activation.verify_paste,
+            )
+        })
+        .await;
+        // Keeps the task alive, the next recording pastes again
+        if let Err(err) = pasted {
+            error!("Pasting failed: {err:#}");
+            notifier.notify(config, "Pasting failed", &format!("{err:#}"));
+            return Ok(None);
         }
+        // The recording goes on, only send at the end
+        let sent = if segment {
+            false
+        } else {
+            match autosend(&mut System::new(&config.activation), config, keys, send_gap).await {
+                Ok(sent) => sent,
+                Err(err) => {
+                    error!("Autosend failed: {err:#}");
+                    notifier.notify(config, "Autosend failed", &format!("{err:#}"));
+                    false
+                }
+            }
+        };
+        return Ok(Some(Pasted { text: output, sent }));
     }
-    Ok(())
+    Ok(None)
 }
 
 impl App {
@@ -91,24 +944,36 @@ impl App {
     ///
     /// This function initializes the application by:
     /// 1. Loading configuration from config.toml or using defaults
+    ///    (written to disk only when `write_config` is set)
     /// 2. Setting up the audio recorder
     /// 3. Loading the Whisper model
-    pub async fn new(config_path: Option<PathBuf>) -> Result<Self> {
+    ///
+    /// `tty_status` enables the terminal status line, `once` makes [`App::run`]
+    /// return after the first transcription.
+    pub async fn new(
+        config_paths: Vec<PathBuf>,
+        write_config: bool,
+        tty_status: bool,
+        once: bool,
+    ) -> Result<Self> {
         // Load configuration
-        let config = if let Some(path) = config_path {
-            Config::from_file(&path).context(format!("Reading config {}", path.display()))?
-        } else {
+        let config = if !config_paths.is_empty() {
+            Config::from_files(&config_paths)?
+        } else if write_config {
             Config::load_or_write_default(None)?
+        } else {
+            Config::load_or_default(None)?
         };
 
         // Warm the handle.
-        simulate(&EventType::KeyPress(Key::ControlLeft))?;
-        std::thread::sleep(Duration::from_millis(2));
-        simulate(&EventType::KeyRelease(Key::ControlLeft))?;
+        warm_up(
+            &mut System::new(&config.activation),
+            input_warmup(&config.activation),
+        )?;
 
         // Initialize audio recorder
         let (tx_audio, mut rx_audio) = unbounded_channel();
-        let recorder = AudioRecorder::new(&config, tx_audio)
+        let recorder = AudioRecorder::new(&config, tx_audio.clone())
             .await
             .context("Failed to create audio recorder")?;
 
@@ -116,31 +981,138 @@ impl App {
         std::fs::create_dir_all(&config.paths.cache_dir)?;
 
         // Download model if it doesn't exist
-        let model_path = download_model(&config)
-            .await
-            .context("Failed to download model")?;
+        let mut asr = transcriber(&config).await?;
 
-        let asr = Asr::new(&model_path)?;
-        let asr_config = config.clone();
-        tokio::task::spawn(async move {
-            let mut asr = asr;
-            let asr_config = asr_config;
-            while let Some(audio) = rx_audio.recv().await {
-                if let Err(err) = handle_audio(&mut asr, &asr_config, audio).await {
-                    error!("Error handling audio {err:?}");
-                }
+        let languages = Languages::new(config.model.transcription_languages());
+        let language = languages.current().map(str::to_string);
+        asr.set_language(language.clone());
+        let rerun = match rerun_config(&config) {
+            Some(rerun_config) => {
+                let rerun = transcriber(&rerun_config)
+                    .await
+                    .context("Failed to load the re-run model")?;
+                Some(Arc::new(Mutex::new(rerun)))
             }
+            None => None,
+        };
+        asr.warm_on_start(&config.model)?;
+        if let Some(recovery) = CrashRecovery::new(&config) {
+            recover(asr.as_mut(), &config, &recovery);
+        }
+        let asr = Arc::new(Mutex::new(asr));
+        let (tx_config, rx_config) = watch::channel(config.clone());
+        let status = StatusLine::new(tty_status);
+        let (tx_keys, rx_keys) = unbounded_channel();
+        let (tx_done, rx_done) = oneshot::channel();
+        let (tx_language, rx_language) = watch::channel(language);
+        let mut transcription = Transcription {
+            asr,
+            rerun,
+            history: AudioHistory::default(),
+            continued: false,
+            rx_config,
+            rx_keys,
+            rx_language,
+            duplicates: DuplicateGuard::default(),
+            send_gap: SendGap::default(),
+            status: status.clone(),
+            print: once,
+            unload_timer: UnloadTimer::default(),
+        };
+        tokio::task::spawn(async move {
+            transcription_loop(&mut rx_audio, once, &mut transcription).await;
+            let _ = tx_done.send(());
         });
         Ok(Self {
             state: State {
                 pressed_keys: HashSet::new(),
                 recording: false,
+                blocked: false,
+                stop_at: None,
+                cancel_pressed: HashSet::new(),
+                cancelled: false,
             },
             recorder,
             config,
+            config_paths,
+            tx_audio,
+            tx_config,
+            tx_keys,
+            rx_done,
+            status,
+            languages,
+            tx_language,
+            last_activity: tokio::time::Instant::now(),
+            hooks: Hooks::default(),
         })
     }
 
+    /// Reloads the configuration file and applies it without restarting,
+    /// loading it up to `attempts` times.
+    ///
+    /// Most settings (prompt, replacements, keys, autosend, ...) apply live.
+    /// Audio, path or trigger changes (including VAD thresholds) recreate the
+    /// audio recorder, the loaded model is kept. Model changes require a restart.
+    async fn reload_config(&mut self, attempts: usize) -> Result<()> {
+        let mut config = load_config(&self.config_paths, attempts).await?;
+
+        if config.model.repo != self.config.model.repo
+            || config.model.filename != self.config.model.filename
+            || config.model.rerun_filename != self.config.model.rerun_filename
+            || config.model.backend != self.config.model.backend
+        {
+            warn!(
+                "Changing the model requires a restart, keeping {}/{}",
+                self.config.model.repo, self.config.model.filename
+            );
+            config.model.repo = self.config.model.repo.clone();
+            config.model.filename = self.config.model.filename.clone();
+            config.model.rerun_filename = self.config.model.rerun_filename.clone();
+            config.model.backend = self.config.model.backend.clone();
+        }
+
+        let (rebuilt, live): (Vec<String>, Vec<String>) = self
+            .config
+            .changes(&config)?
+            .into_iter()
+            .partition(|name| recreates_recorder(name));
+        if !live.is_empty() {
+            info!("Applying live: {}", live.join(", "));
+        }
+        let recreated = !rebuilt.is_empty();
+        if recreated {
+            info!("Recreating the audio recorder for: {}", rebuilt.join(", "));
+            let recorder = AudioRecorder::new(&config, self.tx_audio.clone())
+                .await
+                .context("Failed to create audio recorder")?;
+            if self.state.recording {
+                self.recorder.stop_recording()?;
+                self.state.recording = false;
+                self.status.set(Status::Idle);
+            }
+            self.state.blocked = false;
+            self.recorder = recorder;
+        }
+
+        let languages = config.model.transcription_languages();
+        if languages != self.config.model.transcription_languages() {
+            self.languages = Languages::new(languages);
+            self.tx_language
+                .send_replace(self.languages.current().map(str::to_string));
+        }
+
+        self.state.pressed_keys.clear();
+        self.tx_config.send_replace(config.clone());
+        let restart = recreated && !self.state.recording && config.activation.trigger.auto_starts();
+        self.config = config;
+        info!("Config reloaded");
+        self.notify("Config reloaded", "");
+        if restart {
+            self.toggle_listening()?;
+        }
+        Ok(())
+    }
+
     /// Runs the main application loop.
     ///
     /// This function sets up the keyboard event listener and processes
@@ -160,24 +1132,146 @@ impl App {
             Ok(())
         });
 
-        let keys = &self.config.activation.keys;
+        let keys = self
+            .config
+            .activation
+            .key_sets()
+            .map(|keys| format!("{keys:?}"))
+            .collect::<Vec<_>>()
+            .join(" or ");
         let message = match &self.config.activation.trigger {
-            Trigger::PushToTalk => format!("Press {:?} to start recording, release to stop", keys),
-            Trigger::Toggle => format!("Press {:?} to start recording, press again to stop", keys),
-            Trigger::ToggleVad { .. } => format!("Press {:?} to toggle VAD recording", keys),
+            Trigger::PushToTalk { .. } => {
+                format!("Press {} to start recording, release to stop", keys)
+            }
+            Trigger::Toggle => format!("Press {} to start recording, press again to stop", keys),
+            Trigger::ToggleVad { .. } => format!("Press {} to toggle VAD recording", keys),
         };
         info!("{}", message);
+        self.status.set(Status::Idle);
+        if self.config.activation.trigger.auto_starts() {
+            self.toggle_listening()?;
+        }
 
-        while let Some(event) = rchan.recv().await {
-            if let Err(err) = self.handle_event(event) {
-                error!("error handling event: {err}");
+        let mut reload = ReloadSignal::new()?;
+        let mut config_watch = ConfigWatch::new(Config::sources(&self.config_paths));
+        let mut config_check = tokio::time::interval(CONFIG_CHECK_INTERVAL);
+        config_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut focus_check = tokio::time::interval(FOCUS_CHECK_INTERVAL);
+        focus_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            let watch_focus = !self.config.activation.blocked_apps.is_empty()
+                && matches!(self.config.activation.trigger, Trigger::ToggleVad { .. })
+                && (self.state.recording || self.state.blocked);
+            let recording = self.state.recording;
+            let idle = idle_deadline(
+                self.last_activity,
+                self.config.activation.idle_exit_timeout,
+                recording,
+            );
+            tokio::select! {
+                event = rchan.recv() => {
+                    let Some(event) = event else { break };
+                    // Forward the confirm key to a transcription awaiting it
+                    if let Some(key) = self
+                        .config
+                        .activation
+                        .confirm_key
+                        .filter(|&key| event.event_type == EventType::KeyPress(key))
+                    {
+                        let _ = self.tx_keys.send(key);
+                    }
+                    if self
+                        .config
+                        .activation
+                        .language_key
+                        .is_some_and(|key| event.event_type == EventType::KeyPress(key))
+                    {
+                        self.cycle_language();
+                    }
+                    if self
+                        .config
+                        .activation
+                        .rerun_key
+                        .is_some_and(|key| event.event_type == EventType::KeyPress(key))
+                    {
+                        // The transcription task ended, the keys still work
+                        self.tx_audio
+                            .send(Audio::Rerun)
+                            .unwrap_or_else(|err| error!("Could not send the re-run event: {err}"));
+                    }
+                    if let Err(err) = self.handle_event(event) {
+                        error!("error handling event: {err}");
+                    }
+                }
+                stopped = self.recorder.stopped() => match stopped {
+                    Stopped::Disarmed => {
+                        if self.state.recording {
+                            info!("Utterance done, stopped listening");
+                            self.state.recording = false;
+                            self.recorder.stop_recording()?;
+                            self.notify("Stop listening.", "");
+                            self.status.set(Status::Idle);
+                        }
+                    }
+                    Stopped::Capped => self.capped()?,
+                },
+                _ = &mut self.rx_done => {
+                    info!("Transcription done, exiting");
+                    if self.state.recording {
+                        self.recorder.stop_recording()?;
+                        self.state.recording = false;
+                    }
+                    break;
+                }
+                _ = sleep_until(idle) => {
+                    info!("No dictation for a while, exiting");
+                    self.notify("Whispering exiting", "No dictation for a while");
+                    break;
+                }
+                _ = sleep_until(self.state.stop_at), if self.state.stop_at.is_some() => {
+                    self.stop_push_to_talk()?;
+                }
+                _ = focus_check.tick(), if watch_focus => {
+                    self.pause_when_blocked()?;
+                }
+                _ = reload.recv() => {
+                    info!("Reloading config");
+                    if let Err(err) = self.reload_config(1).await {
+                        error!("Could not reload config: {err:#}");
+                        self.notify("Config reload failed", &format!("{err:#}"));
+                    }
+                }
+                _ = config_check.tick() => {
+                    if config_watch.changed() {
+                        info!("Config file changed, reloading");
+                        if let Err(err) = self.reload_config(RELOAD_ATTEMPTS).await {
+                            error!("Could not reload config: {err:#}");
+                            self.notify("Config reload failed", &format!("{err:#}"));
+                        }
+                    }
+                }
             }
+            if self.state.recording != recording {
+                self.last_activity = tokio::time::Instant::now();
+            }
+            self.hooks.update(self.state.recording, &self.config.hooks);
         }
+        // Recording stopped when exiting
+        self.hooks.update(false, &self.config.hooks);
 
         info!("Done exiting");
         Ok(())
     }
 
+    /// Switches to the next configured language for the next transcriptions.
+    fn cycle_language(&mut self) {
+        let language = self.languages.next().map(str::to_string);
+        let name = language.as_deref().unwrap_or("auto-detect");
+        info!("Transcription language: {name}");
+        self.notify("Language", name);
+        self.tx_language.send_replace(language);
+    }
+
     fn notify(&self, summary: &str, content: &str) {
         // Show desktop notification
         if self.config.activation.notify {
@@ -197,34 +1291,63 @@ impl App {
     /// This function processes keyboard events and updates the application state
     /// accordingly. It manages the recording state and triggers transcription
     /// when recording stops.
+    ///
+    /// Keys `rdev` doesn't name are matched on their raw code
+    /// (`Key::Unknown(code)`), configured as `{ code = ... }`.
     fn handle_event(&mut self, event: rdev::Event) -> Result<()> {
+        if let EventType::KeyPress(Key::Unknown(code)) = event.event_type {
+            debug!("Unnamed key pressed, bind it with {{ code = {code} }}");
+        }
+        if self.handle_cancel(&event)? {
+            return Ok(());
+        }
         match &self.config.activation.trigger {
-            Trigger::PushToTalk => self.handle_event_push_to_talk(event),
+            Trigger::PushToTalk { .. } => self.handle_event_push_to_talk(event),
             Trigger::Toggle => self.handle_event_push_to_toggle(event),
             Trigger::ToggleVad { .. } => self.handle_event_vad(event),
         }
     }
+
+    /// Tracks the `cancel_keys`, cancelling the recording once they are all
+    /// pressed. Returns whether it did.
+    fn handle_cancel(&mut self, event: &rdev::Event) -> Result<bool> {
+        let activation = &self.config.activation;
+        match event.event_type {
+            EventType::KeyPress(key) if activation.cancel_keys.contains(&key) => {
+                self.state.cancel_pressed.insert(key);
+            }
+            EventType::KeyRelease(key) => {
+                self.state.cancel_pressed.remove(&key);
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        }
+        if !self.state.recording || !activation.cancelled(&self.state.cancel_pressed) {
+            return Ok(false);
+        }
+        info!("Cancelling recording...");
+        self.state.recording = false;
+        self.state.stop_at = None;
+        self.state.cancelled = true;
+        self.recorder.cancel_recording()?;
+        self.notify("Recording cancelled", "The audio was discarded");
+        self.status.set(Status::Idle);
+        Ok(true)
+    }
+
     fn handle_event_vad(&mut self, event: rdev::Event) -> Result<()> {
         match event.event_type {
             EventType::KeyPress(key) => {
-                let keys = &self.config.activation.keys;
-                if keys.contains(&key) {
+                let activation = &self.config.activation;
+                if activation.is_activation_key(&key) {
                     self.state.pressed_keys.insert(key);
                 }
-                // Check if all required keys are pressed
-                let all_keys_pressed = keys == &self.state.pressed_keys;
+                // Check if all the keys of a set are pressed, no keys (with
+                // `auto_start`) can't be toggled
+                let all_keys_pressed = activation.activated(&self.state.pressed_keys);
 
                 if all_keys_pressed {
-                    self.state.recording = !self.state.recording;
-                    if self.state.recording {
-                        info!("Starting recording...");
-                        self.notify("Start listening..", "");
-                        self.recorder.start_recording()?;
-                    } else {
-                        info!("Stopped recording");
-                        self.notify("Stop listening.", "");
-                        self.recorder.stop_recording()?;
-                    }
+                    self.toggle_listening()?;
                 }
             }
             EventType::KeyRelease(key) => {
@@ -234,18 +1357,90 @@ impl App {
         }
         Ok(())
     }
+    /// Whether the focused window is one of `blocked_apps`.
+    fn blocked(&self) -> bool {
+        let blocked_apps = &self.config.activation.blocked_apps;
+        if blocked_apps.is_empty() {
+            return false;
+        }
+        let blocked = window::is_blocked(window::focused_window(), blocked_apps);
+        if blocked {
+            info!("Dictation is blocked in the focused window");
+        }
+        blocked
+    }
+
+    /// Pauses VAD listening while a blocked application is focused, resumes
+    /// it once it isn't anymore.
+    fn pause_when_blocked(&mut self) -> Result<()> {
+        let blocked = self.blocked();
+        if self.state.recording && blocked {
+            info!("Pausing listening");
+            self.state.recording = false;
+            self.state.blocked = true;
+            self.recorder.stop_recording()?;
+            self.status.set(Status::Idle);
+        } else if self.state.blocked && !blocked {
+            info!("Resuming listening");
+            self.state.recording = true;
+            self.state.blocked = false;
+            self.recorder.start_recording()?;
+            self.status.set(Status::Listening);
+        }
+        Ok(())
+    }
+
+    /// Starts or stops VAD listening.
+    fn toggle_listening(&mut self) -> Result<()> {
+        if self.state.blocked {
+            // Paused by a blocked application, stop for good
+            info!("Stopped recording");
+            self.state.blocked = false;
+            self.notify("Stop listening.", "");
+            return Ok(());
+        }
+        if !self.state.recording && self.blocked() {
+            // Starts listening once the blocked application loses the focus
+            self.state.blocked = true;
+            self.notify(
+                "Listening paused",
+                "Dictation is blocked in the focused window",
+            );
+            return Ok(());
+        }
+        self.state.recording = !self.state.recording;
+        if self.state.recording {
+            info!("Starting recording...");
+            self.notify("Start listening..", "");
+            self.recorder.start_recording()?;
+            self.status.set(Status::Listening);
+        } else {
+            info!("Stopped recording");
+            self.notify("Stop listening.", "");
+            self.recorder.stop_recording()?;
+            self.status.set(Status::Idle);
+        }
+        Ok(())
+    }
+
     fn handle_event_push_to_toggle(&mut self, event: rdev::Event) -> Result<()> {
         match event.event_type {
             EventType::KeyPress(key) => {
-                let keys = &self.config.activation.keys;
-                if keys.contains(&key) {
+                let activation = &self.config.activation;
+                if activation.is_activation_key(&key) {
                     self.state.pressed_keys.insert(key);
                 }
 
-                // Check if all required keys are pressed
-                let all_keys_pressed = keys == &self.state.pressed_keys;
+                // Check if all the keys of a set are pressed
+                let all_keys_pressed = activation.activated(&self.state.pressed_keys);
 
                 if all_keys_pressed {
+                    if !self.state.recording && self.blocked() {
+                        // Key repeats must not block (and notify) again
+                        self.state.pressed_keys.clear();
+                        self.notify("Dictation blocked", "In the focused window");
+                        return Ok(());
+                    }
                     // Toggle recording state
                     self.state.recording = !self.state.recording;
 
@@ -253,10 +1448,12 @@ impl App {
                         info!("Starting recording...");
                         self.notify("Recording started", "Press again to stop");
                         self.recorder.start_recording()?;
+                        self.status.set(Status::Recording);
                     } else {
                         info!("Stopping recording...");
                         self.notify("Recording stopped", "Processing audio...");
                         self.recorder.stop_recording()?;
+                        self.status.set(Status::Idle);
                     }
                 }
             }
@@ -271,31 +1468,855 @@ impl App {
     fn handle_event_push_to_talk(&mut self, event: rdev::Event) -> Result<()> {
         match event.event_type {
             EventType::KeyPress(key) => {
-                let keys = &self.config.activation.keys;
-                if keys.contains(&key) {
+                let activation = &self.config.activation;
+                if activation.is_activation_key(&key) {
                     self.state.pressed_keys.insert(key);
                 }
-                // Check if all required keys are pressed
-                let all_keys_pressed = keys == &self.state.pressed_keys;
+                // Check if all the keys of a set are pressed
+                let all_keys_pressed = activation.activated(&self.state.pressed_keys);
 
-                if all_keys_pressed && !self.state.recording {
+                if all_keys_pressed && self.state.pressed() {
+                    debug!("Keys pressed again within the release grace, recording on");
+                }
+                if all_keys_pressed && !self.state.recording && !self.state.cancelled {
+                    if self.blocked() {
+                        // Key repeats must not block (and notify) again
+                        self.state.pressed_keys.clear();
+                        self.notify("Dictation blocked", "In the focused window");
+                        return Ok(());
+                    }
                     self.state.recording = true;
                     info!("Starting recording...");
                     self.recorder.start_recording()?;
+                    self.status.set(Status::Recording);
                 }
             }
             EventType::KeyRelease(key) => {
                 self.state.pressed_keys.retain(|&k| k != key);
 
-                let keys = &self.config.activation.keys;
-                if self.state.recording && self.state.pressed_keys != *keys {
-                    self.state.recording = false;
-                    info!("Stopping recording...");
-                    self.recorder.stop_recording()?;
+                let activation = &self.config.activation;
+                if !activation.activated(&self.state.pressed_keys) {
+                    self.state.cancelled = false;
+                }
+                let released = self.state.recording
+                    && self.state.stop_at.is_none()
+                    && !activation.activated(&self.state.pressed_keys);
+                if released {
+                    let grace = match activation.trigger {
+                        Trigger::PushToTalk {
+                            release_grace_ms, ..
+                        } => release_grace_ms,
+                        _ => 0,
+                    };
+                    if self.state.released(grace) {
+                        self.stop_push_to_talk()?;
+                    }
                 }
             }
             _ => (),
         }
         Ok(())
     }
+
+    /// Back to idle once the stream ended the recording at
+    /// `max_recording_secs`, its audio being transcribed already.
+    fn capped(&mut self) -> Result<()> {
+        let activation = &self.config.activation;
+        let held = matches!(activation.trigger, Trigger::PushToTalk { .. })
+            && activation.activated(&self.state.pressed_keys);
+        self.state.capped(held);
+        // Nothing left to send, closes the microphone
+        self.recorder.stop_recording()?;
+        self.status.set(Status::Idle);
+        let secs = activation.max_recording_secs.unwrap_or_default();
+        self.notify(
+            "Recording too long",
+            &format!("Stopped after {secs}s, transcribing"),
+        );
+        Ok(())
+    }
+
+    /// Stops the push-to-talk recording, transcribing it.
+    fn stop_push_to_talk(&mut self) -> Result<()> {
+        self.state.stop_at = None;
+        self.state.recording = false;
+        info!("Stopping recording...");
+        self.recorder.stop_recording()?;
+        self.status.set(Status::Idle);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHandler {
+        handled: usize,
+    }
+
+    impl AudioHandler for CountingHandler {
+        async fn handle(&mut self, _audio: Audio) {
+            self.handled += 1;
+        }
+    }
+
+    #[test]
+    fn test_unload_timer() {
+        // The instants stand in for the clock
+        let start = tokio::time::Instant::now();
+        let mut timer = UnloadTimer::default();
+        // Nothing loaded yet
+        assert_eq!(timer.deadline(Some(60)), None);
+        timer.reset(start);
+        assert_eq!(timer.deadline(None), None);
+        assert_eq!(
+            timer.deadline(Some(60)),
+            Some(start + Duration::from_secs(60))
+        );
+        // A `Warm` before the deadline cancels it, the countdown starts over
+        timer.reset(start + Duration::from_secs(30));
+        assert_eq!(
+            timer.deadline(Some(60)),
+            Some(start + Duration::from_secs(90))
+        );
+        timer.clear();
+        assert_eq!(timer.deadline(Some(60)), None);
+    }
+
+    /// Counts the unloads, due once.
+    struct Unloading {
+        timer: UnloadTimer,
+        unloads: usize,
+    }
+
+    impl AudioHandler for Unloading {
+        async fn handle(&mut self, _audio: Audio) {}
+
+        fn unload_deadline(&self) -> Option<tokio::time::Instant> {
+            self.timer.deadline(Some(0))
+        }
+
+        fn unload(&mut self) {
+            self.unloads += 1;
+            self.timer.clear();
+        }
+    }
+
+    #[test]
+    fn test_config_watch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        let mut watch = ConfigWatch::new(vec![path.clone()]);
+        assert!(!watch.changed());
+
+        // Created
+        std::fs::write(&path, "")?;
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        // Written again
+        let file = std::fs::File::options().write(true).open(&path)?;
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        // Removed, nothing to reload
+        std::fs::remove_file(&path)?;
+        assert!(!watch.changed());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_config_retries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        let contents = toml::to_string(&Config::default())?;
+        // Caught half written
+        std::fs::write(&path, &contents[..contents.len() / 2])?;
+        let paths = vec![path.clone()];
+        assert!(load_config(&paths, 1).await.is_err());
+
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(RELOAD_RETRY_DELAY / 2).await;
+            std::fs::write(&path, contents)
+        });
+        assert_eq!(
+            load_config(&paths, RELOAD_ATTEMPTS).await?,
+            Config::default()
+        );
+        writer.await??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recreates_recorder() {
+        assert!(recreates_recorder("audio.device"));
+        assert!(recreates_recorder("paths.cache_dir"));
+        assert!(recreates_recorder("activation.trigger"));
+        assert!(recreates_recorder("activation.max_recording_secs"));
+        assert!(!recreates_recorder("activation.autosend"));
+        assert!(!recreates_recorder("model.replacements"));
+    }
+
+    #[tokio::test]
+    async fn test_transcription_loop_unloads() {
+        let (tx, mut rx) = unbounded_channel();
+        let mut handler = Unloading {
+            timer: UnloadTimer::default(),
+            unloads: 0,
+        };
+        handler.timer.reset(tokio::time::Instant::now());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let _ = tx.send(Audio::Sample(vec![0.0]));
+        });
+        transcription_loop(&mut rx, true, &mut handler).await;
+        // Unloaded while waiting, only once
+        assert_eq!(handler.unloads, 1);
+    }
+
+    #[tokio::test]
+    async fn test_transcription_loop_once() {
+        let (tx, mut rx) = unbounded_channel();
+        for audio in [
+            Audio::Warm,
+            Audio::Segment(vec![0.0]),
+            Audio::Sample(vec![0.0]),
+            Audio::Sample(vec![0.0]),
+        ] {
+            let _ = tx.send(audio);
+        }
+        let mut handler = CountingHandler::default();
+        // Returns without the channel being closed, once the recording ended
+        transcription_loop(&mut rx, true, &mut handler).await;
+        assert_eq!(handler.handled, 3);
+
+        let _ = tx.send(Audio::Sample(vec![0.0]));
+        drop(tx);
+        let mut handler = CountingHandler::default();
+        transcription_loop(&mut rx, false, &mut handler).await;
+        assert_eq!(handler.handled, 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_confirm() {
+        let (tx, mut rx) = unbounded_channel();
+        let timeout = Duration::from_millis(200);
+
+        // Presses from before the paste don't count
+        let _ = tx.send(Key::ShiftRight);
+        assert!(!wait_for_confirm(&mut rx, Key::ShiftRight, timeout).await);
+
+        let sender = tx.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let _ = sender.send(Key::ShiftRight);
+        });
+        assert!(wait_for_confirm(&mut rx, Key::ShiftRight, timeout).await);
+
+        // Other keys are ignored
+        let sender = tx.clone();
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let _ = sender.send(Key::Escape);
+        });
+        assert!(!wait_for_confirm(&mut rx, Key::ShiftRight, timeout).await);
+    }
+
+    /// Takes `delay` to transcribe anything.
+    struct Sleepy {
+        delay: Duration,
+    }
+
+    impl Transcriber for Sleepy {
+        fn set_language(&mut self, _language: Option<String>) {}
+
+        fn load(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn run(&mut self, _samples: Vec<f32>, _config: &Config) -> Result<String> {
+            std::thread::sleep(self.delay);
+            Ok("slept".into())
+        }
+    }
+
+    /// Like Whisper, runs only once loaded and drops the model after each run.
+    #[derive(Default)]
+    struct Unwarmed {
+        loaded: bool,
+        runs: Arc<AtomicUsize>,
+        unloads: Arc<AtomicUsize>,
+    }
+
+    impl Transcriber for Unwarmed {
+        fn set_language(&mut self, _language: Option<String>) {}
+
+        fn load(&mut self) -> Result<()> {
+            self.loaded = true;
+            Ok(())
+        }
+
+        fn run(&mut self, _samples: Vec<f32>, _config: &Config) -> Result<String> {
+            if !std::mem::take(&mut self.loaded) {
+                bail!("Context was not warm");
+            }
+            self.runs.fetch_add(1, Ordering::Relaxed);
+            // No voice, nothing to paste
+            Ok(String::new())
+        }
+
+        fn unload(&mut self) {
+            if std::mem::take(&mut self.loaded) {
+                self.unloads.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn transcription(asr: Box<dyn Transcriber>, config: Config) -> Transcription {
+        Transcription {
+            asr: Arc::new(Mutex::new(asr)),
+            rerun: None,
+            history: AudioHistory::default(),
+            continued: false,
+            rx_config: watch::channel(config).1,
+            rx_keys: unbounded_channel().1,
+            rx_language: watch::channel(None).1,
+            duplicates: DuplicateGuard::default(),
+            send_gap: SendGap::default(),
+            status: StatusLine::new(false),
+            print: false,
+            unload_timer: UnloadTimer::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recording_longer_than_unload() {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        config.model.unload_after_secs = Some(1);
+        let unwarmed = Unwarmed::default();
+        let runs = unwarmed.runs.clone();
+        let unloads = unwarmed.unloads.clone();
+        let mut handler = transcription(Box::new(unwarmed), config);
+        let (tx, mut rx) = unbounded_channel();
+        let _ = tx.send(Audio::Warm);
+        // The keys are held past the unload deadline
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            let _ = tx.send(Audio::Sample(vec![0.0; 160]));
+        });
+        transcription_loop(&mut rx, true, &mut handler).await;
+        assert_eq!(unloads.load(Ordering::Relaxed), 1);
+        // Loaded again instead of failing on the cold model
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_loads_model() -> Result<()> {
+        let config = Config::default();
+        let unwarmed = Unwarmed::default();
+        let runs = unwarmed.runs.clone();
+        let mut asr: SharedTranscriber = Arc::new(Mutex::new(Box::new(unwarmed)));
+        // Warmed once when the streaming recording starts
+        lock(&asr)?.load()?;
+        for _ in 0..3 {
+            let output = run_with_timeout(&mut asr, vec![0.0; 160], &config).await?;
+            assert_eq!(output.as_deref(), Some(""));
+        }
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunks_transcribed() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        let unwarmed = Unwarmed::default();
+        let runs = unwarmed.runs.clone();
+        let mut asr: SharedTranscriber = Arc::new(Mutex::new(Box::new(unwarmed)));
+        let (_tx, mut keys) = unbounded_channel();
+        let mut duplicates = DuplicateGuard::default();
+        let mut send_gap = SendGap::default();
+        // A long VAD utterance: warmed once, cut in chunks, then its end
+        let audios = [
+            Audio::Warm,
+            Audio::Segment(vec![0.0; 160]),
+            Audio::Segment(vec![0.0; 160]),
+            Audio::Segment(vec![0.0; 160]),
+            Audio::Sample(vec![0.0; 160]),
+        ];
+        for (i, audio) in audios.into_iter().enumerate() {
+            handle_audio(
+                &mut asr,
+                &config,
+                audio,
+                i > 1,
+                &mut keys,
+                &mut duplicates,
+                &mut send_gap,
+                &mut Notifications::default(),
+            )
+            .await?;
+        }
+        assert_eq!(runs.load(Ordering::Relaxed), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout() -> Result<()> {
+        let mut config = Config::default();
+        config.model.run_timeout_secs = 1;
+        config.model.backend = crate::config::Backend::Echo {
+            text: "fresh".into(),
+        };
+        let sleepy =
+            |delay| -> SharedTranscriber { Arc::new(Mutex::new(Box::new(Sleepy { delay }))) };
+
+        let mut asr = sleepy(Duration::from_millis(10));
+        let output = run_with_timeout(&mut asr, vec![0.0; 160], &config).await?;
+        assert_eq!(output.as_deref(), Some("slept"));
+
+        let mut asr = sleepy(Duration::from_secs(2));
+        let start = Instant::now();
+        let output = run_with_timeout(&mut asr, vec![0.0; 160], &config).await?;
+        assert_eq!(output, None);
+        assert!(start.elapsed() < Duration::from_millis(1500));
+        // Replaced, the next utterance doesn't wait for the stuck one
+        let output = run_with_timeout(&mut asr, vec![0.0; 160], &config).await?;
+        assert_eq!(output.as_deref(), Some("fresh"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rerun_timeout() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        config.model.run_timeout_secs = 1;
+        config.model.rerun_filename = Some("ggml-large-v3.bin".into());
+        config.model.backend = crate::config::Backend::Echo {
+            text: "fresh".into(),
+        };
+        let asr = Box::new(Sleepy {
+            delay: Duration::ZERO,
+        });
+        let mut transcription = transcription(asr, config.clone());
+        let sleepy: Box<dyn Transcriber> = Box::new(Sleepy {
+            delay: Duration::from_secs(2),
+        });
+        transcription.rerun = Some(Arc::new(Mutex::new(sleepy)));
+        let pasted = Pasted {
+            text: "wreck a nice beach".into(),
+            sent: false,
+        };
+        transcription
+            .history
+            .push(vec![0.0; 160], pasted, &config.history);
+
+        let start = Instant::now();
+        assert_eq!(transcription.rerun(&config).await?, None);
+        assert!(start.elapsed() < Duration::from_millis(1500));
+        // Replaced, the next re-run doesn't wait for the stuck one
+        let Some(rerun) = &transcription.rerun else {
+            bail!("The re-run model is gone");
+        };
+        assert_eq!(lock(rerun)?.run(vec![0.0; 160], &config)?, "fresh");
+        Ok(())
+    }
+
+    /// Fails to load `failures` times.
+    struct Flaky {
+        failures: usize,
+    }
+
+    impl Transcriber for Flaky {
+        fn set_language(&mut self, _language: Option<String>) {}
+
+        fn load(&mut self) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(anyhow!("No such model"));
+            }
+            Ok(())
+        }
+
+        fn run(&mut self, _samples: Vec<f32>, _config: &Config) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_failure() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        let flaky: Box<dyn Transcriber> = Box::new(Flaky { failures: 1 });
+        let mut asr: SharedTranscriber = Arc::new(Mutex::new(flaky));
+        let (_tx, mut keys) = unbounded_channel();
+        let mut duplicates = DuplicateGuard::default();
+        let mut send_gap = SendGap::default();
+
+        // Neither panics nor poisons the transcriber
+        for _ in 0..2 {
+            let pasted = handle_audio(
+                &mut asr,
+                &config,
+                Audio::Warm,
+                false,
+                &mut keys,
+                &mut duplicates,
+                &mut send_gap,
+                &mut Notifications::default(),
+            )
+            .await?;
+            assert_eq!(pasted, None);
+        }
+        // The retry loaded it
+        lock(&asr)?.load()?;
+        assert!(!asr.is_poisoned());
+        Ok(())
+    }
+
+    /// The notifications shown, as (summary, content).
+    #[derive(Default)]
+    struct Notifications(Vec<(String, String)>);
+
+    impl Notifier for Notifications {
+        fn notify(&mut self, _config: &Config, summary: &str, content: &str) {
+            self.0.push((summary.to_string(), content.to_string()));
+        }
+    }
+
+    /// Transcribes anything with `confidence`.
+    struct Mumbly {
+        confidence: f32,
+    }
+
+    impl Transcriber for Mumbly {
+        fn set_language(&mut self, _language: Option<String>) {}
+
+        fn load(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn run(&mut self, _samples: Vec<f32>, _config: &Config) -> Result<String> {
+            Ok("mumbled".into())
+        }
+
+        fn last_confidence(&self) -> Option<f32> {
+            Some(self.confidence)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_low_confidence() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        assert_eq!(low_confidence(Some(0.2), &config.model), None);
+        config.model.min_confidence = Some(0.5);
+        assert_eq!(
+            low_confidence(Some(0.2), &config.model),
+            Some(LowConfidence::Prompt)
+        );
+        assert_eq!(low_confidence(Some(0.8), &config.model), None);
+        // Backends without probabilities always paste
+        assert_eq!(low_confidence(None, &config.model), None);
+        config.model.low_confidence = LowConfidence::Drop;
+        assert_eq!(
+            low_confidence(Some(0.2), &config.model),
+            Some(LowConfidence::Drop)
+        );
+
+        // Below the threshold, nothing is pasted
+        config.model.low_confidence = LowConfidence::Prompt;
+        let mumbly: Box<dyn Transcriber> = Box::new(Mumbly { confidence: 0.2 });
+        let mut asr: SharedTranscriber = Arc::new(Mutex::new(mumbly));
+        let (_tx, mut keys) = unbounded_channel();
+        let mut notifications = Notifications::default();
+        let pasted = handle_audio(
+            &mut asr,
+            &config,
+            Audio::Sample(vec![0.0; 160]),
+            false,
+            &mut keys,
+            &mut DuplicateGuard::default(),
+            &mut SendGap::default(),
+            &mut notifications,
+        )
+        .await?;
+        assert_eq!(pasted, None);
+        assert_eq!(
+            notifications.0,
+            [("Didn't catch that".to_string(), "Try again".to_string())]
+        );
+
+        // Dropped silently
+        config.model.low_confidence = LowConfidence::Drop;
+        let mut notifications = Notifications::default();
+        let pasted = handle_audio(
+            &mut asr,
+            &config,
+            Audio::Sample(vec![0.0; 160]),
+            false,
+            &mut keys,
+            &mut DuplicateGuard::default(),
+            &mut SendGap::default(),
+            &mut notifications,
+        )
+        .await?;
+        assert_eq!(pasted, None);
+        assert!(notifications.0.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paste_delay() -> Result<()> {
+        let start = std::time::Instant::now();
+        let mut pasted_after = None;
+        paste_after(Duration::from_millis(50), || {
+            pasted_after = Some(start.elapsed());
+            Ok(())
+        })
+        .await?;
+        let pasted_after = pasted_after.ok_or(anyhow!("Pasted"))?;
+        assert!(
+            pasted_after >= Duration::from_millis(50),
+            "{pasted_after:?}"
+        );
+
+        // No delay by default
+        let start = std::time::Instant::now();
+        paste_after(Duration::ZERO, || Ok(())).await?;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_gap() {
+        let gap = Duration::from_millis(50);
+        let mut send_gap = SendGap::default();
+        // Two transcripts back to back
+        let start = Instant::now();
+        send_gap.wait(gap).await;
+        assert!(start.elapsed() < gap);
+        send_gap.wait(gap).await;
+        assert!(start.elapsed() >= gap, "{:?}", start.elapsed());
+
+        // Already spaced enough
+        tokio::time::sleep(gap).await;
+        let start = Instant::now();
+        send_gap.wait(gap).await;
+        assert!(start.elapsed() < gap);
+    }
+
+    #[tokio::test]
+    async fn test_autosend() -> Result<()> {
+        use crate::keyboard::Recorder;
+        let mut config = Config::default();
+        let (tx, mut keys) = unbounded_channel();
+        let mut send_gap = SendGap::default();
+
+        let mut keyboard = Recorder::default();
+        assert!(!autosend(&mut keyboard, &config, &mut keys, &mut send_gap).await?);
+        assert!(keyboard.events.is_empty());
+
+        config.activation.autosend = true;
+        assert!(autosend(&mut keyboard, &config, &mut keys, &mut send_gap).await?);
+        assert_eq!(
+            keyboard.events,
+            [
+                EventType::KeyPress(Key::Return),
+                EventType::KeyRelease(Key::Return)
+            ]
+        );
+
+        // Only sent once confirmed
+        config.activation.confirm_key = Some(Key::ShiftRight);
+        config.activation.confirm_timeout_ms = 50;
+        let mut keyboard = Recorder::default();
+        assert!(!autosend(&mut keyboard, &config, &mut keys, &mut send_gap).await?);
+        assert!(keyboard.events.is_empty());
+        tokio::task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let _ = tx.send(Key::ShiftRight);
+        });
+        assert!(autosend(&mut keyboard, &config, &mut keys, &mut send_gap).await?);
+        assert_eq!(keyboard.events.len(), 2);
+
+        // Nothing was pasted to send
+        config.activation.confirm_key = None;
+        config.activation.output_mode = OutputMode::ClipboardOnly;
+        let mut keyboard = Recorder::default();
+        assert!(!autosend(&mut keyboard, &config, &mut keys, &mut send_gap).await?);
+        assert!(keyboard.events.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capped_state() {
+        let recording = || State {
+            recording: true,
+            stop_at: Some(tokio::time::Instant::now()),
+            ..State::default()
+        };
+        // Toggled recordings start again on the next press
+        let mut state = recording();
+        state.capped(false);
+        assert_eq!(state, State::default());
+        // Stuck push-to-talk keys don't start a new recording right away
+        let mut state = recording();
+        state.capped(true);
+        assert!(!state.recording);
+        assert_eq!(state.stop_at, None);
+        assert!(state.cancelled);
+    }
+
+    #[test]
+    fn test_input_warmup() {
+        let mut activation = ActivationConfig {
+            input_warmup: InputWarmup::Key {
+                key: Key::ShiftLeft,
+            },
+            ..ActivationConfig::default()
+        };
+        assert_eq!(input_warmup(&activation), activation.input_warmup);
+        // Nothing is simulated, not even at startup
+        activation.output_mode = OutputMode::ClipboardOnly;
+        assert_eq!(input_warmup(&activation), InputWarmup::None);
+    }
+
+    #[test]
+    fn test_release_deadline() {
+        let now = tokio::time::Instant::now();
+        // Stops right away by default
+        assert_eq!(release_deadline(now, 0), None);
+        assert_eq!(
+            release_deadline(now, 300),
+            Some(now + Duration::from_millis(300))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_release_grace_delays_stop() {
+        let mut state = State {
+            recording: true,
+            ..State::default()
+        };
+        let released_at = tokio::time::Instant::now();
+        assert!(!state.released(50));
+        assert_eq!(state.stop_at, Some(released_at + Duration::from_millis(50)));
+
+        // Pressed again within the grace, recording on
+        tokio::time::advance(Duration::from_millis(20)).await;
+        assert!(state.pressed());
+        assert_eq!(state.stop_at, None);
+        assert!(state.recording);
+        assert!(!state.pressed());
+
+        // Released again, the grace starts over
+        assert!(!state.released(50));
+        let stop_at = released_at + Duration::from_millis(70);
+        assert_eq!(state.stop_at, Some(stop_at));
+        sleep_until(state.stop_at).await;
+        assert_eq!(tokio::time::Instant::now(), stop_at);
+
+        // Without grace, stops on release
+        assert!(state.released(0));
+        assert_eq!(state.stop_at, None);
+    }
+
+    #[test]
+    fn test_cycle_languages() {
+        let mut languages = Languages::new(vec!["en".to_string(), "fr".to_string()]);
+        assert_eq!(languages.current(), Some("en"));
+        assert_eq!(languages.next(), Some("fr"));
+        assert_eq!(languages.next(), Some("en"));
+        assert_eq!(languages.current(), Some("en"));
+
+        // Nothing configured keeps auto-detection
+        let mut languages = Languages::default();
+        assert_eq!(languages.current(), None);
+        assert_eq!(languages.next(), None);
+    }
+
+    #[test]
+    fn test_rerun_replacement() {
+        let pasted = Pasted {
+            text: "wreck a nice beach".into(),
+            sent: false,
+        };
+        assert_eq!(replacement(&pasted, "recognize speech"), Replace::Erase(18));
+        // Characters, not bytes
+        let accented = Pasted {
+            text: "café".into(),
+            sent: false,
+        };
+        assert_eq!(replacement(&accented, "cafe"), Replace::Erase(4));
+        assert_eq!(
+            replacement(&pasted, "wreck a nice beach"),
+            Replace::Unchanged
+        );
+        assert_eq!(replacement(&pasted, ""), Replace::Unchanged);
+        // Already sent, can't be erased
+        let sent = Pasted {
+            sent: true,
+            ..pasted
+        };
+        assert_eq!(replacement(&sent, "recognize speech"), Replace::Paste);
+    }
+
+    #[test]
+    fn test_duplicate_guard() {
+        let mut guard = DuplicateGuard::default();
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Two identical transcripts are allowed, the third is suppressed
+        assert!(!guard.is_duplicate("hello", at(0), 2, window));
+        assert!(!guard.is_duplicate("hello", at(1), 2, window));
+        assert!(guard.is_duplicate("hello", at(2), 2, window));
+        assert!(guard.is_duplicate("hello", at(3), 2, window));
+
+        // A different transcript resets the count
+        assert!(!guard.is_duplicate("world", at(4), 2, window));
+        assert!(!guard.is_duplicate("hello", at(5), 2, window));
+        assert!(!guard.is_duplicate("hello", at(6), 2, window));
+        assert!(guard.is_duplicate("hello", at(7), 2, window));
+
+        // Older transcripts fall out of the window
+        assert!(!guard.is_duplicate("hello", at(30), 2, window));
+
+        // Disabled
+        let mut guard = DuplicateGuard::default();
+        for secs in 0..5 {
+            assert!(!guard.is_duplicate("hello", at(secs), 0, window));
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_processing_notification_disabled() {
+        // Disabled by default, and when notifications are off
+        let mut config = Config::default();
+        assert!(ProcessingNotification::show(&config).handle.is_none());
+        config.activation.notify_processing = true;
+        config.activation.notify = false;
+        assert!(ProcessingNotification::show(&config).handle.is_none());
+    }
+
+    #[test]
+    fn test_idle_deadline() {
+        let now = tokio::time::Instant::now();
+        assert_eq!(idle_deadline(now, None, false), None);
+        assert_eq!(
+            idle_deadline(now, Some(60), false),
+            Some(now + Duration::from_secs(60))
+        );
+        // Never while recording
+        assert_eq!(idle_deadline(now, Some(60), true), None);
+    }
 }
@@ -2,26 +2,286 @@
 //!
 //! This module provides functionality for downloading and running the Whisper model
 //! for speech-to-text transcription. It handles model management and audio processing.
+//!
+//! Engines implement [`Transcriber`], the one used is picked by
+//! `model.backend`.
 
-use anyhow::{Context, Result, anyhow};
-use hf_hub::api::tokio::ApiBuilder;
+use anyhow::{Context, Result, anyhow, bail};
+use hf_hub::Cache;
+use hf_hub::api::tokio::{ApiBuilder, Progress};
 use hound::{SampleFormat, WavReader};
 use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use whisper_rs::{
     FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
+    WhisperSysContext, WhisperSysState, WhisperToken, WhisperTokenData,
 };
 
-use crate::config::Config;
+use crate::audio::resample::Resample;
+use crate::config::{
+    Backend, Config, DownmixMode, MAX_REPLACEMENT_PASSES, ModelConfig, RegexReplacement, Task,
+};
+use crate::formatting;
 
 /// Downloads the Whisper model from Hugging Face Hub.
 ///
 /// This function fetches the model from the specified repository and filename.
+/// Cached models are returned right away, otherwise the download progress is
+/// reported through logs and notifications.
 pub async fn download_model(config: &Config) -> Result<PathBuf> {
-    let api = ApiBuilder::from_env().build()?;
+    fetch_model(
+        &Cache::from_env(),
+        ApiBuilder::from_env(),
+        config,
+        DownloadProgress::new(config),
+    )
+    .await
+}
+
+async fn fetch_model<P: Progress + Clone + Send + Sync + 'static>(
+    cache: &Cache,
+    api: ApiBuilder,
+    config: &Config,
+    progress: P,
+) -> Result<PathBuf> {
+    let filename = &config.model.filename;
+    if let Some(path) = cache.model(config.model.repo.clone()).get(filename) {
+        debug!("Using cached model {}", path.display());
+        return Ok(path);
+    }
+    let api = api.with_progress(false).build()?;
     let repo = api.model(config.model.repo.clone());
-    let filename = repo.get(&config.model.filename).await?;
-    Ok(filename)
+    let path = repo.download_with_progress(filename, progress).await?;
+    Ok(path)
+}
+
+/// Reports model download progress every 10% in the logs.
+///
+/// Cloned for every downloaded chunk, so the counters are shared.
+#[derive(Clone)]
+struct DownloadProgress {
+    config: Config,
+    total: Arc<AtomicUsize>,
+    downloaded: Arc<AtomicUsize>,
+    logged_tenths: Arc<AtomicUsize>,
+}
+
+impl DownloadProgress {
+    fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            total: Arc::new(AtomicUsize::new(0)),
+            downloaded: Arc::new(AtomicUsize::new(0)),
+            logged_tenths: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+const MB: usize = 1024 * 1024;
+
+impl Progress for DownloadProgress {
+    async fn init(&mut self, size: usize, filename: &str) {
+        self.total.store(size, Ordering::Relaxed);
+        info!("Downloading model {filename} ({} MB)", size / MB);
+        self.config.notify(
+            "Downloading model",
+            &format!("{filename} ({} MB), this can take a few minutes", size / MB),
+        );
+    }
+
+    async fn update(&mut self, size: usize) {
+        let downloaded = self.downloaded.fetch_add(size, Ordering::Relaxed) + size;
+        let total = self.total.load(Ordering::Relaxed).max(1);
+        let tenths = (downloaded * 10 / total).min(10);
+        if self.logged_tenths.fetch_max(tenths, Ordering::Relaxed) < tenths {
+            info!(
+                "Downloading model: {}% ({}/{} MB)",
+                tenths * 10,
+                downloaded / MB,
+                total / MB
+            );
+        }
+    }
+
+    async fn finish(&mut self) {
+        info!("Model downloaded");
+        self.config
+            .notify("Model downloaded", "Whispering is getting ready");
+    }
+}
+
+/// Decoding parameters driven by the model configuration.
+///
+/// Implemented by whisper's `FullParams`, and by a recorder in tests so we can
+/// check which values reach the decoder without loading a model.
+trait DecodeParams {
+    fn set_single_segment(&mut self, single_segment: bool);
+    fn set_suppress_blank(&mut self, suppress_blank: bool);
+    fn set_suppress_nst(&mut self, suppress_nst: bool);
+    fn set_max_tokens(&mut self, max_tokens: i32);
+    fn set_audio_ctx(&mut self, audio_ctx: i32);
+    fn set_token_timestamps(&mut self, token_timestamps: bool);
+    fn set_translate(&mut self, translate: bool);
+    fn set_n_threads(&mut self, n_threads: i32);
+    fn set_temperature(&mut self, temperature: f32);
+    fn set_temperature_inc(&mut self, temperature_inc: f32);
+}
+
+impl DecodeParams for FullParams<'_, '_> {
+    fn set_single_segment(&mut self, single_segment: bool) {
+        FullParams::set_single_segment(self, single_segment);
+    }
+    fn set_suppress_blank(&mut self, suppress_blank: bool) {
+        FullParams::set_suppress_blank(self, suppress_blank);
+    }
+    fn set_suppress_nst(&mut self, suppress_nst: bool) {
+        FullParams::set_suppress_nst(self, suppress_nst);
+    }
+    fn set_max_tokens(&mut self, max_tokens: i32) {
+        FullParams::set_max_tokens(self, max_tokens);
+    }
+    fn set_audio_ctx(&mut self, audio_ctx: i32) {
+        FullParams::set_audio_ctx(self, audio_ctx);
+    }
+    fn set_token_timestamps(&mut self, token_timestamps: bool) {
+        FullParams::set_token_timestamps(self, token_timestamps);
+    }
+    fn set_translate(&mut self, translate: bool) {
+        FullParams::set_translate(self, translate);
+    }
+    fn set_n_threads(&mut self, n_threads: i32) {
+        FullParams::set_n_threads(self, n_threads);
+    }
+    fn set_temperature(&mut self, temperature: f32) {
+        FullParams::set_temperature(self, temperature);
+    }
+    fn set_temperature_inc(&mut self, temperature_inc: f32) {
+        FullParams::set_temperature_inc(self, temperature_inc);
+    }
+}
+
+/// Threads used when the available cores can't be counted, whisper's own
+/// default.
+const DEFAULT_THREADS: usize = 4;
+
+/// `model.n_threads`, every available core if not specified.
+fn n_threads(config: &ModelConfig) -> i32 {
+    let n_threads = config.n_threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(DEFAULT_THREADS, |cores| cores.get())
+    });
+    i32::try_from(n_threads).unwrap_or(i32::MAX)
+}
+
+/// Applies the `[model]` decoding options to the whisper parameters.
+fn apply_model_params(params: &mut impl DecodeParams, config: &ModelConfig) {
+    params.set_single_segment(config.single_segment);
+    params.set_suppress_blank(config.suppress_blank);
+    params.set_suppress_nst(config.suppress_non_speech);
+    params.set_max_tokens(i32::try_from(config.max_tokens).unwrap_or(i32::MAX));
+    // 0 is the model's full context
+    params.set_audio_ctx(config.audio_ctx.unwrap_or(0));
+    params.set_token_timestamps(config.token_timestamps);
+    params.set_translate(config.task == Task::Translate);
+    params.set_n_threads(n_threads(config));
+    params.set_temperature(config.temperature);
+    params.set_temperature_inc(config.temperature_inc);
+}
+
+/// Logits filter masking the tokens of `user_data`, a `Vec<WhisperToken>`
+/// of IDs below the vocabulary size, so the decoder never picks them.
+unsafe extern "C" fn suppress_tokens(
+    _ctx: *mut WhisperSysContext,
+    _state: *mut WhisperSysState,
+    _tokens: *const WhisperTokenData,
+    _n_tokens: c_int,
+    logits: *mut f32,
+    user_data: *mut c_void,
+) {
+    // SAFETY: `run` passes its token list, alive until decoding ends, and
+    // checked against the vocabulary size (the length of `logits`).
+    let tokens = unsafe { &*(user_data as *const Vec<WhisperToken>) };
+    for &token in tokens {
+        unsafe { *logits.add(token as usize) = f32::NEG_INFINITY };
+    }
+}
+
+/// Samples quieter than this (about -40 dBFS) count as silence.
+const SILENCE_LEVEL: f32 = 0.01;
+
+/// Pads `samples` (16kHz) with silence so they end with at least `min_secs`
+/// of it, counting the silence already there.
+fn pad_trailing_silence(samples: &mut Vec<f32>, min_secs: f32) {
+    let wanted = (min_secs.max(0.0) * 16000.0) as usize;
+    let trailing = samples
+        .iter()
+        .rev()
+        .take_while(|s| s.abs() < SILENCE_LEVEL)
+        .count();
+    if trailing < wanted {
+        debug!("Padding {} samples of trailing silence", wanted - trailing);
+        samples.resize(samples.len() + wanted - trailing, 0.0);
+    }
+}
+
+/// A speech-to-text engine.
+pub trait Transcriber: Send {
+    /// Sets the transcription language, `None` to auto-detect.
+    fn set_language(&mut self, language: Option<String>);
+
+    /// Loads the model, a no-op when it is already loaded.
+    fn load(&mut self) -> Result<()>;
+
+    /// Loads the model right away when `warm_on_start` is set, so the first
+    /// transcription doesn't wait for it. Otherwise the model is loaded when
+    /// recording starts.
+    fn warm_on_start(&mut self, config: &ModelConfig) -> Result<()> {
+        if config.warm_on_start {
+            info!("Warming the model on start");
+            self.load()?;
+        }
+        Ok(())
+    }
+
+    /// Transcribes 16kHz mono samples, formatted as configured. Empty when no
+    /// voice was detected.
+    fn run(&mut self, samples: Vec<f32>, config: &Config) -> Result<String>;
+
+    /// Unloads the model, a no-op when it isn't loaded.
+    fn unload(&mut self) {}
+
+    /// Mean token probability (0.0 to 1.0) of the last transcript, `None`
+    /// when not computed (see `model.min_confidence`).
+    fn last_confidence(&self) -> Option<f32> {
+        None
+    }
+
+    /// Timed segments of the last transcript, empty when the backend doesn't
+    /// time them.
+    fn last_segments(&self) -> &[Segment] {
+        &[]
+    }
+}
+
+/// Creates the transcriber configured by `model.backend`, downloading its
+/// model when needed.
+pub async fn transcriber(config: &Config) -> Result<Box<dyn Transcriber>> {
+    match &config.model.backend {
+        Backend::Whisper => {
+            let model_path = download_model(config)
+                .await
+                .context("Failed to download model")?;
+            Ok(Box::new(Asr::new(&model_path)?))
+        }
+        Backend::Echo { text } => {
+            info!("Using the echo backend, every transcript is {text:?}");
+            Ok(Box::new(Echo { text: text.clone() }))
+        }
+    }
 }
 
 pub struct Asr {
@@ -32,6 +292,12 @@ pub struct Asr {
     model_path: PathBuf,
 
     context: Option<(WhisperContext, WhisperState)>,
+    /// Transcription language, `None` to auto-detect
+    language: Option<String>,
+    /// Mean token probability of the last transcript
+    confidence: Option<f32>,
+    /// Segments of the last transcript
+    segments: Vec<Segment>,
 }
 
 impl Asr {
@@ -39,25 +305,18 @@ impl Asr {
         Ok(Self {
             model_path: model_path.to_path_buf(),
             context: None,
+            language: None,
+            confidence: None,
+            segments: Vec::new(),
         })
     }
 
-    pub fn load(&mut self) -> Result<()> {
-        debug!("Trying to load model {}", self.model_path.display());
-        let context = WhisperContext::new_with_params(
-            &self.model_path.to_string_lossy(),
-            WhisperContextParameters::default(),
-        )
-        .context(format!("Loading model at {}", self.model_path.display()))?;
-        let state = context.create_state().context("Creating state")?;
-        self.context = Some((context, state));
-        info!("Context loaded");
-        Ok(())
-    }
-
+    /// The samples of the WAV file at `wav_path`, as the 16kHz mono audio
+    /// the model takes: other rates are resampled, channels averaged.
     pub fn samples_from_file(&self, wav_path: &Path) -> Result<Vec<f32>> {
         let mut reader = WavReader::open(wav_path).context("Opening wav reader")?;
-        let samples: Vec<f32> = if reader.spec().sample_format == SampleFormat::Float {
+        let spec = reader.spec();
+        let samples: Vec<f32> = if spec.sample_format == SampleFormat::Float {
             reader.samples::<f32>().map(|s| s.unwrap_or(0.0)).collect()
         } else {
             reader
@@ -65,22 +324,59 @@ impl Asr {
                 .map(|s| s.unwrap_or(0) as f32 / 32768.0)
                 .collect()
         };
-        Ok(samples)
+        let resampler = Resample::for_model(
+            spec.sample_rate,
+            spec.channels,
+            &[],
+            DownmixMode::Average,
+            0,
+        )
+        .context(format!(
+            "Converting the {}Hz {}-channel audio",
+            spec.sample_rate, spec.channels
+        ))?;
+        Ok(match resampler {
+            Some(mut resampler) => resampler.process(&samples),
+            None => samples,
+        })
     }
 
-    /// Runs the Whisper model on the given audio file.
-    ///
-    /// This function takes a path to a WAV file and returns the transcribed text.
-    pub fn run(&mut self, samples: Vec<f32>, config: &Config) -> Result<String> {
+    /// Runs the Whisper model on the given samples, returning its raw
+    /// segments with their timestamps. Unlike [`Transcriber::run`], the text
+    /// isn't formatted.
+    pub fn run_segments(&mut self, mut samples: Vec<f32>, config: &Config) -> Result<Vec<Segment>> {
         // Take context to let it drop later.
         let (_context, mut state) = self.context.take().ok_or(anyhow!("Context was not warm"))?;
+        self.confidence = None;
 
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        pad_trailing_silence(&mut samples, config.model.min_trailing_silence);
+        let duration = samples.len() as f32 / 16000.0;
+        let best_of = config.model.best_of(duration);
+        debug!("Decoding {duration:.1}s of audio with best_of {best_of}");
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of });
         params.set_print_special(false);
         params.set_print_progress(false);
         params.set_print_realtime(false);
         params.set_print_timestamps(false);
-        params.set_language(None);
+        params.set_language(self.language.as_deref());
+        apply_model_params(&mut params, &config.model);
+        let suppressed: Vec<WhisperToken> = config.model.suppress_tokens.clone();
+        if !suppressed.is_empty() {
+            let n_vocab = state.n_vocab();
+            if let Some(token) = suppressed.iter().find(|t| !(0..n_vocab).contains(*t)) {
+                bail!(
+                    "Token {token} of model.suppress_tokens is not in the model's vocabulary of {n_vocab}"
+                );
+            }
+            // SAFETY: `suppressed` outlives `state.full` below and only holds
+            // IDs within the vocabulary.
+            unsafe {
+                params.set_filter_logits_callback(Some(suppress_tokens));
+                params.set_filter_logits_callback_user_data(
+                    &suppressed as *const Vec<WhisperToken> as *mut c_void,
+                );
+            }
+        }
 
         // Set initial prompt if available
         if let Some(prompt) = config.model.prompt.get_prompt_text() {
@@ -90,20 +386,917 @@ impl Asr {
         state.full(params, &samples).context("Setting context")?;
 
         let num_segments = state.full_n_segments()?;
-        let mut text = String::new();
+        let mut segments = Vec::new();
+        let with_tokens = config.model.token_timestamps || config.model.min_confidence.is_some();
         for i in 0..num_segments {
-            let segment = state.full_get_segment_text(i)?;
-            text.push_str(&segment);
-            text.push(' ');
+            let tokens = if with_tokens {
+                let mut tokens = Vec::new();
+                for j in 0..state.full_n_tokens(i)? {
+                    let data = state.full_get_token_data(i, j)?;
+                    tokens.push(Token {
+                        text: state.full_get_token_text(i, j)?,
+                        start_ms: data.t0 * 10,
+                        end_ms: data.t1 * 10,
+                        p: data.p,
+                    });
+                }
+                word_tokens(tokens)
+            } else {
+                Vec::new()
+            };
+            segments.push(Segment {
+                no_speech_prob: state.full_get_segment_no_speech_prob(i),
+                ..Segment::from_centis(
+                    state.full_get_segment_text(i)?,
+                    state.full_get_segment_t0(i)?,
+                    state.full_get_segment_t1(i)?,
+                    tokens,
+                )
+            });
+        }
+        let mut segments = speech_segments(segments, config.model.no_speech_threshold);
+        if config.model.token_timestamps {
+            debug!("Token timestamps:\n{}", token_timings(&segments));
+        }
+        if config.model.min_confidence.is_some() {
+            self.confidence = confidence(&segments);
+            debug!("Transcript confidence: {:?}", self.confidence);
+        }
+        if !config.model.token_timestamps {
+            // Only needed for the confidence
+            segments
+                .iter_mut()
+                .for_each(|segment| segment.tokens.clear());
+        }
+        Ok(segments)
+    }
+}
+
+impl Transcriber for Asr {
+    fn set_language(&mut self, language: Option<String>) {
+        self.language = language;
+    }
+
+    fn load(&mut self) -> Result<()> {
+        if self.context.is_some() {
+            debug!("Model already loaded");
+            return Ok(());
+        }
+        debug!("Trying to load model {}", self.model_path.display());
+        let context = WhisperContext::new_with_params(
+            &self.model_path.to_string_lossy(),
+            WhisperContextParameters::default(),
+        )
+        .context(format!("Loading model at {}", self.model_path.display()))?;
+        let state = context.create_state().context("Creating state")?;
+        self.context = Some((context, state));
+        info!("Context loaded");
+        Ok(())
+    }
+
+    /// Runs the Whisper model on the given samples.
+    fn run(&mut self, samples: Vec<f32>, config: &Config) -> Result<String> {
+        // No stale segments when the run fails
+        self.segments.clear();
+        self.segments = self.run_segments(samples, config)?;
+        let text = join_segments(&self.segments, config.formatting.paragraph_gap);
+        Ok(post_process(&text, config))
+    }
+
+    fn last_confidence(&self) -> Option<f32> {
+        self.confidence
+    }
+
+    fn last_segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    fn unload(&mut self) {
+        if self.context.take().is_some() {
+            info!("Unloaded the idle model");
+        }
+    }
+}
+
+/// A transcribed segment of the audio.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    /// Raw text, usually starting with a space
+    pub text: String,
+    /// Start in the audio, in milliseconds
+    pub start_ms: i64,
+    /// End in the audio, in milliseconds
+    pub end_ms: i64,
+    /// Probability (0.0 to 1.0) that the segment holds no speech
+    pub no_speech_prob: f32,
+    /// The word tokens with their timestamps, empty (and not serialized)
+    /// without `model.token_timestamps`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<Token>,
+}
+
+impl Segment {
+    /// A segment of `text`, its timestamps in milliseconds.
+    pub fn new(text: impl Into<String>, start_ms: i64, end_ms: i64) -> Self {
+        Self {
+            text: text.into(),
+            start_ms,
+            end_ms,
+            no_speech_prob: 0.0,
+            tokens: Vec::new(),
         }
+    }
+
+    /// A segment from Whisper's timestamps, in centiseconds.
+    fn from_centis(text: String, t0: i64, t1: i64, tokens: Vec<Token>) -> Self {
+        Self {
+            text,
+            start_ms: t0 * 10,
+            end_ms: t1 * 10,
+            no_speech_prob: 0.0,
+            tokens,
+        }
+    }
+}
+
+/// The segments at most `threshold` likely to hold no speech, the others
+/// being hallucinations on silence or noise.
+fn speech_segments(segments: Vec<Segment>, threshold: f32) -> Vec<Segment> {
+    segments
+        .into_iter()
+        .filter(|segment| {
+            let speech = segment.no_speech_prob <= threshold;
+            if !speech {
+                debug!(
+                    "Dropping the segment {:?}, no speech probability {:.2}",
+                    segment.text, segment.no_speech_prob
+                );
+            }
+            speech
+        })
+        .collect()
+}
+
+/// A token of a segment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Token {
+    text: String,
+    /// Start in the audio, in milliseconds
+    start_ms: i64,
+    /// End in the audio, in milliseconds
+    end_ms: i64,
+    /// Probability of the token
+    #[serde(skip)]
+    p: f32,
+}
+
+/// The tokens holding text, without the special ones (`[_BEG_]`,
+/// `<|endoftext|>`, `[_TT_150]`, ...).
+fn word_tokens(tokens: Vec<Token>) -> Vec<Token> {
+    tokens
+        .into_iter()
+        .filter(|token| {
+            let text = token.text.trim();
+            !text.is_empty() && !text.starts_with("[_") && !text.starts_with("<|")
+        })
+        .collect()
+}
+
+/// One `[start-end] token` line per token, in seconds.
+fn token_timings(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .flat_map(|segment| &segment.tokens)
+        .map(|token| {
+            format!(
+                "[{:.2}-{:.2}]{}",
+                token.start_ms as f32 / 1000.0,
+                token.end_ms as f32 / 1000.0,
+                token.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Mean probability of the tokens of all segments, `None` without tokens.
+fn confidence(segments: &[Segment]) -> Option<f32> {
+    let probabilities: Vec<f32> = segments
+        .iter()
+        .flat_map(|segment| &segment.tokens)
+        .map(|token| token.p)
+        .collect();
+    if probabilities.is_empty() {
+        return None;
+    }
+    Some(probabilities.iter().sum::<f32>() / probabilities.len() as f32)
+}
+
+/// Joins the segments with spaces, or newlines when a segment starts at
+/// least `paragraph_gap` seconds after the previous one ended.
+fn join_segments(segments: &[Segment], paragraph_gap: Option<f32>) -> String {
+    let mut text = String::new();
+    let mut previous_end = None;
+    for segment in segments {
+        let segment_text = segment.text.trim();
+        if segment_text.is_empty() {
+            continue;
+        }
+        if let Some(end) = previous_end {
+            let gap = (segment.start_ms - end) as f32 / 1000.0;
+            let paragraph = paragraph_gap.is_some_and(|paragraph_gap| gap >= paragraph_gap);
+            text.push(if paragraph { '\n' } else { ' ' });
+        }
+        text.push_str(segment_text);
+        previous_end = Some(segment.end_ms);
+    }
+    text
+}
+
+/// Transcribes any audio to a fixed text, see [`Backend::Echo`].
+pub struct Echo {
+    text: String,
+}
+
+impl Transcriber for Echo {
+    fn set_language(&mut self, _language: Option<String>) {}
+
+    fn load(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn run(&mut self, samples: Vec<f32>, config: &Config) -> Result<String> {
+        debug!("Echoing {:.1}s of audio", samples.len() as f32 / 16000.0);
+        Ok(post_process(&self.text, config))
+    }
+}
+
+/// Applies the prompt echo removal, replacements and formatting to a raw
+/// transcript, shared by every backend.
+pub(crate) fn post_process(text: &str, config: &Config) -> String {
+    let mut text = text.trim().to_string();
+    let echoed_prompt = config.model.prompt.get_prompt_text();
+    if let Some(prompt) = echoed_prompt.filter(|_| config.model.strip_prompt_echo) {
+        text = strip_prompt_echo(text, &prompt);
+    }
+
+    if is_hallucination(&text, &config.model.hallucinations) {
+        debug!("Dropping the likely hallucination {text:?}");
+        return String::new();
+    }
 
-        let mut text = text.trim().to_string();
+    let text = apply_replacements(
+        text,
+        &config.model.replacements,
+        config.model.replacement_passes,
+    );
+    let text = apply_regex_replacements(text, &config.model.regex_replacements);
+
+    let text = formatting::format_transcript(text, &config.formatting);
+    if is_blank(&text) {
+        // Whisper sometimes outputs a lone space or period on silence,
+        // report it as no voice instead of pasting it.
+        return String::new();
+    }
 
-        // Apply replacements
-        for (from, to) in &config.model.replacements {
+    text
+}
+
+/// Applies the `replacements` up to `passes` times (at most
+/// [`MAX_REPLACEMENT_PASSES`]), stopping once a pass changes nothing.
+fn apply_replacements(
+    mut text: String,
+    replacements: &HashMap<String, String>,
+    passes: usize,
+) -> String {
+    let passes = passes.min(MAX_REPLACEMENT_PASSES);
+    for _ in 0..passes {
+        let before = text.clone();
+        for (from, to) in replacements {
             text = text.replace(from, to);
         }
+        if text == before {
+            return text;
+        }
+    }
+    if passes > 1 {
+        debug!("The replacements changed the text in each of the {passes} passes, cyclic?");
+    }
+    text
+}
+
+/// Applies the `regex_replacements` in order, each to the output of the
+/// previous one.
+fn apply_regex_replacements(mut text: String, replacements: &[RegexReplacement]) -> String {
+    for replacement in replacements {
+        text = replacement
+            .regex
+            .replace_all(&text, replacement.replacement.as_str())
+            .into_owned();
+    }
+    text
+}
+
+/// Removes `prompt` when Whisper echoed it at the very start of `text`.
+///
+/// Only the whole prompt followed by a word boundary is removed, the prompt's
+/// words dictated anywhere else are kept.
+fn strip_prompt_echo(text: String, prompt: &str) -> String {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return text;
+    }
+    let Some(rest) = text.strip_prefix(prompt) else {
+        return text;
+    };
+    if rest.starts_with(char::is_alphanumeric) {
+        return text;
+    }
+    debug!("Stripping the prompt echoed at the start of the transcript");
+    rest.trim_start_matches(|c: char| c.is_whitespace() || matches!(c, ',' | '.' | ';' | ':'))
+        .to_string()
+}
+
+/// Whether `text` contains nothing but whitespace and punctuation.
+fn is_blank(text: &str) -> bool {
+    text.chars().all(|c| !c.is_alphanumeric())
+}
+
+/// Lowercased words of `text`, without punctuation.
+fn bare_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether the whole transcript is one of the `hallucinations` phrases,
+/// ignoring case and punctuation.
+fn is_hallucination(text: &str, hallucinations: &[String]) -> bool {
+    let words = bare_words(text);
+    !words.is_empty()
+        && hallucinations
+            .iter()
+            .any(|phrase| bare_words(phrase) == words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct RecordedParams {
+        single_segment: bool,
+        suppress_blank: bool,
+        suppress_nst: bool,
+        max_tokens: i32,
+        audio_ctx: i32,
+        token_timestamps: bool,
+        translate: bool,
+        n_threads: i32,
+        temperature: f32,
+        temperature_inc: f32,
+    }
+
+    impl DecodeParams for RecordedParams {
+        fn set_single_segment(&mut self, single_segment: bool) {
+            self.single_segment = single_segment;
+        }
+        fn set_suppress_blank(&mut self, suppress_blank: bool) {
+            self.suppress_blank = suppress_blank;
+        }
+        fn set_suppress_nst(&mut self, suppress_nst: bool) {
+            self.suppress_nst = suppress_nst;
+        }
+        fn set_max_tokens(&mut self, max_tokens: i32) {
+            self.max_tokens = max_tokens;
+        }
+        fn set_audio_ctx(&mut self, audio_ctx: i32) {
+            self.audio_ctx = audio_ctx;
+        }
+        fn set_token_timestamps(&mut self, token_timestamps: bool) {
+            self.token_timestamps = token_timestamps;
+        }
+        fn set_translate(&mut self, translate: bool) {
+            self.translate = translate;
+        }
+        fn set_n_threads(&mut self, n_threads: i32) {
+            self.n_threads = n_threads;
+        }
+        fn set_temperature(&mut self, temperature: f32) {
+            self.temperature = temperature;
+        }
+        fn set_temperature_inc(&mut self, temperature_inc: f32) {
+            self.temperature_inc = temperature_inc;
+        }
+    }
+
+    #[test]
+    fn test_single_segment_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        assert!(!params.single_segment);
+
+        config.single_segment = true;
+        apply_model_params(&mut params, &config);
+        assert!(params.single_segment);
+    }
+
+    #[test]
+    fn test_suppress_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        assert!(params.suppress_blank);
+        assert!(!params.suppress_nst);
+
+        config.suppress_blank = false;
+        config.suppress_non_speech = true;
+        apply_model_params(&mut params, &config);
+        assert!(!params.suppress_blank);
+        assert!(params.suppress_nst);
+    }
+
+    #[test]
+    fn test_max_tokens_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        // Whisper's default, no limit
+        assert_eq!(params.max_tokens, 0);
+
+        config.max_tokens = 64;
+        apply_model_params(&mut params, &config);
+        assert_eq!(params.max_tokens, 64);
+    }
+
+    #[test]
+    fn test_token_timestamps() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        assert!(!params.token_timestamps);
+        config.token_timestamps = true;
+        apply_model_params(&mut params, &config);
+        assert!(params.token_timestamps);
+
+        let token = |text: &str, t0: i64, t1: i64| Token {
+            text: text.into(),
+            start_ms: t0 * 10,
+            end_ms: t1 * 10,
+            p: 0.9,
+        };
+        let tokens = word_tokens(vec![
+            token("[_BEG_]", 0, 0),
+            token(" Hello", 0, 32),
+            token(" world", 32, 75),
+            token(".", 75, 80),
+            token("[_TT_40]", 80, 80),
+            token("<|endoftext|>", 80, 80),
+        ]);
+        assert_eq!(
+            tokens,
+            [
+                token(" Hello", 0, 32),
+                token(" world", 32, 75),
+                token(".", 75, 80)
+            ]
+        );
+        let segments = [Segment::from_centis(" Hello world.".into(), 0, 80, tokens)];
+        assert_eq!(
+            token_timings(&segments),
+            "[0.00-0.32] Hello\n[0.32-0.75] world\n[0.75-0.80]."
+        );
+    }
+
+    #[test]
+    fn test_confidence() {
+        let segment = |probabilities: &[f32]| {
+            let tokens = probabilities
+                .iter()
+                .map(|&p| Token {
+                    text: " word".into(),
+                    start_ms: 0,
+                    end_ms: 0,
+                    p,
+                })
+                .collect();
+            Segment::from_centis(String::new(), 0, 0, tokens)
+        };
+        assert_eq!(confidence(&[]), None);
+        assert_eq!(confidence(&[segment(&[])]), None);
+        // Every token weighs the same, whatever its segment
+        let confidence = confidence(&[segment(&[0.9, 0.8]), segment(&[0.1])]);
+        assert!(confidence.is_some_and(|c| (c - 0.6).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_segment_milliseconds() {
+        // Whisper counts in centiseconds
+        let segment = Segment::from_centis(" Hello.".into(), 150, 1275, Vec::new());
+        assert_eq!(segment.start_ms, 1500);
+        assert_eq!(segment.end_ms, 12750);
+        let segment = Segment::from_centis(" Hi.".into(), 0, 3, Vec::new());
+        assert_eq!((segment.start_ms, segment.end_ms), (0, 30));
+    }
+
+    #[test]
+    fn test_segment_serialization() -> Result<()> {
+        let token = Token {
+            text: " Hi".into(),
+            start_ms: 0,
+            end_ms: 30,
+            p: 0.9,
+        };
+        let segment = Segment::from_centis(" Hi.".into(), 0, 3, vec![token]);
+        let value = toml::Value::try_from(&segment)?;
+        let tokens = value.get("tokens").and_then(toml::Value::as_array);
+        // The probability is internal
+        let expected: toml::Value = toml::from_str("text = ' Hi'\nstart_ms = 0\nend_ms = 30")?;
+        assert_eq!(tokens, Some(&vec![expected]));
+
+        // Without token_timestamps
+        let value = toml::Value::try_from(Segment::new(" Hi.", 0, 30))?;
+        assert_eq!(value.get("tokens"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_speech_segments() {
+        let segment = |text: &str, no_speech_prob| Segment {
+            no_speech_prob,
+            ..Segment::new(text, 0, 1000)
+        };
+        let segments = vec![
+            segment(" Hello.", 0.05),
+            segment(" Thank you.", 0.92),
+            segment(" Borderline.", 0.6),
+        ];
+        let kept = speech_segments(segments.clone(), 0.6);
+        assert_eq!(kept, [segments[0].clone(), segments[2].clone()]);
+        assert_eq!(speech_segments(segments.clone(), 1.0), segments);
+        assert!(speech_segments(segments, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_no_speech_leaves_no_voice() {
+        let config = Config::default();
+        let segment = Segment {
+            no_speech_prob: 0.9,
+            ..Segment::new(" Subtitles by the community.", 0, 900)
+        };
+        // Every segment dropped, reported as no voice like silence
+        let segments = speech_segments(vec![segment], config.model.no_speech_threshold);
+        let text = join_segments(&segments, None);
+        assert_eq!(post_process(&text, &config), "");
+    }
+
+    #[test]
+    fn test_join_segments() {
+        let segment = |text: &str, t0, t1| Segment::from_centis(text.into(), t0, t1, Vec::new());
+        let segments = [
+            segment(" First point.", 0, 150),
+            // 0.5s pause
+            segment(" Same paragraph.", 200, 400),
+            // 2s pause
+            segment(" Second point.", 600, 800),
+            segment(" ", 800, 900),
+            // 1.5s pause, from the end of the last non-empty segment
+            segment(" Third.", 950, 1000),
+        ];
+        assert_eq!(
+            join_segments(&segments, None),
+            "First point. Same paragraph. Second point. Third."
+        );
+        assert_eq!(
+            join_segments(&segments, Some(1.5)),
+            "First point. Same paragraph.\nSecond point.\nThird."
+        );
+        assert_eq!(
+            join_segments(&segments, Some(3.0)),
+            "First point. Same paragraph. Second point. Third."
+        );
+        assert_eq!(join_segments(&[], Some(1.0)), "");
+    }
+
+    #[test]
+    fn test_audio_ctx_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        // The full context
+        assert_eq!(params.audio_ctx, 0);
+
+        config.audio_ctx = Some(512);
+        apply_model_params(&mut params, &config);
+        assert_eq!(params.audio_ctx, 512);
+    }
+
+    #[test]
+    fn test_task_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        assert!(!params.translate);
+
+        config.task = Task::Translate;
+        apply_model_params(&mut params, &config);
+        assert!(params.translate);
+    }
+
+    #[test]
+    fn test_n_threads_reaches_params() -> Result<()> {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        let cores = std::thread::available_parallelism()?.get();
+        assert_eq!(params.n_threads, i32::try_from(cores)?);
+
+        config.n_threads = Some(2);
+        apply_model_params(&mut params, &config);
+        assert_eq!(params.n_threads, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temperature_reaches_params() {
+        let mut config = ModelConfig::default();
+        let mut params = RecordedParams::default();
+        apply_model_params(&mut params, &config);
+        assert_eq!((params.temperature, params.temperature_inc), (0.0, 0.2));
+
+        config.temperature = 0.4;
+        config.temperature_inc = 0.0;
+        apply_model_params(&mut params, &config);
+        assert_eq!((params.temperature, params.temperature_inc), (0.4, 0.0));
+    }
+
+    #[test]
+    fn test_pad_trailing_silence() {
+        // Speech right up to the end
+        let mut samples = vec![0.5; 16000];
+        pad_trailing_silence(&mut samples, 0.5);
+        assert_eq!(samples.len(), 24000);
+        assert!(samples[16000..].iter().all(|&s| s == 0.0));
+
+        // Existing silence counts towards the minimum
+        let mut samples = vec![0.5; 16000];
+        samples.extend(vec![0.001; 4000]);
+        pad_trailing_silence(&mut samples, 0.5);
+        assert_eq!(samples.len(), 24000);
+
+        // Enough silence already, or disabled
+        let mut samples = vec![0.5; 16000];
+        samples.extend(vec![0.0; 8000]);
+        pad_trailing_silence(&mut samples, 0.5);
+        assert_eq!(samples.len(), 24000);
+        pad_trailing_silence(&mut samples, 0.0);
+        assert_eq!(samples.len(), 24000);
+    }
+
+    #[test]
+    fn test_strip_prompt_echo() {
+        let prompt = "Google, HuggingFace";
+        let strip = |text: &str| strip_prompt_echo(text.to_string(), prompt);
+        assert_eq!(
+            strip("Google, HuggingFace. Send the report"),
+            "Send the report"
+        );
+        assert_eq!(strip("Google, HuggingFace"), "");
+        // Not at the very start
+        assert_eq!(
+            strip("I like Google, HuggingFace too"),
+            "I like Google, HuggingFace too"
+        );
+        // Only a prefix of a longer word
+        assert_eq!(strip("Google, HuggingFaces"), "Google, HuggingFaces");
+        assert_eq!(strip_prompt_echo("Hello".to_string(), " "), "Hello");
+    }
+
+    #[test]
+    fn test_replacement_passes() {
+        let replacements = HashMap::from([
+            ("co pilot".to_string(), "copilot".to_string()),
+            ("copilot x".to_string(), "Copilot X".to_string()),
+        ]);
+        // Whatever the order the replacements are applied in
+        assert_eq!(
+            apply_replacements("try co pilot x".into(), &replacements, 2),
+            "try Copilot X"
+        );
+
+        // Bounded, whichever replacement comes first
+        let cyclic = HashMap::from([
+            ("yes".to_string(), "no".to_string()),
+            ("no".to_string(), "yes".to_string()),
+        ]);
+        let text = apply_replacements("yes".into(), &cyclic, MAX_REPLACEMENT_PASSES);
+        assert!(text == "yes" || text == "no", "{text}");
+        // Even when the caller skipped validation
+        let text = apply_replacements("yes".into(), &cyclic, usize::MAX);
+        assert!(text == "yes" || text == "no", "{text}");
+    }
+
+    #[test]
+    fn test_regex_replacements() -> Result<()> {
+        let replacements = [
+            RegexReplacement::new(r"(?i)\bteh\b", "the")?,
+            RegexReplacement::new(r"(\d+) percent", "$1%")?,
+            RegexReplacement::new(r"(?<word>\w+) (?<number>\d+)%", "${number}% ${word}")?,
+        ];
+        // Whole words only, whatever their case
+        assert_eq!(
+            apply_regex_replacements("Teh tehran teh".into(), &replacements[..1]),
+            "the tehran the"
+        );
+        // In order, each on the output of the previous one
+        assert_eq!(
+            apply_regex_replacements("growth 12 percent".into(), &replacements),
+            "12% growth"
+        );
+        let reversed = [replacements[2].clone(), replacements[1].clone()];
+        assert_eq!(
+            apply_regex_replacements("growth 12 percent".into(), &reversed),
+            "growth 12%"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_regex_after_replacements() -> Result<()> {
+        let mut config = Config::builder()
+            .replacement("per cent", "percent")
+            .build()?;
+        config.model.regex_replacements = vec![RegexReplacement::new(r"(\d+) percent", "$1%")?];
+        assert_eq!(post_process(" 5 per cent", &config), "5%");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_hallucination() {
+        let hallucinations = ModelConfig::default().hallucinations;
+        assert!(is_hallucination("Thank you.", &hallucinations));
+        assert!(is_hallucination(" you", &hallucinations));
+        assert!(is_hallucination("Thanks for watching!", &hallucinations));
+        assert!(!is_hallucination("thank you for this", &hallucinations));
+        assert!(!is_hallucination("Thank you, Anna.", &hallucinations));
+        assert!(!is_hallucination("", &hallucinations));
+        // Disabled with an empty list
+        assert!(!is_hallucination("Thank you.", &[]));
+    }
+
+    #[test]
+    fn test_hallucination_not_pasted() {
+        let config = Config::default();
+        assert_eq!(post_process(" Thank you.", &config), "");
+        assert_eq!(
+            post_process(" Thank you for this.", &config),
+            "Thank you for this."
+        );
+    }
+
+    #[test]
+    fn test_is_blank() {
+        assert!(is_blank(""));
+        assert!(is_blank("   "));
+        assert!(is_blank("."));
+        assert!(is_blank(" ... ?"));
+        assert!(!is_blank("Hi."));
+        assert!(!is_blank("42"));
+    }
+
+    #[tokio::test]
+    async fn test_echo_backend() -> Result<()> {
+        let mut config = Config::builder().replacement("teh", "the").build()?;
+        config.model.backend = Backend::Echo {
+            text: " teh answer ".into(),
+        };
+        // No model to download
+        let mut echo = transcriber(&config).await?;
+        echo.warm_on_start(&config.model)?;
+        assert_eq!(echo.run(vec![0.0; 16000], &config)?, "the answer");
+
+        config.model.backend = Backend::Echo { text: " . ".into() };
+        let mut echo = transcriber(&config).await?;
+        assert_eq!(echo.run(Vec::new(), &config)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_warm_on_start() -> Result<()> {
+        let mut config = ModelConfig::default();
+        let mut asr = Asr::new(Path::new("/nonexistent/ggml-base.en.bin"))?;
+        asr.warm_on_start(&config)?;
+        assert!(asr.context.is_none());
+
+        // Loading is attempted at startup, failing on the missing model
+        config.warm_on_start = true;
+        let err = asr
+            .warm_on_start(&config)
+            .err()
+            .map(|err| err.to_string())
+            .unwrap_or_default();
+        assert!(err.contains("Loading model"), "{err}");
+        Ok(())
+    }
+
+    /// Counts the download progress calls.
+    #[derive(Clone, Default)]
+    struct CountingProgress {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Progress for CountingProgress {
+        async fn init(&mut self, _size: usize, _filename: &str) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        async fn update(&mut self, _size: usize) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        async fn finish(&mut self) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_samples_from_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, channels, sample_rate, frame: &[i16]| -> Result<PathBuf> {
+            let path = dir.path().join(name);
+            let spec = hound::WavSpec {
+                channels,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::create(&path, spec)?;
+            for _ in 0..sample_rate {
+                for &sample in frame {
+                    writer.write_sample(sample)?;
+                }
+            }
+            writer.finalize()?;
+            Ok(path)
+        };
+        let asr = Asr::new(Path::new("unused.bin"))?;
+
+        // A second at 16kHz mono is used as is
+        let mono = write("mono.wav", 1, 16000, &[8192])?;
+        let samples = asr.samples_from_file(&mono)?;
+        assert_eq!(samples.len(), 16000);
+        assert!(samples.iter().all(|&s| s == 0.25));
+
+        // A second at 48kHz stereo becomes about a second at 16kHz mono
+        let stereo = write("stereo.wav", 2, 48000, &[0, 16384])?;
+        let samples = asr.samples_from_file(&stereo)?;
+        assert!(
+            (15000..=16000).contains(&samples.len()),
+            "{}",
+            samples.len()
+        );
+        // Past the filter warm-up, the channels' average
+        let last = samples[samples.len() - 1];
+        assert!((last - 0.25).abs() < 0.01, "{last}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cached_model_skips_progress() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = Cache::new(dir.path().to_path_buf());
+        let config = Config::default();
+        let repo = cache.model(config.model.repo.clone());
+        repo.create_ref("0123abcd")?;
+        let snapshot = dir
+            .path()
+            .join("models--ggerganov--whisper.cpp")
+            .join("snapshots")
+            .join("0123abcd");
+        std::fs::create_dir_all(&snapshot)?;
+        std::fs::write(snapshot.join(&config.model.filename), b"model")?;
+
+        let progress = CountingProgress::default();
+        let api = ApiBuilder::from_cache(cache.clone()).with_endpoint("http://127.0.0.1:9".into());
+        let path = fetch_model(&cache, api, &config, progress.clone()).await?;
+        assert_eq!(path, snapshot.join(&config.model.filename));
+        assert_eq!(progress.calls.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
 
-        Ok(text)
+    #[tokio::test]
+    async fn test_download_progress_counts_chunks() {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        let mut progress = DownloadProgress::new(&config);
+        progress.init(100 * MB, "model.bin").await;
+        // Chunks are reported from clones sharing the counters
+        let mut other = progress.clone();
+        progress.update(25 * MB).await;
+        other.update(30 * MB).await;
+        assert_eq!(progress.downloaded.load(Ordering::Relaxed), 55 * MB);
+        assert_eq!(progress.logged_tenths.load(Ordering::Relaxed), 5);
+        other.update(45 * MB).await;
+        assert_eq!(progress.logged_tenths.load(Ordering::Relaxed), 10);
     }
 }
@@ -0,0 +1,95 @@
+//! Input clipping detection.
+//!
+//! Counts samples at or near full scale in the audio callback and warns once
+//! when too many of them clip, since Whisper accuracy drops sharply on
+//! saturated input.
+
+use log::warn;
+
+use crate::config::Config;
+
+/// Samples with a magnitude above this level are considered clipped.
+const CLIP_LEVEL: f32 = 0.99;
+
+/// Proportion of samples at or near ±1.0.
+pub fn clipping_ratio(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let clipped = samples.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+    clipped as f32 / samples.len() as f32
+}
+
+/// Warns (once) when the clipping ratio of a chunk exceeds the threshold.
+pub struct ClippingDetector {
+    threshold: f32,
+    warned: bool,
+    config: Config,
+}
+
+impl ClippingDetector {
+    /// Returns `None` when clipping detection is disabled.
+    pub fn new(config: &Config) -> Option<Self> {
+        config.audio.clipping_threshold.map(|threshold| Self {
+            threshold,
+            warned: false,
+            config: config.clone(),
+        })
+    }
+
+    /// Checks a chunk of samples, returns true if it triggered the warning.
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        if self.warned {
+            return false;
+        }
+        let ratio = clipping_ratio(samples);
+        if ratio <= self.threshold {
+            return false;
+        }
+        self.warned = true;
+        warn!(
+            "Audio input is clipping ({:.1}% of samples), consider lowering the input gain",
+            ratio * 100.0
+        );
+        // Never block the audio callback on the notification daemon.
+        let config = self.config.clone();
+        std::thread::spawn(move || {
+            config.notify(
+                "Audio input is clipping",
+                "Lower your microphone input gain for better transcriptions",
+            );
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipping_ratio() {
+        assert_eq!(clipping_ratio(&[]), 0.0);
+        assert_eq!(clipping_ratio(&[0.0, 0.5, -0.5, 0.9]), 0.0);
+        assert_eq!(clipping_ratio(&[1.0, -1.0, 0.995, 0.1]), 0.75);
+        assert_eq!(clipping_ratio(&[0.2, -0.999, 0.3, 0.4]), 0.25);
+    }
+
+    #[test]
+    fn test_detector_warns_once() -> anyhow::Result<()> {
+        let mut config = Config::default();
+        config.activation.notify = false;
+        assert!(ClippingDetector::new(&config).is_none());
+
+        config.audio.clipping_threshold = Some(0.1);
+        let mut detector =
+            ClippingDetector::new(&config).ok_or(anyhow::anyhow!("Detection is enabled"))?;
+        assert!(!detector.process(&[0.1; 100]));
+        let mut hot = vec![0.1; 100];
+        hot[..20].fill(1.0);
+        assert!(detector.process(&hot));
+        // Only warn once
+        assert!(!detector.process(&hot));
+        Ok(())
+    }
+}
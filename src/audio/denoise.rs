@@ -0,0 +1,253 @@
+//! Optional noise suppression of the 16kHz mono samples.
+//!
+//! A spectral gate (built with the `denoise` feature): the noise floor of
+//! every frequency bin is tracked over time, and bins close to it are
+//! attenuated. Steady noise (fans, hum, traffic) is removed before the VAD
+//! and Whisper see the audio, speech passes through.
+
+use crate::config::Config;
+
+/// Denoises the samples in the audio callback, see [`NoiseSuppression::new`].
+#[cfg_attr(not(feature = "denoise"), allow(dead_code))]
+pub struct NoiseSuppression {
+    #[cfg(feature = "denoise")]
+    gate: gate::SpectralGate,
+}
+
+#[cfg_attr(not(feature = "denoise"), allow(dead_code))]
+impl NoiseSuppression {
+    /// Returns `None` when noise suppression is disabled. It runs on the
+    /// 16kHz mono audio given to the VAD and Whisper.
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.audio.noise_suppression {
+            return None;
+        }
+        #[cfg(feature = "denoise")]
+        {
+            Some(Self {
+                gate: gate::SpectralGate::new(config.audio.noise_suppression_strength),
+            })
+        }
+        // Rejected by `Config::validate` without the feature
+        #[cfg(not(feature = "denoise"))]
+        {
+            None
+        }
+    }
+
+    /// Denoises a chunk of samples. The output lags the input by a few
+    /// milliseconds, so its length can differ from the chunk's.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        #[cfg(feature = "denoise")]
+        {
+            self.gate.process(samples)
+        }
+        #[cfg(not(feature = "denoise"))]
+        {
+            samples.to_vec()
+        }
+    }
+}
+
+#[cfg(feature = "denoise")]
+mod gate {
+    use std::sync::Arc;
+
+    use log::warn;
+    use realfft::num_complex::Complex;
+    use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+    /// Analysis frame, 32ms at 16kHz.
+    const FRAME: usize = 512;
+    /// Frames overlap by half, the output lags the input by `HOP` samples.
+    const HOP: usize = FRAME / 2;
+    /// Per frame smoothing of the bin levels, reduces the variance of noise
+    /// (and the "musical noise" of isolated bins let through).
+    const LEVEL_SMOOTHING: f32 = 0.6;
+    /// Per frame smoothing of the noise floor when the level drops below it
+    /// (follows within a few frames) or rises above it (follows over seconds,
+    /// so speech isn't learnt as noise).
+    const NOISE_FALL: f32 = 0.9;
+    const NOISE_RISE: f32 = 0.999;
+
+    /// Tracked magnitudes of a frequency bin.
+    #[derive(Clone, Copy)]
+    struct Bin {
+        level: f32,
+        noise: f32,
+    }
+
+    pub struct SpectralGate {
+        r2c: Arc<dyn RealToComplex<f32>>,
+        c2r: Arc<dyn ComplexToReal<f32>>,
+        /// Square root of a periodic Hann window, applied before and after
+        /// the FFT so overlapping frames add up to the input
+        window: Vec<f32>,
+        /// Level and noise floor of each bin, `None` until the first frame
+        bins: Option<Vec<Bin>>,
+        /// How much of the noise floor is subtracted from each bin
+        over_subtraction: f32,
+        /// Minimum gain of a bin, keeps some background instead of
+        /// "musical noise"
+        floor: f32,
+        /// Input not processed yet, starts with `HOP` samples of silence
+        pending: Vec<f32>,
+        /// Second half of the previous frame, added to the next one
+        overlap: Vec<f32>,
+        frame: Vec<f32>,
+        spectrum: Vec<Complex<f32>>,
+    }
+
+    impl SpectralGate {
+        /// `strength` goes from 0.0 (no-op) to 1.0 (aggressive).
+        pub fn new(strength: f32) -> Self {
+            let strength = strength.clamp(0.0, 1.0);
+            let mut planner = RealFftPlanner::new();
+            let r2c = planner.plan_fft_forward(FRAME);
+            let c2r = planner.plan_fft_inverse(FRAME);
+            let window = (0..FRAME)
+                .map(|i| {
+                    let phase = 2.0 * std::f32::consts::PI * i as f32 / FRAME as f32;
+                    (0.5 - 0.5 * phase.cos()).sqrt()
+                })
+                .collect();
+            let spectrum = r2c.make_output_vec();
+            Self {
+                r2c,
+                c2r,
+                window,
+                bins: None,
+                over_subtraction: 1.0 + 2.0 * strength,
+                floor: 1.0 - 0.9 * strength,
+                pending: vec![0.0; HOP],
+                overlap: vec![0.0; HOP],
+                frame: vec![0.0; FRAME],
+                spectrum,
+            }
+        }
+
+        pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+            self.pending.extend_from_slice(samples);
+            let mut output = Vec::with_capacity(self.pending.len());
+            while self.pending.len() >= FRAME {
+                self.process_frame(&mut output);
+                self.pending.drain(..HOP);
+            }
+            output
+        }
+
+        /// Denoises the first `FRAME` pending samples, outputs `HOP` of them.
+        fn process_frame(&mut self, output: &mut Vec<f32>) {
+            for ((x, &sample), &w) in self.frame.iter_mut().zip(&self.pending).zip(&self.window) {
+                *x = sample * w;
+            }
+            if let Err(err) = self.r2c.process(&mut self.frame, &mut self.spectrum) {
+                warn!("Noise suppression failed, passing audio through: {err}");
+                output.extend_from_slice(&self.pending[..HOP]);
+                return;
+            }
+
+            let bins = self.bins.get_or_insert_with(|| {
+                self.spectrum
+                    .iter()
+                    .map(|bin| Bin {
+                        level: bin.norm(),
+                        noise: bin.norm(),
+                    })
+                    .collect()
+            });
+            for (value, bin) in self.spectrum.iter_mut().zip(bins) {
+                bin.level = LEVEL_SMOOTHING * bin.level + (1.0 - LEVEL_SMOOTHING) * value.norm();
+                let smoothing = if bin.level < bin.noise {
+                    NOISE_FALL
+                } else {
+                    NOISE_RISE
+                };
+                bin.noise = smoothing * bin.noise + (1.0 - smoothing) * bin.level;
+                let gain = if bin.level > 0.0 {
+                    (1.0 - self.over_subtraction * bin.noise / bin.level).max(self.floor)
+                } else {
+                    self.floor
+                };
+                *value *= gain;
+            }
+
+            if let Err(err) = self.c2r.process(&mut self.spectrum, &mut self.frame) {
+                warn!("Noise suppression failed, passing audio through: {err}");
+                output.extend_from_slice(&self.pending[..HOP]);
+                return;
+            }
+            // The inverse FFT isn't normalized
+            let scale = 1.0 / FRAME as f32;
+            let (first, second) = self.frame.split_at(HOP);
+            let (first_window, second_window) = self.window.split_at(HOP);
+            for ((overlap, &x), &w) in self.overlap.iter().zip(first).zip(first_window) {
+                output.push(overlap + x * w * scale);
+            }
+            for ((overlap, &x), &w) in self.overlap.iter_mut().zip(second).zip(second_window) {
+                *overlap = x * w * scale;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn rms(samples: &[f32]) -> f32 {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        }
+
+        /// Deterministic white-ish noise.
+        fn noise(n: usize, level: f32) -> Vec<f32> {
+            let mut state: u32 = 12345;
+            (0..n)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    ((state >> 16) as f32 / 32768.0 - 1.0) * level
+                })
+                .collect()
+        }
+
+        #[test]
+        fn test_zero_strength_reconstructs_input() {
+            let mut gate = SpectralGate::new(0.0);
+            let input = noise(16000, 0.3);
+            let output = gate.process(&input);
+            // Lags by HOP samples, the tail waits for the next chunk
+            assert!(16000 - output.len() < HOP, "{}", output.len());
+            let error = input
+                .iter()
+                .zip(&output[HOP..])
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 1e-4, "{error}");
+        }
+
+        #[test]
+        fn test_attenuates_steady_noise() {
+            let mut gate = SpectralGate::new(1.0);
+            let input = noise(16000 * 3, 0.1);
+            let output = gate.process(&input);
+            // Once the noise floor is learnt
+            let before = rms(&input[16000..]);
+            let after = rms(&output[16000..]);
+            assert!(after < before * 0.3, "{before} -> {after}");
+        }
+
+        #[test]
+        fn test_keeps_tone_over_noise() {
+            let mut gate = SpectralGate::new(1.0);
+            let background = noise(16000 * 3, 0.02);
+            // Noise only, then a loud 440Hz tone over it
+            let mut input = background[..16000 * 2].to_vec();
+            input.extend(background[16000 * 2..].iter().enumerate().map(|(i, n)| {
+                n + 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin()
+            }));
+            let output = gate.process(&input);
+            let tone = rms(&input[16000 * 2 + HOP..]);
+            let denoised = rms(&output[16000 * 2 + FRAME..]);
+            assert!(denoised > tone * 0.9, "{tone} -> {denoised}");
+        }
+    }
+}
@@ -0,0 +1,801 @@
+//! Input device selection shared by the audio recorders.
+
+use anyhow::{Context, Result, anyhow, bail};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Sample, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange};
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::config::{AudioConfig, Device, SampleFormat};
+
+use super::permission::check_microphone;
+use super::resample::MODEL_SAMPLE_RATE;
+
+/// Name of an output device captured in loopback, e.g. "Speakers (loopback)".
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn loopback_name(name: &str) -> String {
+    format!("{name} (loopback)")
+}
+
+/// Whether `name` looks like a source capturing system audio rather than a
+/// microphone: PulseAudio/PipeWire monitors, Windows "Stereo Mix" and output
+/// devices captured in loopback.
+fn is_monitor(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ["monitor", "loopback", "stereo mix"]
+        .iter()
+        .any(|monitor| name.contains(monitor))
+}
+
+/// Finds the device matching `wanted` among `names`.
+///
+/// An exact match always wins, otherwise the first device whose name contains
+/// `wanted` (case-insensitive) is picked, e.g. `"C920"` finds
+/// `"sysdefault:CARD=C920"`.
+fn find_device(names: &[String], wanted: &str) -> Option<usize> {
+    if let Some(index) = names.iter().position(|name| name == wanted) {
+        return Some(index);
+    }
+    let wanted = wanted.to_lowercase();
+    let matches: Vec<usize> = names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| name.to_lowercase().contains(&wanted))
+        .map(|(index, _)| index)
+        .collect();
+    if matches.len() > 1 {
+        let matched: Vec<&String> = matches.iter().map(|&index| &names[index]).collect();
+        info!("Several devices match '{wanted}': {matched:?}, using the first one");
+    }
+    matches.first().copied()
+}
+
+/// Checks that `index` selects one of the devices `names`, in the order of
+/// `whispering list-devices`.
+fn device_index(index: usize, names: &[String]) -> Result<usize> {
+    if names.is_empty() {
+        bail!("Requested audio device {index} but no input device was found");
+    }
+    if index >= names.len() {
+        bail!(
+            "Requested audio device {index} out of range, valid indices are 0-{}: {names:?}",
+            names.len() - 1
+        );
+    }
+    Ok(index)
+}
+
+/// Whether `name` contains one of the blacklisted names (case-insensitive).
+fn is_blacklisted(name: &str, blacklist: &[String]) -> bool {
+    let name = name.to_lowercase();
+    blacklist
+        .iter()
+        .any(|blacklisted| name.contains(&blacklisted.to_lowercase()))
+}
+
+/// Device used when none is configured.
+#[derive(Debug, PartialEq)]
+enum Fallback {
+    /// The system default input
+    Default,
+    /// The default is blacklisted, use this device from the list instead
+    Device(usize),
+    /// Every input device is blacklisted
+    Blacklisted,
+}
+
+/// Picks the default input unless blacklisted, otherwise the first input
+/// device which isn't. Monitor sources are only used when configured.
+fn fallback_device(default: Option<&str>, names: &[String], blacklist: &[String]) -> Fallback {
+    let Some(default) = default else {
+        return Fallback::Default;
+    };
+    if !is_blacklisted(default, blacklist) {
+        return Fallback::Default;
+    }
+    names
+        .iter()
+        .position(|name| !is_blacklisted(name, blacklist) && !is_monitor(name))
+        .map_or(Fallback::Blacklisted, Fallback::Device)
+}
+
+/// Output devices which can be captured in loopback, only supported by
+/// WASAPI (Windows).
+#[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+fn loopback_devices(host: &cpal::Host) -> Result<Vec<(String, cpal::Device)>> {
+    #[cfg(target_os = "windows")]
+    {
+        Ok(host
+            .output_devices()?
+            .filter_map(|device| Some((loopback_name(&device.name().ok()?), device)))
+            .collect())
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        warn!(
+            "Capturing output devices in loopback is only supported on Windows, \
+             select a monitor source with `device` instead"
+        );
+        Ok(Vec::new())
+    }
+}
+
+/// A device to capture audio from.
+pub struct InputDevice {
+    pub device: cpal::Device,
+    /// An output device captured in loopback
+    pub loopback: bool,
+}
+
+impl InputDevice {
+    /// Picks the stream configuration: the configured format (or the next
+    /// preferred one) when the device supports it, the device's default
+    /// otherwise.
+    pub fn stream_config(&self, config: &AudioConfig) -> SupportedStreamConfig {
+        let device = &self.device;
+        if self.loopback {
+            // Loopback captures the output mix in its own format
+            match device.default_output_config() {
+                Ok(default_config) => return default_config,
+                Err(err) => warn!("Could not get the loopback config: {err}"),
+            }
+        }
+
+        let supported: Vec<SupportedStreamConfigRange> = match device.supported_input_configs() {
+            Ok(supported) => supported.collect(),
+            Err(err) => {
+                debug!("Could not list the supported configs: {err}");
+                Vec::new()
+            }
+        };
+        let channels = supported_channels(config.channels, &channel_counts(&supported));
+
+        // Try to find a supported configuration that matches what we want
+        let stream_config =
+            if let Some(stream_config) = matching_config(&supported, config, channels) {
+                Some(stream_config)
+            } else {
+                debug!("Could not find supported configs");
+                if let Ok(default_config) = device.default_input_config() {
+                    debug!("Device default config: {:?}", default_config);
+                    Some(default_config)
+                } else {
+                    warn!("Could not default_config");
+                    None
+                }
+            };
+
+        // If we can't find an exact match, use the default config
+        stream_config.unwrap_or_else(|| {
+            warn!("Falling back to config defined configuration, It might not work");
+            SupportedStreamConfig::new(
+                channels,
+                cpal::SampleRate(config.sample_rate),
+                cpal::SupportedBufferSize::Unknown,
+                config.sample_format.into(),
+            )
+        })
+    }
+}
+
+/// The channel counts of the `supported` configurations, sorted.
+fn channel_counts(supported: &[SupportedStreamConfigRange]) -> Vec<u16> {
+    let mut counts: Vec<u16> = supported.iter().map(|range| range.channels()).collect();
+    counts.sort_unstable();
+    counts.dedup();
+    counts
+}
+
+/// The configured channel count when the device supports it, the closest
+/// supported one otherwise. Kept as is when the device doesn't report any.
+fn supported_channels(wanted: u16, counts: &[u16]) -> u16 {
+    if counts.is_empty() || counts.contains(&wanted) {
+        return wanted;
+    }
+    let closest = counts
+        .iter()
+        .copied()
+        .min_by_key(|count| count.abs_diff(wanted))
+        .unwrap_or(wanted);
+    warn!(
+        "The device doesn't support {wanted} channel(s) (supported: {counts:?}), \
+         recording {closest} channel(s) instead, set `channels = {closest}` to silence this"
+    );
+    closest
+}
+
+/// The configured `downmix_weights` when they match the `channels` the
+/// stream records, none otherwise: the device negotiated another channel
+/// count, and its channels are averaged equally.
+pub fn downmix_weights(config: &AudioConfig, channels: u16) -> &[f32] {
+    let weights = &config.downmix_weights;
+    if weights.is_empty() || weights.len() == usize::from(channels) {
+        return weights;
+    }
+    warn!(
+        "Recording {channels} channel(s) instead of `channels = {}`, ignoring the {} \
+         `downmix_weights` and averaging the channels equally",
+        config.channels,
+        weights.len()
+    );
+    &[]
+}
+
+/// The sample formats to try: the configured one, then the preferred ones.
+fn format_preference(config: &AudioConfig) -> Vec<SampleFormat> {
+    let mut formats = vec![config.sample_format];
+    for &format in &config.sample_format_preference {
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    formats
+}
+
+/// The first `supported` configuration with the configured sample rate, in
+/// the first format of [`format_preference`] available, preferably with
+/// `channels`.
+fn matching_config(
+    supported: &[SupportedStreamConfigRange],
+    config: &AudioConfig,
+    channels: u16,
+) -> Option<SupportedStreamConfig> {
+    let sample_rate = cpal::SampleRate(config.sample_rate);
+    for format in format_preference(config) {
+        let matching: Vec<SupportedStreamConfigRange> = supported
+            .iter()
+            .copied()
+            .filter(|range| {
+                range.min_sample_rate() <= sample_rate
+                    && range.max_sample_rate() >= sample_rate
+                    && range.sample_format() == format.into()
+            })
+            .collect();
+        let Some(range) = matching
+            .iter()
+            .find(|range| range.channels() == channels)
+            .or(matching.first())
+        else {
+            continue;
+        };
+        if format != config.sample_format {
+            info!(
+                "The device doesn't support {:?} samples, using {format:?}",
+                config.sample_format
+            );
+        }
+        return Some(range.with_sample_rate(sample_rate));
+    }
+    None
+}
+
+/// The input devices with their names, followed by the output devices
+/// captured in loopback with `loopback`, and how many are inputs.
+fn devices(
+    host: &cpal::Host,
+    config: &AudioConfig,
+) -> Result<(Vec<String>, Vec<cpal::Device>, usize)> {
+    let (mut names, mut devices): (Vec<String>, Vec<cpal::Device>) = host
+        .input_devices()?
+        .filter_map(|device| Some((device.name().ok()?, device)))
+        .unzip();
+    let inputs = names.len();
+    if config.loopback {
+        let (loopback_names, loopback_devices): (Vec<String>, Vec<cpal::Device>) =
+            loopback_devices(host)?.into_iter().unzip();
+        names.extend(loopback_names);
+        devices.extend(loopback_devices);
+    }
+    Ok((names, devices, inputs))
+}
+
+/// Selects the configured input device, or the default one.
+///
+/// Without a configured device, blacklisted defaults are skipped in favor of
+/// the next available input. With `loopback`, output devices can be
+/// configured as well (as `"<name> (loopback)"`).
+pub fn input_device(host: &cpal::Host, config: &AudioConfig) -> Result<InputDevice> {
+    debug!("Available hosts: {:?}", cpal::available_hosts());
+    debug!("Default host: {:?}", host.id());
+
+    let (names, mut devices, inputs) = devices(host, config)?;
+    debug!("Available input devices: {names:?}");
+    let monitors: Vec<&String> = names.iter().filter(|name| is_monitor(name)).collect();
+    if !monitors.is_empty() {
+        debug!("System audio (monitor) sources: {monitors:?}");
+    }
+
+    // Find the requested device or use default
+    let mut loopback = false;
+    let device = if let Some(selected) = &config.device {
+        let index = match selected {
+            Device::Name(device_name) => find_device(&names, device_name).ok_or_else(|| {
+                anyhow!(
+                    "Requested audio device '{}' not found, available: {:?}",
+                    device_name,
+                    names
+                )
+            })?,
+            Device::Index(index) => device_index(*index, &names)?,
+        };
+        loopback = index >= inputs;
+        devices.swap_remove(index)
+    } else {
+        let default = host.default_input_device();
+        let default_name = default.as_ref().and_then(|device| device.name().ok());
+        match fallback_device(default_name.as_deref(), &names, &config.device_blacklist) {
+            Fallback::Default => default.ok_or_else(|| anyhow!("No default input device found"))?,
+            Fallback::Device(index) => {
+                info!(
+                    "Default input device {:?} is blacklisted, using {}",
+                    default_name, names[index]
+                );
+                devices.swap_remove(index)
+            }
+            Fallback::Blacklisted => {
+                return Err(anyhow!(
+                    "Every input device is blacklisted, available: {:?}",
+                    names
+                ));
+            }
+        }
+    };
+
+    if loopback {
+        info!("Using output device in loopback: {}", device.name()?);
+    } else {
+        info!("Using input device: {}", device.name()?);
+    }
+    Ok(InputDevice { device, loopback })
+}
+
+/// Describes a device of [`list_devices`]: its name, marked when it is the
+/// default input or a monitor source, then one line per supported format.
+fn device_entry(
+    index: usize,
+    name: &str,
+    default: bool,
+    supported: &[SupportedStreamConfigRange],
+) -> String {
+    let mut title = format!("{index}: {name}");
+    if default {
+        title.push_str(" (default)");
+    }
+    if is_monitor(name) {
+        title.push_str(" (monitor)");
+    }
+    let mut lines = vec![title];
+    if supported.is_empty() {
+        lines.push("    no supported formats reported".to_string());
+    }
+    for range in supported {
+        let (min, max) = (range.min_sample_rate().0, range.max_sample_rate().0);
+        let rates = if min == max {
+            format!("{min}Hz")
+        } else {
+            format!("{min}-{max}Hz")
+        };
+        lines.push(format!(
+            "    {} channel(s), {rates}, {}",
+            range.channels(),
+            range.sample_format()
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Lists the devices `device` can select (output devices captured in
+/// loopback too with `loopback`), with their supported formats and which
+/// one is the default input. Nothing is opened.
+pub fn list_devices(config: &AudioConfig) -> Result<String> {
+    let host = cpal::default_host();
+    let (names, devices, inputs) = devices(&host, config)?;
+    if names.is_empty() {
+        bail!("No input device found on the {:?} host", host.id());
+    }
+    let default = host
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+    let entries: Vec<String> = names
+        .iter()
+        .zip(&devices)
+        .enumerate()
+        .map(|(index, (name, device))| {
+            let supported: Vec<SupportedStreamConfigRange> = if index < inputs {
+                device
+                    .supported_input_configs()
+                    .map(Iterator::collect)
+                    .unwrap_or_default()
+            } else {
+                device
+                    .supported_output_configs()
+                    .map(Iterator::collect)
+                    .unwrap_or_default()
+            };
+            let is_default = index < inputs && default.as_deref() == Some(name.as_str());
+            device_entry(index, name, is_default, &supported)
+        })
+        .collect();
+    Ok(entries.join("\n"))
+}
+
+/// How long [`describe_stream`] captures from the device.
+const PROBE_DURATION: Duration = Duration::from_millis(500);
+
+/// Describes the negotiated `stream` next to the configured format, and the
+/// conversion to the model's 16kHz mono.
+fn stream_summary(name: &str, stream: &SupportedStreamConfig, config: &AudioConfig) -> String {
+    let rate = stream.sample_rate().0;
+    let channels = stream.channels();
+    let buffer = match stream.buffer_size() {
+        SupportedBufferSize::Range { min, max } => format!("{min}-{max} frames"),
+        SupportedBufferSize::Unknown => "unknown".to_string(),
+    };
+    let resampled = rate.abs_diff(MODEL_SAMPLE_RATE) > config.sample_rate_tolerance;
+    let conversion = match (resampled, channels > 1) {
+        (false, false) => "none".to_string(),
+        (true, false) => format!("resampled {rate}Hz -> {MODEL_SAMPLE_RATE}Hz"),
+        (false, true) => format!("downmixed {channels} -> 1 channel"),
+        (true, true) => {
+            format!(
+                "resampled {rate}Hz -> {MODEL_SAMPLE_RATE}Hz, downmixed {channels} -> 1 channel"
+            )
+        }
+    };
+    [
+        format!("device {name:?}"),
+        format!(
+            "stream {rate}Hz, {channels} channel(s), {}, buffer {buffer}",
+            stream.sample_format()
+        ),
+        format!(
+            "configured {}Hz, {} channel(s), {:?}",
+            config.sample_rate, config.channels, config.sample_format
+        ),
+        format!("conversion {conversion}"),
+    ]
+    .join("\n    ")
+}
+
+/// Builds the input stream of `stream_config`, handing `on_data` the samples
+/// as f32 whatever the captured format. Only F32 and I16 streams, the formats
+/// of [`SampleFormat`], are supported.
+pub(super) fn build_input_stream(
+    device: &cpal::Device,
+    stream_config: &SupportedStreamConfig,
+    mut on_data: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream> {
+    let config = stream_config.config();
+    let stream = match stream_config.sample_format() {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &_| on_data(data),
+            on_error,
+            None,
+        ),
+        cpal::SampleFormat::I16 => {
+            let mut converted = Vec::new();
+            device.build_input_stream(
+                &config,
+                move |data: &[i16], _: &_| {
+                    converted.clear();
+                    converted.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+                    on_data(&converted);
+                },
+                on_error,
+                None,
+            )
+        }
+        format => bail!("Unsupported sample format {format}, only f32 and i16 can be captured"),
+    };
+    stream.context("Failed to create audio stream")
+}
+
+/// Opens the stream the recorders would use for `config` and captures from
+/// it briefly, describing what was negotiated and received. Nothing is
+/// transcribed.
+pub fn describe_stream(config: &AudioConfig) -> Result<String> {
+    check_microphone()?;
+    let host = cpal::default_host();
+    let input = input_device(&host, config)?;
+    let stream_config = input.stream_config(config);
+    let name = input.device.name()?;
+    let summary = stream_summary(&name, &stream_config, config);
+
+    let channels = stream_config.channels() as usize;
+    let frames = Arc::new(AtomicUsize::new(0));
+    // Bits of the loudest sample, ordered like the (non-negative) values
+    let peak = Arc::new(AtomicU32::new(0));
+    let stream = {
+        let frames = frames.clone();
+        let peak = peak.clone();
+        build_input_stream(
+            &input.device,
+            &stream_config,
+            move |data| {
+                frames.fetch_add(data.len() / channels, Ordering::Relaxed);
+                let loudest = data.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+                peak.fetch_max(loudest.to_bits(), Ordering::Relaxed);
+            },
+            |err| warn!("Audio stream error: {err}"),
+        )
+        .context(summary.clone())?
+    };
+    stream.play().context("Cannot start the stream")?;
+    std::thread::sleep(PROBE_DURATION);
+    drop(stream);
+
+    let frames = frames.load(Ordering::Relaxed);
+    if frames == 0 {
+        bail!("{summary}\n    the stream delivered no audio in {PROBE_DURATION:?}");
+    }
+    let peak = f32::from_bits(peak.load(Ordering::Relaxed));
+    let silent = if peak == 0.0 {
+        " (digital silence)"
+    } else {
+        ""
+    };
+    Ok(format!(
+        "{summary}\n    captured {frames} frames in {PROBE_DURATION:?}, peak {peak:.3}{silent}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        [
+            "default",
+            "sysdefault:CARD=PCH",
+            "sysdefault:CARD=C920",
+            "front:CARD=C920,DEV=0",
+            "C920",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    #[test]
+    fn test_exact_match_first() {
+        assert_eq!(find_device(&names(), "C920"), Some(4));
+        assert_eq!(find_device(&names(), "default"), Some(0));
+    }
+
+    #[test]
+    fn test_substring_match() {
+        assert_eq!(find_device(&names(), "CARD=PCH"), Some(1));
+        // Several matches, the first one wins
+        assert_eq!(find_device(&names(), "CARD=C920"), Some(2));
+    }
+
+    #[test]
+    fn test_case_insensitive_match() {
+        assert_eq!(find_device(&names(), "card=pch"), Some(1));
+        assert_eq!(find_device(&names()[..4], "c920"), Some(2));
+    }
+
+    #[test]
+    fn test_blacklisted_default_skipped() {
+        let names = vec![
+            "HDMI Capture".to_string(),
+            "sysdefault:CARD=PCH".to_string(),
+        ];
+        let blacklist = vec!["hdmi".to_string()];
+        assert_eq!(
+            fallback_device(Some("HDMI Capture"), &names, &blacklist),
+            Fallback::Device(1)
+        );
+        assert_eq!(
+            fallback_device(Some("sysdefault:CARD=PCH"), &names, &blacklist),
+            Fallback::Default
+        );
+        assert_eq!(
+            fallback_device(Some("HDMI Capture"), &names, &[]),
+            Fallback::Default
+        );
+        assert_eq!(
+            fallback_device(Some("HDMI Capture"), &names[..1], &blacklist),
+            Fallback::Blacklisted
+        );
+    }
+
+    #[test]
+    fn test_monitor_sources() {
+        let names: Vec<String> = [
+            "default",
+            "Monitor of Built-in Audio Analog Stereo",
+            "Built-in Audio Analog Stereo",
+            &loopback_name("Speakers"),
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        // Selectable through `device`
+        assert_eq!(find_device(&names, "monitor"), Some(1));
+        assert_eq!(find_device(&names, "Speakers"), Some(3));
+        assert_eq!(find_device(&names, "Speakers (loopback)"), Some(3));
+        assert!(is_monitor(&names[1]) && is_monitor(&names[3]));
+        assert!(!is_monitor(&names[2]));
+        assert!(is_monitor("Stereo Mix (Realtek Audio)"));
+        // Never picked implicitly
+        let blacklist = vec!["default".to_string()];
+        assert_eq!(
+            fallback_device(Some("default"), &names, &blacklist),
+            Fallback::Device(2)
+        );
+    }
+
+    #[test]
+    fn test_stream_summary() {
+        let stream = SupportedStreamConfig::new(
+            2,
+            cpal::SampleRate(44100),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            cpal::SampleFormat::F32,
+        );
+        let summary = stream_summary("C920", &stream, &AudioConfig::default());
+        assert_eq!(
+            summary,
+            "device \"C920\"\n    \
+             stream 44100Hz, 2 channel(s), f32, buffer 64-4096 frames\n    \
+             configured 16000Hz, 1 channel(s), F32\n    \
+             conversion resampled 44100Hz -> 16000Hz, downmixed 2 -> 1 channel"
+        );
+
+        let stream = SupportedStreamConfig::new(
+            1,
+            cpal::SampleRate(16000),
+            SupportedBufferSize::Unknown,
+            cpal::SampleFormat::F32,
+        );
+        let summary = stream_summary("C920", &stream, &AudioConfig::default());
+        assert!(summary.ends_with(
+            "buffer unknown\n    configured 16000Hz, 1 channel(s), F32\n    conversion none"
+        ));
+    }
+
+    #[test]
+    fn test_device_index() -> Result<()> {
+        let names = names();
+        assert_eq!(device_index(0, &names)?, 0);
+        assert_eq!(device_index(4, &names)?, 4);
+        let err = device_index(5, &names).map_err(|err| err.to_string());
+        assert!(err.is_err_and(|err| err.contains("0-4")));
+        assert!(device_index(0, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_entry() {
+        let supported = [
+            range(2, 8000, 48000),
+            range_of(cpal::SampleFormat::I16, 1, 44100, 44100),
+        ];
+        assert_eq!(
+            device_entry(0, "sysdefault:CARD=C920", true, &supported),
+            "0: sysdefault:CARD=C920 (default)\n    \
+             2 channel(s), 8000-48000Hz, f32\n    \
+             1 channel(s), 44100Hz, i16"
+        );
+        assert_eq!(
+            device_entry(3, "Monitor of Speakers", false, &[]),
+            "3: Monitor of Speakers (monitor)\n    no supported formats reported"
+        );
+    }
+
+    fn range(channels: u16, min: u32, max: u32) -> SupportedStreamConfigRange {
+        range_of(cpal::SampleFormat::F32, channels, min, max)
+    }
+
+    fn range_of(
+        format: cpal::SampleFormat,
+        channels: u16,
+        min: u32,
+        max: u32,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            cpal::SampleRate(min),
+            cpal::SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn test_format_preference() {
+        let i16_only = [range_of(cpal::SampleFormat::I16, 1, 8000, 48000)];
+        let config = AudioConfig::default();
+        let stream = matching_config(&i16_only, &config, 1);
+        assert_eq!(
+            stream.map(|stream| stream.sample_format()),
+            Some(cpal::SampleFormat::I16)
+        );
+        // The configured format comes first, duplicates are tried once
+        let config = AudioConfig {
+            sample_format: SampleFormat::I16,
+            ..AudioConfig::default()
+        };
+        assert_eq!(
+            format_preference(&config),
+            [SampleFormat::I16, SampleFormat::F32]
+        );
+        let both = [
+            range(1, 8000, 48000),
+            range_of(cpal::SampleFormat::I16, 1, 8000, 48000),
+        ];
+        let stream = matching_config(&both, &config, 1);
+        assert_eq!(
+            stream.map(|stream| stream.sample_format()),
+            Some(cpal::SampleFormat::I16)
+        );
+        // Without I16 in the preference, the device default is left to decide
+        let config = AudioConfig {
+            sample_format_preference: vec![SampleFormat::F32],
+            ..AudioConfig::default()
+        };
+        assert!(matching_config(&i16_only, &config, 1).is_none());
+    }
+
+    #[test]
+    fn test_stereo_request_on_mono_device() {
+        let mono = [range(1, 8000, 48000)];
+        let config = AudioConfig {
+            channels: 2,
+            ..AudioConfig::default()
+        };
+        assert_eq!(channel_counts(&mono), [1]);
+        let channels = supported_channels(config.channels, &channel_counts(&mono));
+        assert_eq!(channels, 1);
+        let stream = matching_config(&mono, &config, channels);
+        assert_eq!(stream.map(|stream| stream.channels()), Some(1));
+
+        // Supported counts are kept, unknown ones too
+        assert_eq!(supported_channels(2, &[1, 2]), 2);
+        assert_eq!(supported_channels(2, &[]), 2);
+        assert_eq!(supported_channels(3, &[1, 4]), 4);
+    }
+
+    #[test]
+    fn test_matching_config_prefers_channels() {
+        let supported = [
+            range(2, 44100, 48000),
+            range(4, 8000, 48000),
+            range(1, 8000, 48000),
+        ];
+        let config = AudioConfig::default();
+        let stream = matching_config(&supported, &config, 1);
+        assert_eq!(stream.map(|stream| stream.channels()), Some(1));
+        let stream = matching_config(&supported, &config, 2);
+        // The stereo range can't record at 16kHz
+        assert_eq!(stream.map(|stream| stream.channels()), Some(4));
+        let config = AudioConfig {
+            sample_rate: 96000,
+            ..AudioConfig::default()
+        };
+        assert!(matching_config(&supported, &config, 1).is_none());
+    }
+
+    #[test]
+    fn test_downmix_weights() {
+        let config = AudioConfig {
+            channels: 2,
+            downmix_weights: vec![0.7, 0.3],
+            ..AudioConfig::default()
+        };
+        assert_eq!(downmix_weights(&config, 2), &[0.7, 0.3]);
+        // The device negotiated another count, averaged equally
+        assert!(downmix_weights(&config, 4).is_empty());
+        assert!(downmix_weights(&AudioConfig::default(), 4).is_empty());
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert_eq!(find_device(&names(), "USB"), None);
+    }
+}
@@ -1,15 +1,28 @@
-use std::path::PathBuf;
-
 use anyhow::Result;
 use log::info;
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::config::{Config, Trigger};
 
+mod clipping;
+mod denoise;
+mod device;
+mod permission;
 mod push_to_talk;
-mod resample;
+pub(crate) mod resample;
 mod vad;
 
+pub use device::{describe_stream, list_devices};
+
+/// Why a recorder stopped on its own, see [`AudioRecorder::stopped`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stopped {
+    /// The recording reached `max_recording_secs`, its audio was sent
+    Capped,
+    /// The `arm_once` utterance is done
+    Disarmed,
+}
+
 pub enum AudioRecorder {
     Push(push_to_talk::AudioRecorder),
     Vad(vad::AudioRecorder),
@@ -18,22 +31,42 @@ pub enum AudioRecorder {
 #[derive(Debug)]
 pub enum Audio {
     Warm,
-    Path(PathBuf),
+    /// Re-transcribe the last audio with the re-run model
+    Rerun,
     Sample(Vec<f32>),
+    /// Part of a recording still going on, transcribed without autosend
+    Segment(Vec<f32>),
 }
 
 impl AudioRecorder {
     pub async fn new(config: &Config, tx_audio: UnboundedSender<Audio>) -> Result<Self> {
         info!("Using trigger {:?}", config.activation.trigger);
         match config.activation.trigger {
-            Trigger::PushToTalk | Trigger::Toggle => Ok(Self::Push(
-                push_to_talk::AudioRecorder::new(config, tx_audio)?,
+            Trigger::PushToTalk {
+                streaming,
+                pre_buffer_duration,
+                ..
+            } => Ok(Self::Push(
+                push_to_talk::AudioRecorder::new(config, streaming, pre_buffer_duration, tx_audio)
+                    .await?,
+            )),
+            Trigger::Toggle => Ok(Self::Push(
+                push_to_talk::AudioRecorder::new(config, false, 0.0, tx_audio).await?,
             )),
             Trigger::ToggleVad {
                 threshold,
                 silence_duration,
                 speech_duration,
                 pre_buffer_duration,
+                arm_once,
+                coast_prob,
+                silent_skip_factor,
+                warmup_duration,
+                vad_model,
+                energy_threshold,
+                max_utterance_secs,
+                max_chunk_secs,
+                ..
             } => Ok(Self::Vad(
                 vad::AudioRecorder::new(
                     config,
@@ -41,6 +74,14 @@ impl AudioRecorder {
                     silence_duration,
                     speech_duration,
                     pre_buffer_duration,
+                    arm_once,
+                    coast_prob,
+                    silent_skip_factor,
+                    warmup_duration,
+                    vad_model,
+                    energy_threshold,
+                    max_utterance_secs,
+                    max_chunk_secs,
                     tx_audio,
                 )
                 .await?,
@@ -60,4 +101,29 @@ impl AudioRecorder {
             Self::Vad(p) => p.stop_recording(),
         }
     }
+
+    /// Stops recording like [`AudioRecorder::stop_recording`], discarding the
+    /// audio instead of sending it.
+    pub fn cancel_recording(&mut self) -> Result<()> {
+        match self {
+            Self::Push(p) => p.cancel_recording(),
+            Self::Vad(p) => p.cancel_recording(),
+        }
+    }
+
+    /// Resolves when the recorder stopped on its own: a push-to-talk
+    /// recording reached `max_recording_secs`, or an `arm_once` VAD recorder
+    /// disarmed itself after an utterance.
+    pub async fn stopped(&mut self) -> Stopped {
+        match self {
+            Self::Push(p) => {
+                p.capped().await;
+                Stopped::Capped
+            }
+            Self::Vad(p) => {
+                p.disarmed().await;
+                Stopped::Disarmed
+            }
+        }
+    }
 }
@@ -0,0 +1,50 @@
+//! Microphone permission check.
+//!
+//! On macOS, a stream opened without the microphone permission silently
+//! delivers silence, the recorders check it up front to report a clear error.
+
+use anyhow::Result;
+
+/// Fails when the microphone access was denied, a no-op outside of macOS.
+///
+/// An undetermined permission is fine: macOS asks the user when the stream
+/// opens, failing would never show that prompt.
+#[cfg(target_os = "macos")]
+pub fn check_microphone() -> Result<()> {
+    use anyhow::{anyhow, bail};
+    use log::{debug, info, warn};
+    use objc2_av_foundation::{AVAuthorizationStatus, AVCaptureDevice, AVMediaTypeAudio};
+
+    // SAFETY: a constant string defined by AVFoundation
+    let media_type =
+        unsafe { AVMediaTypeAudio }.ok_or(anyhow!("AVFoundation has no audio media type"))?;
+    // SAFETY: audio is one of the two media types accepted
+    let status = unsafe { AVCaptureDevice::authorizationStatusForMediaType(media_type) };
+    match status {
+        AVAuthorizationStatus::Authorized => {
+            debug!("Microphone access granted");
+            Ok(())
+        }
+        AVAuthorizationStatus::NotDetermined => {
+            info!("macOS will ask for microphone access when recording starts");
+            Ok(())
+        }
+        AVAuthorizationStatus::Denied => bail!(
+            "Microphone access denied, allow your terminal (or whispering) in \
+             System Settings > Privacy & Security > Microphone, then restart it"
+        ),
+        AVAuthorizationStatus::Restricted => bail!(
+            "Microphone access is restricted on this Mac (parental controls or \
+             device management), audio can't be recorded"
+        ),
+        status => {
+            warn!("Unknown microphone authorization status {status:?}");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn check_microphone() -> Result<()> {
+    Ok(())
+}
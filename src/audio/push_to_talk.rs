@@ -3,46 +3,189 @@
 //! This module provides functionality for recording audio from the default input device
 //! and saving it to a WAV file. It handles device initialization, stream configuration,
 //! and audio data processing.
+//!
+//! The WAV file keeps the audio as captured (in the configured format when the
+//! device supports it), Whisper gets the 16kHz mono samples recorded alongside.
+//!
+//! In streaming mode the samples are cut at the pauses while recording, each
+//! part sent as an [`Audio::Segment`] before the keys are released.
+//!
+//! With a pre-roll the stream keeps running between recordings, the last
+//! moments before the keys are pressed being prepended to the next recording.
 
 use anyhow::{Context, Result, anyhow};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{FromSample, Sample, SupportedStreamConfig};
+use cpal::Sample;
+use cpal::traits::StreamTrait;
 use hound::{WavSpec, WavWriter};
-use log::{debug, error, info, warn};
-use rubato::{FftFixedInOut, Resampler};
-use std::collections::HashSet;
+use log::{debug, error, warn};
+use ringbuf::HeapRb;
+use ringbuf::traits::Consumer;
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
 use crate::audio::resample::Resample;
 use crate::config::{AudioConfig, Config};
 
 use super::Audio;
-use super::resample::audio_resample;
+use super::clipping::ClippingDetector;
+use super::denoise::NoiseSuppression;
+use super::device::{build_input_stream, downmix_weights, input_device};
+use super::permission::check_microphone;
+use super::resample::MODEL_SAMPLE_RATE;
+use super::vad::{Segmenter, push_dropping_oldest};
+
+type WavFileWriter = WavWriter<BufWriter<File>>;
+type RecordingHandle = Arc<Mutex<Option<Recording>>>;
+
+/// An active recording.
+struct Recording {
+    /// The audio as captured
+    wav: WavFileWriter,
+    /// The 16kHz mono audio transcribed
+    samples: Vec<f32>,
+    /// Samples already sent as segments
+    sent: usize,
+    /// Speech was heard after the last segment
+    pending_speech: bool,
+}
+
+impl Recording {
+    fn write(&mut self, captured: &[f32], samples: &[f32]) {
+        match self.wav.spec().sample_format {
+            hound::SampleFormat::Float => {
+                for &sample in captured {
+                    self.wav.write_sample(sample).ok();
+                }
+            }
+            hound::SampleFormat::Int => {
+                for &sample in captured {
+                    self.wav.write_sample(sample.to_sample::<i16>()).ok();
+                }
+            }
+        }
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// Finalizes the WAV file, returns the samples not sent as segments yet:
+    /// everything when not streaming, and nothing when only silence followed
+    /// the last segment.
+    fn end(mut self) -> Result<Vec<f32>> {
+        self.wav.finalize()?;
+        if self.sent == 0 {
+            return Ok(self.samples);
+        }
+        if !self.pending_speech {
+            return Ok(Vec::new());
+        }
+        Ok(self.samples.split_off(self.sent))
+    }
+}
+
+/// The audio captured while not recording, prepended to the next recording.
+struct PreRoll {
+    /// The audio as captured
+    captured: HeapRb<f32>,
+    /// The 16kHz mono audio
+    samples: HeapRb<f32>,
+}
+
+impl PreRoll {
+    /// Keeps `duration` seconds of a stream of `channels` at `sample_rate`,
+    /// returns `None` when that's nothing.
+    fn new(duration: f32, channels: u16, sample_rate: u32) -> Option<Self> {
+        let frames = (duration * sample_rate as f32) as usize;
+        let samples = (duration * 16000.0) as usize;
+        (frames > 0 && samples > 0).then(|| Self {
+            captured: HeapRb::new(frames * usize::from(channels)),
+            samples: HeapRb::new(samples),
+        })
+    }
+
+    fn push(&mut self, captured: &[f32], samples: &[f32]) {
+        // Callbacks hold whole frames, dropping some keeps the channels aligned
+        push_dropping_oldest(&mut self.captured, captured);
+        push_dropping_oldest(&mut self.samples, samples);
+    }
+
+    /// Moves the kept audio to `recording`.
+    fn drain_into(&mut self, recording: &mut Recording) {
+        let captured: Vec<f32> = self.captured.pop_iter().collect();
+        let samples: Vec<f32> = self.samples.pop_iter().collect();
+        recording.write(&captured, &samples);
+    }
+}
+
+/// Starts a recording with a new writer unless one is active, returns
+/// whether it did.
+fn begin(
+    recording: &RecordingHandle,
+    create: impl FnOnce() -> Result<WavFileWriter>,
+) -> Result<bool> {
+    let mut guard = recording
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock writer: {}", e))?;
+    if guard.is_some() {
+        return Ok(false);
+    }
+    *guard = Some(Recording {
+        wav: create()?,
+        samples: Vec::new(),
+        sent: 0,
+        pending_speech: false,
+    });
+    Ok(true)
+}
+
+/// The number of 16kHz samples in `secs` seconds.
+fn max_samples(secs: f32) -> usize {
+    (secs * MODEL_SAMPLE_RATE as f32) as usize
+}
 
-type WavWriterHandle = Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>;
+/// Takes the recording once it holds `max_samples` 16kHz samples, `None`
+/// while shorter or not recording. Finalizing it is left to the caller, off
+/// the audio callback.
+fn end_capped(recording: &RecordingHandle, max_samples: usize) -> Option<Recording> {
+    let mut guard = recording.try_lock().ok()?;
+    if guard.as_ref()?.samples.len() < max_samples {
+        return None;
+    }
+    guard.take()
+}
+
+/// Takes the active recording, `None` when not recording.
+fn finish(recording: &RecordingHandle) -> Result<Option<Recording>> {
+    Ok(recording
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock writer: {}", e))?
+        .take())
+}
 
 /// Handles audio recording functionality.
 ///
 /// This struct manages the audio recording process, including device initialization,
 /// stream configuration, and writing audio data to a WAV file.
 pub struct AudioRecorder {
-    writer: WavWriterHandle,
+    recording: RecordingHandle,
     stream: cpal::Stream,
     recording_path: PathBuf,
-    config: AudioConfig,
+    wav_spec: WavSpec,
     tx_audio: UnboundedSender<Audio>,
+    /// The stream runs between recordings, filling the pre-roll
+    keep_running: bool,
+    /// Recordings the stream ended at `max_recording_secs`, to finalize
+    rx_capped: UnboundedReceiver<Recording>,
 }
 
 impl AudioRecorder {
-    /// Creates a new WAV specification for recording.
-    fn create_wav_spec(config: &AudioConfig) -> WavSpec {
+    /// Creates a new WAV specification for recording a stream of `channels`
+    /// at `sample_rate`.
+    fn create_wav_spec(config: &AudioConfig, channels: u16, sample_rate: u32) -> WavSpec {
         WavSpec {
-            channels: config.channels,
-            sample_rate: config.sample_rate,
+            channels,
+            sample_rate,
             bits_per_sample: config.sample_format.bits_per_sample(),
             sample_format: match config.sample_format {
                 crate::config::SampleFormat::F32 => hound::SampleFormat::Float,
@@ -54,226 +197,456 @@ impl AudioRecorder {
     /// Creates a new AudioRecorder instance.
     ///
     /// This function initializes the default audio input device, configures it
-    /// for recording, and sets up the WAV file writer.
-    pub fn new(config: &Config, tx_audio: UnboundedSender<Audio>) -> Result<Self> {
+    /// for recording, and sets up the WAV file writer. `streaming` loads the
+    /// VAD model to send the segments while recording. The last
+    /// `pre_buffer_duration` seconds before a recording starts are prepended
+    /// to it.
+    pub async fn new(
+        config: &Config,
+        streaming: bool,
+        pre_buffer_duration: f32,
+        tx_audio: UnboundedSender<Audio>,
+    ) -> Result<Self> {
+        check_microphone()?;
         let host = cpal::default_host();
-        debug!("Available hosts: {:?}", cpal::available_hosts());
-        debug!("Default host: {:?}", host.id());
-
-        let devices = host.input_devices()?;
-        let names: HashSet<_> = devices.into_iter().flat_map(|d| d.name()).collect();
-        debug!("Available input devices: {names:?}");
-
-        let mut devices = host.input_devices()?;
-        // Find the requested device or use default
-        let device = if let Some(device_name) = &config.audio.device {
-            devices
-                .find(|d| {
-                    if let Ok(name) = d.name() {
-                        name == *device_name
-                    } else {
-                        false
-                    }
-                })
-                .ok_or_else(|| {
-                    anyhow!(
-                        "Requested audio device '{}' not found, available: {:?}",
-                        device_name,
-                        names
-                    )
-                })?
-        } else {
-            host.default_input_device()
-                .ok_or_else(|| anyhow!("No default input device found"))?
-        };
-
-        info!("Using input device: {}", device.name()?);
-
-        // Try to find a supported configuration that matches what we want
-        let stream_config = if let Ok(supported_configs) = device.supported_input_configs() {
-            let mut stream_config = None;
-
-            for config_range in supported_configs {
-                let sample_rate = cpal::SampleRate(config.audio.sample_rate);
-                if config_range.min_sample_rate() <= sample_rate
-                    && config_range.max_sample_rate() >= sample_rate
-                    && config_range.sample_format() == config.audio.sample_format.into()
-                {
-                    stream_config = Some(config_range.with_sample_rate(sample_rate));
-                    break;
-                }
-            }
-            stream_config
-        } else {
-            None
-        };
-        let stream_config = if let Some(stream_config) = stream_config {
-            Some(stream_config)
-        } else {
-            debug!("Could not find supported configs");
-            if let Ok(default_config) = device.default_input_config() {
-                debug!("Device default config: {:?}", default_config);
-                Some(default_config)
-            } else {
-                warn!("Could not default_config");
-                None
-            }
-        };
-
-        // If we can't find an exact match, use the default config
-        let stream_config = stream_config.unwrap_or_else(|| {
-            warn!("Falling back to config defined configuration, It might not work");
-            SupportedStreamConfig::new(
-                config.audio.channels,
-                cpal::SampleRate(config.audio.sample_rate),
-                cpal::SupportedBufferSize::Unknown,
-                config.audio.sample_format.into(),
-            )
-        });
+        let input = input_device(&host, &config.audio)?;
 
+        let stream_config = input.stream_config(&config.audio);
+        let device = input.device;
         debug!("Using stream config: {:?}", stream_config);
+        if stream_config.sample_rate().0 != config.audio.sample_rate
+            || stream_config.channels() != config.audio.channels
+        {
+            warn!(
+                "The device doesn't support {} channel(s) at {}Hz, saving the recording as captured",
+                config.audio.channels, config.audio.sample_rate
+            );
+        }
+        let wav_spec = Self::create_wav_spec(
+            &config.audio,
+            stream_config.channels(),
+            stream_config.sample_rate().0,
+        );
 
         // Create cache directory if it doesn't exist
         std::fs::create_dir_all(&config.paths.cache_dir).context("Creating cache directory")?;
+        config.paths.prepare_recording_path()?;
 
-        // Create WAV writer
-        let writer = WavWriter::create(
-            &config.paths.recording_path,
-            Self::create_wav_spec(&config.audio),
-        )
-        .context("Wav writer failed")?;
-        let writer = Arc::new(Mutex::new(Some(writer)));
-        let writer2 = writer.clone();
+        // The WAV writer is created when recording starts
+        let recording = Arc::new(Mutex::new(None));
+        let recording2 = recording.clone();
         let err_fn = move |err| {
             error!("Audio stream error: {}", err);
         };
 
-        // Create resampler if needed
-        let resampler = if stream_config.sample_rate().0 != config.audio.sample_rate
-            || stream_config.channels() != config.audio.channels
-            || stream_config.sample_format() != cpal::SampleFormat::F32
-        {
-            if stream_config.sample_format() != cpal::SampleFormat::F32 {
-                todo!("Unimplemented resampling samples");
-            }
-            Some(Resample {
-                samplerate_in: stream_config.sample_rate().0,
-                samplerate_out: 16000,
-                in_channels: stream_config.channels(),
-            })
+        // Whisper needs 16kHz mono, whatever the configured format
+        let mut resampler = Resample::for_model(
+            stream_config.sample_rate().0,
+            stream_config.channels(),
+            downmix_weights(&config.audio, stream_config.channels()),
+            config.audio.downmix_mode,
+            config.audio.sample_rate_tolerance,
+        )?;
+        let mut noise = NoiseSuppression::new(config);
+        let mut clipping = ClippingDetector::new(config);
+        let mut segmenter = if streaming {
+            Some(Segmenter::new().await?)
         } else {
             None
         };
+        let mut pre_roll = PreRoll::new(
+            pre_buffer_duration,
+            stream_config.channels(),
+            stream_config.sample_rate().0,
+        );
+        let keep_running = pre_roll.is_some();
+        let tx_segments = tx_audio.clone();
+        let max_samples = config.activation.max_recording_secs.map(max_samples);
+        let (tx_capped, rx_capped) = unbounded_channel();
+        let stream = build_input_stream(
+            &device,
+            &stream_config,
+            move |data| {
+                if let Some(clipping) = clipping.as_mut() {
+                    clipping.process(data);
+                }
+                Self::write_input_data(
+                    data,
+                    &recording2,
+                    &mut resampler,
+                    &mut noise,
+                    &mut segmenter,
+                    &mut pre_roll,
+                    &tx_segments,
+                );
+                if let Some(capped) = max_samples.and_then(|max| end_capped(&recording2, max)) {
+                    let _ = tx_capped.send(capped);
+                }
+            },
+            err_fn,
+        )?;
 
-        let stream = device
-            .build_input_stream(
-                &stream_config.into(),
-                move |data, _: &_| {
-                    Self::write_input_data_sample::<f32, f32>(data, &writer2, resampler);
-                },
-                err_fn,
-                None,
-            )
-            .context("Failed to create audio stream")?;
-
-        stream.pause().context("Cannot pause")?;
+        if keep_running {
+            stream.play().context("Cannot start the pre-roll")?;
+        } else {
+            stream.pause().context("Cannot pause")?;
+        }
 
         Ok(Self {
-            writer,
+            recording,
             stream,
             tx_audio,
             recording_path: config.paths.recording_path.clone(),
-            config: config.audio.clone(),
+            wav_spec,
+            keep_running,
+            rx_capped,
         })
     }
 
+    /// Resolves when a recording reached `max_recording_secs`, once the
+    /// recording the stream ended is finalized and its audio sent. Stopping
+    /// it afterwards is a no-op.
+    pub async fn capped(&mut self) {
+        let Some(recording) = self.rx_capped.recv().await else {
+            return std::future::pending().await;
+        };
+        warn!("Reached max_recording_secs, stopped recording");
+        match recording.end() {
+            Ok(samples) => {
+                if self.tx_audio.send(Audio::Sample(samples)).is_err() {
+                    warn!("Transcription stopped, dropping the recording");
+                }
+            }
+            Err(err) => error!("Could not end the recording: {err:#}"),
+        }
+    }
+
     /// Starts the audio recording.
     ///
     /// This function begins capturing audio from the input device and writing
     /// it to the WAV file.
     pub fn start_recording(&self) -> Result<()> {
-        let writer = WavWriter::create(&self.recording_path, Self::create_wav_spec(&self.config))?;
-        *self
-            .writer
-            .lock()
-            .map_err(|e| anyhow!("Failed to lock writer: {}", e))? = Some(writer);
+        let started = begin(&self.recording, || {
+            WavWriter::create(&self.recording_path, self.wav_spec).context("Wav writer failed")
+        })?;
+        if !started {
+            // Duplicate OS events can start twice, keep the current recording.
+            debug!("Already recording, ignoring start");
+            return Ok(());
+        }
         self.stream.play()?;
         self.tx_audio.send(Audio::Warm)?;
         Ok(())
     }
 
-    /// Stops the audio recording and returns the path to the recorded file.
+    /// Stops the audio recording and sends the recorded samples.
     ///
-    /// This function stops the audio stream, finalizes the WAV file, and returns
-    /// the path to the recorded audio file.
+    /// This function stops the audio stream, finalizes the WAV file, and sends
+    /// the 16kHz mono samples to transcribe (the ones not sent as segments
+    /// when streaming). Stopping when not recording is a no-op.
     pub fn stop_recording(&self) -> Result<()> {
-        self.stream.pause()?;
-        let writer = self
-            .writer
-            .lock()
-            .map_err(|e| anyhow!("Failed to lock writer: {}", e))?
-            .take()
-            .ok_or_else(|| anyhow!("Writer is missing"))?;
-        writer.finalize()?;
-        let wav_path = self.recording_path.clone();
-        self.tx_audio.send(Audio::Path(wav_path))?;
+        let Some(samples) = self.end_recording()? else {
+            // Duplicate key releases can stop twice.
+            debug!("Not recording, ignoring stop");
+            return Ok(());
+        };
+        self.tx_audio.send(Audio::Sample(samples))?;
+        Ok(())
+    }
+
+    /// Stops the audio recording, discarding it.
+    ///
+    /// The WAV file is finalized as usual, but nothing is transcribed.
+    /// Cancelling when not recording is a no-op.
+    pub fn cancel_recording(&self) -> Result<()> {
+        let Some(samples) = self.end_recording()? else {
+            debug!("Not recording, ignoring cancel");
+            return Ok(());
+        };
+        debug!("Discarded {:.1}s of audio", samples.len() as f32 / 16000.0);
+        Ok(())
+    }
+
+    /// Stops the stream and finalizes the recording, returns its samples to
+    /// transcribe, `None` when not recording. The stream is paused either
+    /// way, it keeps running after `max_recording_secs` ended a recording.
+    fn end_recording(&self) -> Result<Option<Vec<f32>>> {
+        let recording = finish(&self.recording)?;
+        if !self.keep_running {
+            self.stream.pause()?;
+        }
+        recording.map(Recording::end).transpose()
+    }
+
+    fn write_input_data(
+        input: &[f32],
+        recording: &RecordingHandle,
+        resampler: &mut Option<Resample>,
+        noise: &mut Option<NoiseSuppression>,
+        segmenter: &mut Option<Segmenter>,
+        pre_roll: &mut Option<PreRoll>,
+        tx_audio: &UnboundedSender<Audio>,
+    ) {
+        // Resample and downmix to 16kHz mono
+        let samples = match resampler.as_mut() {
+            Some(resampler) => resampler.process(input),
+            None => input.to_vec(),
+        };
+        let samples = match noise.as_mut() {
+            Some(noise) => noise.process(&samples),
+            None => samples,
+        };
+        let Ok(mut guard) = recording.try_lock() else {
+            return;
+        };
+        let Some(recording) = guard.as_mut() else {
+            if let Some(pre_roll) = pre_roll.as_mut() {
+                pre_roll.push(input, &samples);
+            }
+            return;
+        };
+        let started = recording.samples.is_empty();
+        if let Some(pre_roll) = pre_roll.as_mut() {
+            pre_roll.drain_into(recording);
+        }
+        let Some(segmenter) = segmenter.as_mut() else {
+            recording.write(input, &samples);
+            return;
+        };
+        if started {
+            segmenter.reset();
+        }
+        recording.write(input, &samples);
+        if segmenter.process(&samples) {
+            let segment = recording.samples[recording.sent..].to_vec();
+            recording.sent = recording.samples.len();
+            if tx_audio.send(Audio::Segment(segment)).is_err() {
+                warn!("Transcription stopped, dropping a segment");
+            }
+        }
+        recording.pending_speech = segmenter.heard_speech();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::vad::{N_SAMPLES, SpeechProb};
+    use crate::config::DownmixMode;
+
+    #[test]
+    fn test_double_start_and_stop() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 1, 16000);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+
+        // Nothing to stop yet
+        assert!(finish(&recording)?.is_none());
+
+        assert!(begin(&recording, || Ok(WavWriter::create(&path, spec)?))?);
+        // A second start keeps the current writer
+        assert!(!begin(&recording, || Err(anyhow!(
+            "Must not create a new writer"
+        )))?);
+
+        let active = finish(&recording)?.ok_or(anyhow!("Writer is active"))?;
+        active.wav.finalize()?;
+        // A second stop is a no-op
+        assert!(finish(&recording)?.is_none());
         Ok(())
     }
 
-    fn write_input_data_sample<T, U>(
-        input: &[T],
-        writer: &WavWriterHandle,
-        resampler: Option<Resample>,
-    ) where
-        T: Sample + rubato::Sample,
-        U: Sample + hound::Sample + FromSample<T>,
-        FftFixedInOut<T>: Resampler<T>,
-    {
-        if let Some(resampler) = resampler {
-            // Convert the input samples to f32
-            let samples: Vec<f32> = input
-                .iter()
-                .map(|s| s.to_float_sample().to_sample())
-                .collect();
-
-            // Resample the stereo audio to the desired sample rate
-            // let resampled_stereo: Vec<f32> = audio_resample(&samples, sample_rate, 16000, channels);
-            let resampled_stereo: Vec<f32> = audio_resample(
-                &samples,
-                resampler.samplerate_in,
-                resampler.samplerate_out,
-                resampler.in_channels,
+    #[test]
+    fn test_odd_rate_device() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        // The default 16kHz mono config, on a device only capturing 44.1kHz stereo
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 2, 44100);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+        let mut resampler = Resample::for_model(44100, 2, &[], DownmixMode::Average, 0)?;
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        begin(&recording, || Ok(WavWriter::create(&path, spec)?))?;
+        for _ in 0..50 {
+            // 20ms callbacks
+            AudioRecorder::write_input_data(
+                &[0.1; 2 * 882],
+                &recording,
+                &mut resampler,
+                &mut None,
+                &mut None,
+                &mut None,
+                &tx,
             );
+        }
+        let active = finish(&recording)?.ok_or(anyhow!("Writer is active"))?;
+        let samples = active.end()?;
 
-            let samples = if resampler.in_channels != 1 {
-                let n = resampler.in_channels as usize;
-                // Convert the resampled stereo audio to mono
-                let mono_samples: Vec<_> = resampled_stereo
-                    .chunks(n)
-                    .map(|chunk| {
-                        let mono_sample = (chunk.iter().sum::<f32>()) / n as f32; // Average channels
-                        mono_sample
-                    })
-                    .collect();
-                mono_samples
-            } else {
-                resampled_stereo
-            };
-            if let Ok(mut guard) = writer.try_lock() {
-                if let Some(writer) = guard.as_mut() {
-                    for &sample in samples.iter() {
-                        // let sample: U = U::from_sample(sample);
-                        writer.write_sample(sample).ok();
-                    }
-                }
+        // The saved recording is as captured, Whisper gets 16kHz mono
+        let reader = hound::WavReader::open(&path)?;
+        assert_eq!(reader.spec().sample_rate, 44100);
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.len(), 2 * 44100);
+        let n = samples.len();
+        assert!((15000..=16000).contains(&n), "{n}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pre_roll() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 1, 16000);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+        // 0.3s kept before the keys are pressed
+        let mut pre_roll = PreRoll::new(0.3, 1, 16000);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut write = |seconds: f32| {
+            // 20ms callbacks
+            for _ in 0..(seconds * 50.0) as usize {
+                AudioRecorder::write_input_data(
+                    &[0.1; 320],
+                    &recording,
+                    &mut None,
+                    &mut None,
+                    &mut None,
+                    &mut pre_roll,
+                    &tx,
+                );
             }
-        } else if let Ok(mut guard) = writer.try_lock() {
-            if let Some(writer) = guard.as_mut() {
-                for &sample in input.iter() {
-                    let sample: U = U::from_sample(sample);
-                    writer.write_sample(sample).ok();
-                }
+        };
+
+        // Longer than the pre-roll, only its end is kept
+        write(1.0);
+        begin(&recording, || Ok(WavWriter::create(&path, spec)?))?;
+        write(0.5);
+        let active = finish(&recording)?.ok_or(anyhow!("Writer is active"))?;
+        let samples = active.end()?;
+        assert_eq!(samples.len(), 4800 + 8000);
+        let reader = hound::WavReader::open(&path)?;
+        assert_eq!(reader.len(), 4800 + 8000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_recording() -> Result<()> {
+        assert_eq!(max_samples(1.0), 16000);
+        assert_eq!(max_samples(0.5), 8000);
+        assert_eq!(max_samples(120.0), 1_920_000);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 1, 16000);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        // Not recording
+        assert!(end_capped(&recording, 0).is_none());
+
+        begin(&recording, || Ok(WavWriter::create(&path, spec)?))?;
+        let max = max_samples(1.0);
+        let write = || {
+            // 20ms callbacks
+            AudioRecorder::write_input_data(
+                &[0.1; 320],
+                &recording,
+                &mut None,
+                &mut None,
+                &mut None,
+                &mut None,
+                &tx,
+            );
+        };
+        for _ in 0..49 {
+            write();
+        }
+        assert!(end_capped(&recording, max).is_none());
+        write();
+        // Ended right at the cap
+        let samples = end_capped(&recording, max)
+            .ok_or(anyhow!("Reached the cap"))?
+            .end()?;
+        assert_eq!(samples.len(), 16000);
+        assert!(finish(&recording)?.is_none());
+        assert_eq!(hound::WavReader::open(&path)?.len(), 16000);
+        Ok(())
+    }
+
+    /// Loud frames are speech.
+    struct Energy;
+
+    impl SpeechProb for Energy {
+        fn speech_prob(&mut self, frame: &[f32; N_SAMPLES]) -> Result<f32> {
+            Ok(frame.iter().fold(0.0, |max: f32, s| max.max(s.abs())))
+        }
+    }
+
+    #[test]
+    fn test_streaming_segments() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 1, 16000);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+        let mut segmenter = Some(Segmenter::with_model(Box::new(Energy)));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        begin(&recording, || Ok(WavWriter::create(&path, spec)?))?;
+        let mut write = |sample: f32, seconds: f32| {
+            // 20ms callbacks
+            for _ in 0..(seconds * 50.0) as usize {
+                AudioRecorder::write_input_data(
+                    &[sample; 320],
+                    &recording,
+                    &mut None,
+                    &mut None,
+                    &mut segmenter,
+                    &mut None,
+                    &tx,
+                );
             }
+        };
+
+        // Speech and a pause, the key still held
+        write(0.9, 1.0);
+        write(0.0, 1.5);
+        let Ok(Audio::Segment(first)) = rx.try_recv() else {
+            return Err(anyhow!("Expected a segment before the release"));
+        };
+        // The speech and the pause ending it, not what follows
+        assert!((16000..40000).contains(&first.len()), "{}", first.len());
+
+        // More speech, released without a pause
+        write(0.9, 0.5);
+        let active = finish(&recording)?.ok_or(anyhow!("Writer is active"))?;
+        let total = active.samples.len();
+        let rest = active.end()?;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(first.len() + rest.len(), total);
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_trailing_silence() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recorded.wav");
+        let spec = AudioRecorder::create_wav_spec(&AudioConfig::default(), 1, 16000);
+        let recording: RecordingHandle = Arc::new(Mutex::new(None));
+        let mut segmenter = Some(Segmenter::with_model(Box::new(Energy)));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        begin(&recording, || Ok(WavWriter::create(&path, spec)?))?;
+        for sample in [0.9; 50].into_iter().chain([0.0; 100]) {
+            AudioRecorder::write_input_data(
+                &[sample; 320],
+                &recording,
+                &mut None,
+                &mut None,
+                &mut segmenter,
+                &mut None,
+                &tx,
+            );
         }
+        assert!(matches!(rx.try_recv(), Ok(Audio::Segment(_))));
+        // Only silence after the segment, nothing left to transcribe
+        let active = finish(&recording)?.ok_or(anyhow!("Writer is active"))?;
+        assert!(active.end()?.is_empty());
+        Ok(())
     }
 }
@@ -1,23 +1,264 @@
-#[derive(Clone, Copy)]
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use rubato::{FftFixedIn, Resampler};
+
+use crate::config::DownmixMode;
+
+/// Number of input frames the resampler consumes at once.
+const CHUNK_SIZE: usize = 1024;
+
+/// Sample rate of the mono audio given to the VAD and Whisper, whatever the
+/// configured capture format.
+pub const MODEL_SAMPLE_RATE: u32 = 16000;
+
+/// Streaming resampler from the device rate/channels to mono at `samplerate_out`.
+///
+/// The resampler is kept alive across audio callbacks so that its filter state
+/// is continuous, instead of being reset (and producing small discontinuities)
+/// at every chunk boundary.
 pub struct Resample {
-    pub samplerate_in: u32,
-    pub samplerate_out: u32,
-    pub in_channels: u16,
+    resampler: FftFixedIn<f32>,
+    in_channels: usize,
+    /// Weight of each channel in the mono downmix
+    weights: Vec<f32>,
+    mode: DownmixMode,
+    /// Deinterleaved input waiting for a full resampler chunk.
+    pending: Vec<Vec<f32>>,
+}
+
+impl Resample {
+    /// `weights` (one per channel) are used for the `average` mono downmix,
+    /// empty averages the channels equally.
+    pub fn new(
+        samplerate_in: u32,
+        samplerate_out: u32,
+        in_channels: u16,
+        weights: &[f32],
+        mode: DownmixMode,
+    ) -> Result<Self> {
+        let in_channels = in_channels as usize;
+        let weights = match weights.len() {
+            0 => vec![1.0 / in_channels.max(1) as f32; in_channels],
+            n if n == in_channels => weights.to_vec(),
+            n => bail!("{n} downmix weights for {in_channels} input channels"),
+        };
+        let resampler = FftFixedIn::new(
+            samplerate_in as usize,
+            samplerate_out as usize,
+            CHUNK_SIZE,
+            2,
+            in_channels,
+        )?;
+        Ok(Self {
+            resampler,
+            in_channels,
+            weights,
+            mode,
+            pending: vec![Vec::with_capacity(2 * CHUNK_SIZE); in_channels],
+        })
+    }
+
+    /// Resampler from a stream at `samplerate_in` with `in_channels` to the
+    /// mono [`MODEL_SAMPLE_RATE`] audio, `None` when the stream is already
+    /// mono and within `tolerance` Hz of it.
+    pub fn for_model(
+        samplerate_in: u32,
+        in_channels: u16,
+        weights: &[f32],
+        mode: DownmixMode,
+        tolerance: u32,
+    ) -> Result<Option<Self>> {
+        if in_channels == 1 && samplerate_in.abs_diff(MODEL_SAMPLE_RATE) <= tolerance {
+            if samplerate_in != MODEL_SAMPLE_RATE {
+                debug!("Using the {samplerate_in}Hz stream as {MODEL_SAMPLE_RATE}Hz");
+            }
+            return Ok(None);
+        }
+        Self::new(samplerate_in, MODEL_SAMPLE_RATE, in_channels, weights, mode).map(Some)
+    }
+
+    /// Feeds a chunk of interleaved audio and returns the mono samples that
+    /// are ready. Leftover frames are kept for the next call.
+    ///
+    /// On failure the chunk is dropped and a warning is logged so that
+    /// missing audio is visible.
+    pub fn process(&mut self, data: &[f32]) -> Vec<f32> {
+        for frame in data.chunks_exact(self.in_channels) {
+            for (channel, &sample) in self.pending.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+        }
+
+        let mut output = Vec::new();
+        loop {
+            let needed = self.resampler.input_frames_next();
+            if self.pending[0].len() < needed {
+                break;
+            }
+            let chunk: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|channel| channel.drain(..needed).collect())
+                .collect();
+            match self.resampler.process(&chunk, None) {
+                Ok(resampled) => {
+                    // Convert the resampled audio to mono
+                    let mut frame = Vec::with_capacity(self.in_channels);
+                    output.extend((0..resampled[0].len()).map(|i| {
+                        frame.clear();
+                        frame.extend(resampled.iter().map(|channel| channel[i]));
+                        downmix(&frame, &self.weights, self.mode)
+                    }));
+                }
+                Err(err) => {
+                    warn!("Resampling {needed} frames failed, dropping chunk: {err}");
+                }
+            }
+        }
+        output
+    }
 }
 
-pub fn audio_resample(
-    data: &[f32],
-    sample_rate0: u32,
-    sample_rate: u32,
-    channels: u16,
-) -> Vec<f32> {
-    use samplerate::{ConverterType, convert};
-    convert(
-        sample_rate0 as _,
-        sample_rate as _,
-        channels as _,
-        ConverterType::SincBestQuality,
-        data,
-    )
-    .unwrap_or_default()
+/// Mixes a frame (one sample per channel) down to a mono sample.
+fn downmix(frame: &[f32], weights: &[f32], mode: DownmixMode) -> f32 {
+    match mode {
+        DownmixMode::Average => frame.iter().zip(weights).map(|(s, w)| s * w).sum(),
+        DownmixMode::Peak => frame
+            .iter()
+            .copied()
+            .fold(0.0, |peak, s| if s.abs() > peak.abs() { s } else { peak }),
+        DownmixMode::First => frame.first().copied().unwrap_or(0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_sweep(sample_rate: u32, seconds: f32) -> Vec<f32> {
+        let n = (sample_rate as f32 * seconds) as usize;
+        (0..n)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                // 100Hz -> 4kHz linear sweep
+                let freq = 100.0 + (4000.0 - 100.0) * t / seconds / 2.0;
+                (2.0 * std::f32::consts::PI * freq * t).sin() * 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_for_model() -> Result<()> {
+        assert!(Resample::for_model(16000, 1, &[], DownmixMode::Average, 0)?.is_none());
+        // The configured format doesn't matter, only the stream's
+        assert!(Resample::for_model(16000, 2, &[], DownmixMode::Average, 0)?.is_some());
+        assert!(Resample::for_model(48000, 1, &[], DownmixMode::Average, 0)?.is_some());
+        // An odd rate close to 16kHz is used as is within the tolerance
+        assert!(Resample::for_model(16001, 1, &[], DownmixMode::Average, 0)?.is_some());
+        assert!(Resample::for_model(16001, 1, &[], DownmixMode::Average, 10)?.is_none());
+        assert!(Resample::for_model(16001, 2, &[], DownmixMode::Average, 10)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_odd_rate() -> Result<()> {
+        // A 22050Hz stereo device still yields 16kHz mono
+        let mut resampler = Resample::for_model(22050, 2, &[], DownmixMode::Average, 0)?
+            .ok_or(anyhow::anyhow!("Needs resampling"))?;
+        let data = vec![0.5; 2 * 22050];
+        let out = resampler.process(&data);
+        assert!((15000..=16000).contains(&out.len()), "{}", out.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_invalid_rate() {
+        assert!(Resample::new(0, 16000, 1, &[], DownmixMode::Average).is_err());
+    }
+
+    #[test]
+    fn test_resample_length() -> Result<()> {
+        let mut resampler = Resample::new(48000, 16000, 1, &[], DownmixMode::Average)?;
+        let data = vec![0.5; 48000];
+        let out = resampler.process(&data);
+        // Only full chunks are emitted, the remainder waits for the next call.
+        assert!((15000..=16000).contains(&out.len()), "{}", out.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_stereo_downmix() -> Result<()> {
+        let mut resampler = Resample::new(48000, 16000, 2, &[], DownmixMode::Average)?;
+        let data: Vec<f32> = (0..48000).flat_map(|_| [0.2, 0.6]).collect();
+        let out = resampler.process(&data);
+        assert!(!out.is_empty());
+        // Skip the filter warm-up, then the average of both channels remains.
+        let last = out[out.len() - 1];
+        assert!((last - 0.4).abs() < 0.01, "{last}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample_weighted_downmix() -> Result<()> {
+        let data: Vec<f32> = (0..48000).flat_map(|_| [0.2, 0.6]).collect();
+        let last = |weights: &[f32]| -> Result<f32> {
+            let out = Resample::new(48000, 16000, 2, weights, DownmixMode::Average)?.process(&data);
+            out.last().copied().ok_or(anyhow::anyhow!("No output"))
+        };
+        // Equal weights match the default average
+        assert!((last(&[0.5, 0.5])? - last(&[])?).abs() < 1e-6);
+        let weighted = last(&[0.75, 0.25])?;
+        assert!((weighted - 0.3).abs() < 0.01, "{weighted}");
+        // Mid/side: the side is the difference of the channels
+        let side = last(&[0.5, -0.5])?;
+        assert!((side + 0.2).abs() < 0.01, "{side}");
+
+        assert!(Resample::new(48000, 16000, 2, &[1.0], DownmixMode::Average).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_downmix_modes() {
+        let frame = [0.1, -0.6];
+        let weights = [0.5, 0.5];
+        assert!((downmix(&frame, &weights, DownmixMode::Average) + 0.25).abs() < 1e-6);
+        // The loudest channel, sign included
+        assert_eq!(downmix(&frame, &weights, DownmixMode::Peak), -0.6);
+        assert_eq!(downmix(&[0.6, -0.1], &weights, DownmixMode::Peak), 0.6);
+        assert_eq!(downmix(&frame, &weights, DownmixMode::First), 0.1);
+    }
+
+    #[test]
+    fn test_continuous_vs_per_chunk() -> Result<()> {
+        let sweep = sine_sweep(44100, 1.0);
+
+        // Reference: the whole signal through a single resampler.
+        let mut reference = Resample::new(44100, 16000, 1, &[], DownmixMode::Average)?;
+        let expected = reference.process(&sweep);
+
+        // Streaming: same resampler state kept across irregular callback sizes.
+        let mut streaming = Resample::new(44100, 16000, 1, &[], DownmixMode::Average)?;
+        let mut continuous = Vec::new();
+        for chunk in sweep.chunks(441) {
+            continuous.extend(streaming.process(chunk));
+        }
+        assert_eq!(continuous, expected);
+
+        // Previous behavior: a fresh resampler per chunk resets the filter and
+        // drifts away from the continuous output.
+        let mut per_chunk = Vec::new();
+        for chunk in sweep.chunks(4410) {
+            let mut fresh = Resample::new(44100, 16000, 1, &[], DownmixMode::Average)?;
+            per_chunk.extend(fresh.process(chunk));
+        }
+        let n = per_chunk.len().min(expected.len());
+        let error: f32 = per_chunk[..n]
+            .iter()
+            .zip(&expected[..n])
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>()
+            / n as f32;
+        assert!(error > 1e-3, "{error}");
+        Ok(())
+    }
 }
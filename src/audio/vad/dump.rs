@@ -0,0 +1,149 @@
+//! Continuous dump of the stream the VAD listens to, for debugging missed
+//! detections.
+//!
+//! Everything the VAD sees (16kHz mono, after resampling and noise
+//! suppression) is written to a WAV file, speech or not. Once the file
+//! reaches its size bound it is moved aside (replacing the previous one) and
+//! a new one starts, so at most two files are kept.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{debug, warn};
+
+use crate::config::Config;
+
+const DUMP_FILE: &str = "vad_stream.wav";
+const PREVIOUS_FILE: &str = "vad_stream.prev.wav";
+
+const SPEC: WavSpec = WavSpec {
+    channels: 1,
+    sample_rate: 16000,
+    bits_per_sample: 32,
+    sample_format: SampleFormat::Float,
+};
+
+/// Rolling WAV writer.
+struct Rolling {
+    path: PathBuf,
+    previous: PathBuf,
+    max_samples: usize,
+    writer: Option<WavWriter<std::io::BufWriter<std::fs::File>>>,
+    written: usize,
+}
+
+impl Rolling {
+    fn new(dir: &Path, max_samples: usize) -> Self {
+        Self {
+            path: dir.join(DUMP_FILE),
+            previous: dir.join(PREVIOUS_FILE),
+            max_samples,
+            writer: None,
+            written: 0,
+        }
+    }
+
+    fn write(&mut self, mut samples: &[f32]) -> Result<()> {
+        while !samples.is_empty() {
+            if self.written >= self.max_samples {
+                self.roll()?;
+            }
+            let writer = match self.writer.as_mut() {
+                Some(writer) => writer,
+                None => self.writer.insert(
+                    WavWriter::create(&self.path, SPEC)
+                        .context(format!("Creating {}", self.path.display()))?,
+                ),
+            };
+            let n = samples.len().min(self.max_samples - self.written);
+            for &sample in &samples[..n] {
+                writer.write_sample(sample)?;
+            }
+            self.written += n;
+            samples = &samples[n..];
+        }
+        Ok(())
+    }
+
+    /// Moves the full file aside, the next write starts a new one.
+    fn roll(&mut self) -> Result<()> {
+        self.finish()?;
+        std::fs::rename(&self.path, &self.previous)
+            .context(format!("Moving {} aside", self.path.display()))?;
+        debug!("Rolled the VAD stream dump");
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Sends the VAD stream to a writer thread, keeping file I/O off the audio
+/// callback. The file is finalized when dropped.
+pub(super) struct StreamDump {
+    tx: Sender<Vec<f32>>,
+}
+
+impl StreamDump {
+    /// Returns `None` unless `[debug] vad_stream` is set.
+    pub(super) fn new(config: &Config) -> Result<Option<Self>> {
+        if !config.debug.vad_stream {
+            return Ok(None);
+        }
+        let dir = config.paths.cache_dir.clone();
+        std::fs::create_dir_all(&dir).context("Creating cache directory")?;
+        // 4 bytes per sample
+        let max_samples = (config.debug.vad_stream_max_mb * 1024 * 1024 / 4) as usize;
+        let rolling = Rolling::new(&dir, max_samples);
+        let (tx, rx) = channel();
+        std::thread::spawn(move || write_all(rolling, rx));
+        debug!(
+            "Dumping the VAD stream to {}",
+            dir.join(DUMP_FILE).display()
+        );
+        Ok(Some(Self { tx }))
+    }
+
+    pub(super) fn send(&self, samples: &[f32]) {
+        // The writer thread only stops on errors, already logged
+        let _ = self.tx.send(samples.to_vec());
+    }
+}
+
+fn write_all(mut rolling: Rolling, rx: Receiver<Vec<f32>>) {
+    let result = rx
+        .iter()
+        .try_for_each(|samples| rolling.write(&samples))
+        .and_then(|()| rolling.finish());
+    if let Err(err) = result {
+        warn!("Stopped dumping the VAD stream: {err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut rolling = Rolling::new(dir.path(), 100);
+        rolling.write(&[0.1; 60])?;
+        rolling.write(&[0.2; 60])?;
+        rolling.finish()?;
+
+        // The first 100 samples were moved aside, the rest started over
+        let previous = hound::WavReader::open(dir.path().join(PREVIOUS_FILE))?;
+        assert_eq!(previous.len(), 100);
+        let current = hound::WavReader::open(dir.path().join(DUMP_FILE))?;
+        assert_eq!(current.len(), 20);
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! Energy based voice activity detection, for when the Silero model can't
+//! be loaded (offline).
+//!
+//! A frame is speech when its RMS level exceeds a fixed threshold. Much less
+//! accurate than Silero: any loud enough noise (typing, music) is speech, and
+//! quiet speech isn't.
+
+use anyhow::Result;
+
+use super::{N_SAMPLES, SpeechProb};
+
+/// Speech probability 1.0 when a frame is louder than `threshold`, 0.0
+/// otherwise.
+pub(super) struct Energy {
+    threshold: f32,
+}
+
+impl Energy {
+    /// `threshold` is an RMS level, e.g. 0.01 (-40 dBFS).
+    pub(super) fn new(threshold: f32) -> Self {
+        Self { threshold }
+    }
+}
+
+impl SpeechProb for Energy {
+    fn speech_prob(&mut self, frame: &[f32; N_SAMPLES]) -> Result<f32> {
+        Ok(if rms(frame) > self.threshold {
+            1.0
+        } else {
+            0.0
+        })
+    }
+}
+
+/// Root mean square level of `samples`.
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 440Hz sine of `amplitude` at 16kHz.
+    fn sine(amplitude: f32) -> [f32; N_SAMPLES] {
+        std::array::from_fn(|i| {
+            amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin()
+        })
+    }
+
+    #[test]
+    fn test_rms() {
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(rms(&[0.0; N_SAMPLES]), 0.0);
+        assert_eq!(rms(&[-0.5; N_SAMPLES]), 0.5);
+        // A sine's RMS is its amplitude over sqrt(2)
+        let level = rms(&sine(0.5));
+        assert!((level - 0.5 / 2f32.sqrt()).abs() < 0.01, "{level}");
+    }
+
+    #[test]
+    fn test_energy_threshold() -> Result<()> {
+        let mut energy = Energy::new(0.01);
+        assert_eq!(energy.speech_prob(&[0.0; N_SAMPLES])?, 0.0);
+        assert_eq!(energy.speech_prob(&sine(0.3))?, 1.0);
+        // RMS 0.0071 and 0.0141
+        assert_eq!(energy.speech_prob(&sine(0.01))?, 0.0);
+        assert_eq!(energy.speech_prob(&sine(0.02))?, 1.0);
+        // Stricter threshold
+        let mut energy = Energy::new(0.25);
+        assert_eq!(energy.speech_prob(&sine(0.3))?, 0.0);
+        Ok(())
+    }
+}
@@ -4,9 +4,8 @@
 //! and saving it to a WAV file. It handles device initialization, stream configuration,
 //! and audio data processing.
 
-use anyhow::{Context, Result, anyhow};
-use cpal::SupportedStreamConfig;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use anyhow::Result;
+use cpal::traits::StreamTrait;
 use hf_hub::api::tokio::ApiBuilder;
 // use hound::{WavSpec, WavWriter};
 use log::{debug, error, info, warn};
@@ -15,22 +14,32 @@ use ringbuf::{
     HeapRb,
     traits::{Consumer, Producer},
 };
-use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
-use crate::audio::resample::{Resample, audio_resample};
-use crate::config::Config;
+use crate::audio::resample::Resample;
+use crate::config::{Config, VadModel};
 
+mod dump;
+mod energy;
 mod silero;
+use dump::StreamDump;
+use energy::Energy;
 use silero::Silero;
 
 use super::Audio;
+use super::clipping::ClippingDetector;
+use super::denoise::NoiseSuppression;
+use super::device::{build_input_stream, downmix_weights, input_device};
+use super::permission::check_microphone;
 
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq))]
 enum VADEvent {
     StartSpeech,
+    /// Part of an utterance still going on, cut at `max_chunk_secs`
+    Chunk(Vec<f32>),
     EndSpeech(Vec<f32>),
 }
 
@@ -55,6 +64,26 @@ struct VADState {
     threshold: f32,
     audio_buffer: HeapRb<f32>,
     pre_buffer: HeapRb<f32>,
+    /// The current utterance overflowed `audio_buffer`, warned once
+    overflowed: bool,
+    /// Length of the warm-up after listening starts
+    warmup_samples: usize,
+    /// Remaining warm-up, speech can't start a recording until it's over
+    warmup_left: usize,
+    /// Utterance length at which a chunk is cut off, `None` to never cut
+    max_chunk_samples: Option<usize>,
+}
+
+/// Pushes `samples` to `buffer`, dropping its oldest samples (or the oldest of
+/// `samples` if they don't fit at all) to make room. Returns the number of
+/// samples dropped.
+pub(super) fn push_dropping_oldest(buffer: &mut HeapRb<f32>, samples: &[f32]) -> usize {
+    let capacity = buffer.capacity().get();
+    let overflow = (buffer.occupied_len() + samples.len()).saturating_sub(capacity);
+    let skipped = samples.len().saturating_sub(capacity);
+    buffer.skip(overflow - skipped);
+    buffer.push_slice(&samples[skipped..]);
+    overflow
 }
 
 impl VADState {
@@ -63,12 +92,19 @@ impl VADState {
         speech_duration: f32,
         silence_duration: f32,
         pre_buffer_duration: f32,
+        warmup_duration: f32,
+        max_utterance_duration: f32,
+        max_chunk_duration: Option<f32>,
     ) -> Self {
         // Calculate sizes based on sample rate (16kHz)
         let sample_rate = 16000.0;
         let pre_buffer_size = (sample_rate * pre_buffer_duration) as usize;
+        let warmup_samples = (sample_rate * warmup_duration) as usize;
         let speech_threshold_samples = (sample_rate * speech_duration) as usize;
         let silence_threshold_samples = (sample_rate * silence_duration) as usize;
+        // At least a frame, for durations shorter than one
+        let max_utterance_samples =
+            ((sample_rate * max_utterance_duration) as usize).max(N_SAMPLES);
 
         Self {
             state: VADStateEnum::Silent,
@@ -77,42 +113,71 @@ impl VADState {
             speech_threshold_samples,
             silence_threshold_samples,
             threshold,
-            // Create a large enough buffer for the maximum possible recording length
-            audio_buffer: HeapRb::new(16000 * 60), // 60 seconds buffer
+            audio_buffer: HeapRb::new(max_utterance_samples),
             pre_buffer: HeapRb::new(pre_buffer_size),
+            overflowed: false,
+            warmup_samples,
+            warmup_left: 0,
+            max_chunk_samples: max_chunk_duration
+                .map(|duration| ((sample_rate * duration) as usize).max(N_SAMPLES)),
         }
     }
 
+    /// Back to silent with empty buffers, for a new recording.
+    fn reset(&mut self) {
+        self.state = VADStateEnum::Silent;
+        self.speech_samples = 0;
+        self.silence_samples = 0;
+        self.audio_buffer.clear();
+        self.pre_buffer.clear();
+        self.overflowed = false;
+    }
+
+    /// Starts the warm-up, called when listening starts. Frames still go
+    /// through the model (keeping its state warm) but the gain control's
+    /// burst of noise can't be mistaken for speech.
+    fn warm_up(&mut self) {
+        self.warmup_left = self.warmup_samples;
+        // Stale audio from before the stream was paused
+        self.pre_buffer.clear();
+    }
+
+    /// Moves the pre-buffer to the (cleared) audio buffer, returns how many
+    /// samples it held.
+    fn start_with_pre_buffer(&mut self) -> usize {
+        let pre_buffer: Vec<f32> = self.pre_buffer.pop_iter().collect();
+        push_dropping_oldest(&mut self.audio_buffer, &pre_buffer);
+        pre_buffer.len()
+    }
+
     /// Process a frame: update state and manage buffers in sync
     fn process_frame(&mut self, speech_prob: f32, samples: &[f32; N_SAMPLES]) -> Option<VADEvent> {
-        // Buffer management (pre-buffer and audio buffer) is now always in sync with state
-        let pre_buffer_capacity: usize = self.pre_buffer.capacity().into();
-        let samples_to_add = samples.len();
-        // Pre-buffer management
-        if self.pre_buffer.occupied_len() + samples_to_add > pre_buffer_capacity {
-            let samples_to_drop =
-                self.pre_buffer.occupied_len() + samples_to_add - pre_buffer_capacity;
-            let mut drop_buffer = vec![0.0; samples_to_drop];
-            let _ = self.pre_buffer.pop_slice(&mut drop_buffer);
-        }
-        let n = self.pre_buffer.push_slice(samples);
-        if n != samples.len() {
-            error!("Failed to add samples to pre-buffer");
+        if self.warmup_left > 0 {
+            self.warmup_left = self.warmup_left.saturating_sub(N_SAMPLES);
+            // An utterance interrupted by stopping goes on
+            if matches!(
+                self.state,
+                VADStateEnum::Silent | VADStateEnum::SpeechDetected
+            ) {
+                // Neither detected as speech nor kept in the pre-buffer
+                self.state = VADStateEnum::Silent;
+                self.speech_samples = 0;
+                return None;
+            }
         }
 
+        // Buffer management (pre-buffer and audio buffer) is now always in sync with state
+        push_dropping_oldest(&mut self.pre_buffer, samples);
+
         // Audio buffer management (only if recording)
         if self.state != VADStateEnum::Silent {
-            let audio_buffer_capacity: usize = self.audio_buffer.capacity().into();
-            let samples_to_add = samples.len();
-            if self.audio_buffer.occupied_len() + samples_to_add > audio_buffer_capacity {
-                let samples_to_drop =
-                    self.audio_buffer.occupied_len() + samples_to_add - audio_buffer_capacity;
-                let mut drop_buffer = vec![0.0; samples_to_drop];
-                let _ = self.audio_buffer.pop_slice(&mut drop_buffer);
-            }
-            let n = self.audio_buffer.push_slice(samples);
-            if n != samples.len() {
-                error!("Audio buffer full, dropping samples");
+            let dropped = push_dropping_oldest(&mut self.audio_buffer, samples);
+            if dropped > 0 && !self.overflowed {
+                self.overflowed = true;
+                warn!(
+                    "Utterance longer than {}s, dropping its beginning",
+                    self.audio_buffer.capacity().get() / 16_000
+                );
             }
         }
 
@@ -124,13 +189,9 @@ impl VADState {
                     if self.speech_samples >= self.speech_threshold_samples {
                         self.state = VADStateEnum::Recording;
                         self.audio_buffer.clear();
+                        self.overflowed = false;
                         // Add pre-buffer to the start of audio_buffer
-                        let mut temp = vec![0.0; self.pre_buffer.occupied_len()];
-                        let n = self.pre_buffer.occupied_len();
-                        let n2 = self.pre_buffer.pop_slice(&mut temp);
-                        assert_eq!(n, n2);
-                        let n3 = self.audio_buffer.push_slice(&temp[..n]);
-                        assert_eq!(n2, n3);
+                        let n = self.start_with_pre_buffer();
                         info!(
                             "Got {n} samples for pre buffer: this is {}s",
                             n as f32 / 16_000.0
@@ -151,13 +212,9 @@ impl VADState {
                     if self.speech_samples >= self.speech_threshold_samples {
                         self.state = VADStateEnum::Recording;
                         self.audio_buffer.clear();
+                        self.overflowed = false;
                         // Add pre-buffer to the start of audio_buffer
-                        let n = self.pre_buffer.occupied_len();
-                        let mut temp = vec![0.0; n];
-                        let n2 = self.pre_buffer.pop_slice(&mut temp);
-                        let n3 = self.audio_buffer.push_slice(&temp[..n]);
-                        assert_eq!(n, n2);
-                        assert_eq!(n2, n3);
+                        let n = self.start_with_pre_buffer();
                         debug!(
                             "Got {n} samples for pre buffer: this is {}s",
                             n as f32 / 16_000.0
@@ -208,7 +265,302 @@ impl VADState {
                 }
             }
         }
-        None
+        self.cut_chunk()
+    }
+
+    /// Cuts a chunk off an utterance reaching `max_chunk_samples`, at the
+    /// quietest frame of its end. The rest stays in the audio buffer, the
+    /// recording going on from there.
+    fn cut_chunk(&mut self) -> Option<VADEvent> {
+        let max_chunk_samples = self.max_chunk_samples?;
+        let recording = matches!(
+            self.state,
+            VADStateEnum::Recording | VADStateEnum::SilenceDetected
+        );
+        if !recording || self.audio_buffer.occupied_len() < max_chunk_samples {
+            return None;
+        }
+        let mut samples: Vec<f32> = self.audio_buffer.pop_iter().collect();
+        // Half the chunk at most, the rest must not reach the limit again
+        let search = CHUNK_SEARCH.min(max_chunk_samples / 2);
+        let at = split_point(&samples, search);
+        self.audio_buffer.push_slice(&samples[at..]);
+        samples.truncate(at);
+        debug!(
+            "Cut a {}s chunk off the utterance",
+            samples.len() as f32 / 16_000.0
+        );
+        Some(VADEvent::Chunk(samples))
+    }
+}
+
+/// Where chunks are looked for a quiet moment to cut at, from their end (1s).
+const CHUNK_SEARCH: usize = 16_000;
+
+/// Where to cut `samples` within their last `search` samples: the middle of
+/// their quietest frame, the frames sliding back from the end by a quarter
+/// frame (the latest one on ties, for longer chunks).
+fn split_point(samples: &[f32], search: usize) -> usize {
+    let search = search.min(samples.len());
+    if search < N_SAMPLES {
+        return samples.len();
+    }
+    let hop = N_SAMPLES / 4;
+    let last = samples.len() - N_SAMPLES;
+    let energy = |start: usize| {
+        samples[start..start + N_SAMPLES]
+            .iter()
+            .map(|sample| sample * sample)
+            .sum::<f32>()
+    };
+    let quietest = (0..=(search - N_SAMPLES) / hop)
+        .map(|i| last - i * hop)
+        .map(|start| (start, energy(start)))
+        .reduce(|quietest, frame| {
+            if frame.1 < quietest.1 {
+                frame
+            } else {
+                quietest
+            }
+        });
+    quietest.map_or(samples.len(), |(start, _)| start + N_SAMPLES / 2)
+}
+
+/// Runs the model on one frame out of `factor` while silent, saving its
+/// cost during long silences, and on every frame otherwise.
+struct SilentSkip {
+    factor: u32,
+    /// Silent frames since the model last ran, modulo `factor`
+    silent_frames: u32,
+}
+
+impl SilentSkip {
+    fn new(factor: u32) -> Self {
+        Self {
+            factor: factor.max(1),
+            silent_frames: 0,
+        }
+    }
+
+    /// Whether the model runs on the next frame, heard in `state`.
+    fn run_model(&mut self, state: VADStateEnum) -> bool {
+        if state != VADStateEnum::Silent {
+            self.silent_frames = 0;
+            return true;
+        }
+        let run = self.silent_frames == 0;
+        self.silent_frames = (self.silent_frames + 1) % self.factor;
+        run
+    }
+}
+
+/// Disarms an `arm_once` recorder after its first utterance.
+#[derive(Clone)]
+struct Disarm {
+    arm_once: bool,
+    armed: Arc<AtomicBool>,
+    tx_disarmed: UnboundedSender<()>,
+}
+
+impl Disarm {
+    fn new(arm_once: bool) -> (Self, UnboundedReceiver<()>) {
+        let (tx_disarmed, rx_disarmed) = unbounded_channel();
+        let disarm = Self {
+            arm_once,
+            armed: Arc::new(AtomicBool::new(true)),
+            tx_disarmed,
+        };
+        (disarm, rx_disarmed)
+    }
+
+    fn arm(&self) {
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether incoming audio should go through the VAD.
+    fn is_armed(&self) -> bool {
+        !self.arm_once || self.armed.load(Ordering::Relaxed)
+    }
+
+    /// Called at the end of every utterance, disarms and signals `arm_once`
+    /// recorders.
+    fn utterance_done(&self) {
+        if self.arm_once {
+            self.armed.store(false, Ordering::Relaxed);
+            let _ = self.tx_disarmed.send(());
+        }
+    }
+}
+
+/// Downloads (when not cached) and loads the Silero VAD model.
+async fn load_silero() -> Result<Silero> {
+    let api = ApiBuilder::from_env().build()?;
+    let model = api.model("Narsil/silero".to_string());
+    let model_path = model.get("silero_vad.onnx").await?;
+    Ok(Silero::new(16_000, model_path)?)
+}
+
+/// Loads the VAD model of `toggle_vad`, the energy VAD (with
+/// `energy_threshold`) replacing Silero when selected or, with
+/// [`VadModel::SileroOrEnergy`], when Silero can't be loaded.
+async fn load_model(model: VadModel, energy_threshold: f32) -> Result<Box<dyn SpeechProb>> {
+    let energy = || -> Box<dyn SpeechProb> { Box::new(Energy::new(energy_threshold)) };
+    match model {
+        VadModel::Silero => Ok(Box::new(load_silero().await?)),
+        VadModel::SileroOrEnergy => match load_silero().await {
+            Ok(silero) => Ok(Box::new(silero)),
+            Err(err) => {
+                warn!(
+                    "Could not load the Silero VAD model, using the less accurate energy VAD: {err:#}"
+                );
+                Ok(energy())
+            }
+        },
+        VadModel::Energy => {
+            info!("Using the energy VAD");
+            Ok(energy())
+        }
+    }
+}
+
+/// Speech probability of 16kHz mono frames.
+pub(super) trait SpeechProb: Send {
+    fn speech_prob(&mut self, frame: &[f32; N_SAMPLES]) -> Result<f32>;
+}
+
+impl SpeechProb for Silero {
+    fn speech_prob(&mut self, frame: &[f32; N_SAMPLES]) -> Result<f32> {
+        Ok(self.calc_level(frame)?)
+    }
+}
+
+/// Speech starting a segment of a streaming push-to-talk recording (in
+/// seconds).
+const SEGMENT_SPEECH: f32 = 0.25;
+/// Pause ending a segment (in seconds).
+const SEGMENT_SILENCE: f32 = 1.0;
+/// Longest segment (in seconds).
+const SEGMENT_MAX: f32 = 60.0;
+
+/// Cuts a held push-to-talk recording at its pauses with the VAD state
+/// machine, so the parts are transcribed before the key is released.
+pub(super) struct Segmenter {
+    model: Box<dyn SpeechProb>,
+    state: VADState,
+    /// Samples short of a frame
+    pending: Vec<f32>,
+    /// A frame was speech since the last cut
+    heard_speech: bool,
+}
+
+impl Segmenter {
+    pub(super) async fn new() -> Result<Self> {
+        Ok(Self::with_model(Box::new(load_silero().await?)))
+    }
+
+    pub(super) fn with_model(model: Box<dyn SpeechProb>) -> Self {
+        let pre_buffer_duration = N_SAMPLES as f32 / 16_000.0;
+        Self {
+            model,
+            state: VADState::new(
+                0.5,
+                SEGMENT_SPEECH,
+                SEGMENT_SILENCE,
+                pre_buffer_duration,
+                0.0,
+                SEGMENT_MAX,
+                None,
+            ),
+            pending: Vec::with_capacity(N_SAMPLES),
+            heard_speech: false,
+        }
+    }
+
+    /// Starts over, for a new recording.
+    pub(super) fn reset(&mut self) {
+        self.state.reset();
+        self.pending.clear();
+        self.heard_speech = false;
+    }
+
+    /// Feeds 16kHz mono samples, returns whether a pause ended a segment.
+    pub(super) fn process(&mut self, samples: &[f32]) -> bool {
+        self.pending.extend_from_slice(samples);
+        let mut cut = false;
+        let mut frame = [0.0; N_SAMPLES];
+        let mut start = 0;
+        while self.pending.len() - start >= N_SAMPLES {
+            frame.copy_from_slice(&self.pending[start..start + N_SAMPLES]);
+            start += N_SAMPLES;
+            let speech_prob = self.model.speech_prob(&frame).unwrap_or_else(|err| {
+                warn!("VAD failed on a frame, assuming silence: {err}");
+                0.0
+            });
+            if speech_prob > self.state.threshold {
+                self.heard_speech = true;
+            }
+            if let Some(VADEvent::EndSpeech(_)) = self.state.process_frame(speech_prob, &frame) {
+                self.heard_speech = false;
+                cut = true;
+            }
+        }
+        self.pending.drain(..start);
+        cut
+    }
+
+    /// Whether a frame was speech since the last cut, even too short to
+    /// start a segment.
+    pub(super) fn heard_speech(&self) -> bool {
+        self.heard_speech
+    }
+}
+
+/// Runs the VAD on the whole frames of `buffer`, sending the utterances to
+/// `tx_audio`. Runs in the audio callback, so failures are logged, never
+/// panicking.
+fn process_frames(
+    buffer: &mut HeapRb<f32>,
+    vad_state: &mut VADState,
+    model: &mut dyn SpeechProb,
+    skip: &mut SilentSkip,
+    coast_prob: f32,
+    tx_audio: &UnboundedSender<Audio>,
+    disarm: &Disarm,
+) {
+    let mut frame = [0.0; N_SAMPLES];
+    while buffer.occupied_len() >= N_SAMPLES {
+        buffer.pop_slice(&mut frame);
+        let speech_prob = if skip.run_model(vad_state.state) {
+            // Treated like a skipped frame, keeping its audio
+            model.speech_prob(&frame).unwrap_or_else(|err| {
+                error!("VAD failed on a frame, assuming coast_prob: {err:#}");
+                coast_prob
+            })
+        } else {
+            coast_prob
+        };
+        match vad_state.process_frame(speech_prob, &frame) {
+            Some(VADEvent::StartSpeech) => {
+                info!("Speech detected");
+                if let Err(err) = tx_audio.send(Audio::Warm) {
+                    error!("Could not send the warm up event: {err}");
+                }
+            }
+            Some(VADEvent::Chunk(audio)) => {
+                info!("Speech going on, transcribing a chunk");
+                if tx_audio.send(Audio::Segment(audio)).is_err() {
+                    error!("Transcription stopped, dropping the chunk");
+                }
+            }
+            Some(VADEvent::EndSpeech(audio)) => {
+                info!("Speech finished");
+                if tx_audio.send(Audio::Sample(audio)).is_err() {
+                    error!("Transcription stopped, dropping the utterance");
+                }
+                disarm.utterance_done();
+            }
+            None => {}
+        }
     }
 }
 
@@ -218,6 +570,13 @@ impl VADState {
 /// stream configuration, and writing audio data to a WAV file.
 pub struct AudioRecorder {
     stream: Arc<Mutex<cpal::Stream>>,
+    disarm: Disarm,
+    /// Set when listening starts, the callback then warms up
+    warm_up: Arc<AtomicBool>,
+    /// Set when listening is cancelled, the callback then drops the
+    /// utterance in progress
+    cancel: Arc<AtomicBool>,
+    rx_disarmed: UnboundedReceiver<()>,
 }
 
 pub const N_SAMPLES: usize = 512;
@@ -236,89 +595,29 @@ impl AudioRecorder {
     ///
     /// This function initializes the default audio input device, configures it
     /// for recording, and sets up the WAV file writer.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         config: &Config,
         threshold: f32,
         silence_duration: f32,
         speech_duration: f32,
         pre_buffer_duration: f32,
+        arm_once: bool,
+        coast_prob: f32,
+        silent_skip_factor: u32,
+        warmup_duration: f32,
+        vad_model: VadModel,
+        energy_threshold: f32,
+        max_utterance_secs: f32,
+        max_chunk_secs: Option<f32>,
         tx_audio: UnboundedSender<Audio>,
     ) -> Result<Self> {
+        check_microphone()?;
         let host = cpal::default_host();
-        debug!("Available hosts: {:?}", cpal::available_hosts());
-        debug!("Default host: {:?}", host.id());
-
-        let devices = host.input_devices()?;
-        let names: HashSet<_> = devices.into_iter().flat_map(|d| d.name()).collect();
-        debug!("Available input devices: {names:?}");
-
-        let mut devices = host.input_devices()?;
-        // Find the requested device or use default
-        let device = if let Some(device_name) = &config.audio.device {
-            devices
-                .find(|d| {
-                    if let Ok(name) = d.name() {
-                        name == *device_name
-                    } else {
-                        false
-                    }
-                })
-                .ok_or_else(|| {
-                    anyhow!(
-                        "Requested audio device '{}' not found, available: {:?}",
-                        device_name,
-                        names
-                    )
-                })?
-        } else {
-            host.default_input_device()
-                .ok_or_else(|| anyhow!("No default input device found"))?
-        };
-
-        info!("Using input device: {}", device.name()?);
-
-        // Try to find a supported configuration that matches what we want
-        let stream_config = if let Ok(supported_configs) = device.supported_input_configs() {
-            let mut stream_config = None;
-
-            for config_range in supported_configs {
-                let sample_rate = cpal::SampleRate(config.audio.sample_rate);
-                if config_range.min_sample_rate() <= sample_rate
-                    && config_range.max_sample_rate() >= sample_rate
-                    && config_range.sample_format() == config.audio.sample_format.into()
-                {
-                    stream_config = Some(config_range.with_sample_rate(sample_rate));
-                    break;
-                }
-            }
-            stream_config
-        } else {
-            None
-        };
-        let stream_config = if let Some(stream_config) = stream_config {
-            Some(stream_config)
-        } else {
-            debug!("Could not find supported configs");
-            if let Ok(default_config) = device.default_input_config() {
-                debug!("Device default config: {:?}", default_config);
-                Some(default_config)
-            } else {
-                warn!("Could not default_config");
-                None
-            }
-        };
-
-        // If we can't find an exact match, use the default config
-        let stream_config = stream_config.unwrap_or_else(|| {
-            warn!("Falling back to config defined configuration, It might not work");
-            SupportedStreamConfig::new(
-                config.audio.channels,
-                cpal::SampleRate(config.audio.sample_rate),
-                cpal::SupportedBufferSize::Unknown,
-                config.audio.sample_format.into(),
-            )
-        });
+        let input = input_device(&host, &config.audio)?;
 
+        let stream_config = input.stream_config(&config.audio);
+        let device = input.device;
         debug!("Using stream config: {:?}", stream_config);
 
         let err_fn = move |err| {
@@ -326,137 +625,93 @@ impl AudioRecorder {
         };
 
         let mut buffer = HeapRb::new(16000 * 2); // 2 seconds buffer at 16kHz
-        let mut temp_chunk = [0.0; N_SAMPLES];
-        let sample_rate = 16_000;
-        let api = ApiBuilder::from_env().build()?;
-        let model = api.model("Narsil/silero".to_string());
-        let model_path = model.get("silero_vad.onnx").await?;
-        let mut silero = Silero::new(sample_rate, model_path)?;
+        let mut model = load_model(vad_model, energy_threshold).await?;
         let mut vad_state = VADState::new(
             threshold,
             speech_duration,
             silence_duration,
             pre_buffer_duration,
+            warmup_duration,
+            max_utterance_secs,
+            max_chunk_secs,
         );
 
-        // Create resampler if needed
-        let resampler = if stream_config.sample_rate().0 != config.audio.sample_rate
-            || stream_config.channels() != config.audio.channels
-            || stream_config.sample_format() != cpal::SampleFormat::F32
-        {
-            if stream_config.sample_format() != cpal::SampleFormat::F32 {
-                todo!("Unimplemented resampling samples");
-            }
-            Some(Resample {
-                samplerate_in: stream_config.sample_rate().0,
-                samplerate_out: 16000,
-                in_channels: stream_config.channels(),
-            })
-        } else {
-            None
-        };
+        // The VAD needs 16kHz mono, whatever the configured format
+        let mut resampler = Resample::for_model(
+            stream_config.sample_rate().0,
+            stream_config.channels(),
+            downmix_weights(&config.audio, stream_config.channels()),
+            config.audio.downmix_mode,
+            config.audio.sample_rate_tolerance,
+        )?;
 
-        // let recording_path = config.paths.recording_path.clone();
-        // let wav_spec = Self::create_wav_spec(&config.audio);
-
-        // let recording_path2 = recording_path.clone();
-
-        let mut i = 0;
-        let stream = Arc::new(Mutex::new(
-            device
-                .build_input_stream(
-                    &stream_config.into(),
-                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                        let data = if let Some(resampler) = resampler {
-                            // Convert the input samples to f32
-                            let samples: Vec<f32> = data.to_vec();
-
-                            // Resample the stereo audio to the desired sample rate
-                            let resampled_stereo: Vec<f32> = audio_resample(
-                                &samples,
-                                resampler.samplerate_in,
-                                resampler.samplerate_out,
-                                resampler.in_channels,
-                            );
-
-                            let samples = if resampler.in_channels != 1 {
-                                let n = resampler.in_channels as usize;
-                                // Convert the resampled stereo audio to mono
-                                let mono_samples: Vec<_> = resampled_stereo
-                                    .chunks(n)
-                                    .map(|chunk| {
-                                        let mono_sample = (chunk.iter().sum::<f32>()) / n as f32; // Average channels
-                                        mono_sample
-                                    })
-                                    .collect();
-                                mono_samples
-                            } else {
-                                resampled_stereo
-                            };
-                            samples
-                        } else {
-                            data.to_vec()
-                        };
-
-                        // Write to WAV file
-                        let buf = &mut buffer;
-                        for &sample in &data {
-                            if buf.try_push(sample).is_err() {
-                                error!("Buffer full, dropping samples");
-                            }
-                        }
-
-                        // Process chunks of N_SAMPLES samples while we have enough data
-                        while buf.occupied_len() >= N_SAMPLES {
-                            i += 1;
-                            // Get a chunk of N_SAMPLES samples efficiently
-                            let n = buf.pop_slice(&mut temp_chunk);
-                            assert_eq!(n, N_SAMPLES, "Expected to pop N_SAMPLES from buffer");
-                            // Process the chunk
-                            let speech_prob: f32 =
-                                if vad_state.state == VADStateEnum::Silent && i % 1 != 0 {
-                                    0.4
-                                } else {
-                                    silero.calc_level(&temp_chunk).expect("Prob")
-                                };
-                            // Update VAD state and handle events
-                            if let Some(event) = vad_state.process_frame(speech_prob, &temp_chunk) {
-                                match event {
-                                    VADEvent::StartSpeech => {
-                                        tx_audio.send(Audio::Warm).expect("Send warm event");
-                                        info!("Speech detected");
-                                    }
-                                    VADEvent::EndSpeech(audio) => {
-                                        // TODO This is debugging audio range.
-                                        // if let Ok(mut writer) =
-                                        //     WavWriter::create(&recording_path2, wav_spec)
-                                        // {
-                                        //     for &sample in &audio {
-                                        //         writer.write_sample(sample).ok();
-                                        //     }
-                                        //     writer.finalize().ok();
-                                        // }
-                                        // info!(
-                                        //     "Wrote wav file at {} : {wav_spec:?}",
-                                        //     recording_path2.display()
-                                        // );
-
-                                        tx_audio
-                                            .send(Audio::Sample(audio))
-                                            .expect("Send the example");
-                                        info!("Speech finished");
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    err_fn,
-                    None,
-                )
-                .context("Failed to create audio stream")?,
-        ));
+        let dump = StreamDump::new(config)?;
+        let mut noise = NoiseSuppression::new(config);
+        let mut clipping = ClippingDetector::new(config);
+        let (disarm, rx_disarmed) = Disarm::new(arm_once);
+        let callback_disarm = disarm.clone();
+        let warm_up = Arc::new(AtomicBool::new(false));
+        let callback_warm_up = warm_up.clone();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let callback_cancel = cancel.clone();
+        let mut skip = SilentSkip::new(silent_skip_factor);
+        let stream = Arc::new(Mutex::new(build_input_stream(
+            &device,
+            &stream_config,
+            move |data| {
+                if !callback_disarm.is_armed() {
+                    // Until the recorder is stopped after the utterance
+                    return;
+                }
+                if callback_cancel.swap(false, Ordering::Relaxed) {
+                    vad_state.reset();
+                    buffer.clear();
+                }
+                if callback_warm_up.swap(false, Ordering::Relaxed) {
+                    vad_state.warm_up();
+                }
+                if let Some(clipping) = clipping.as_mut() {
+                    clipping.process(data);
+                }
+                let data = if let Some(resampler) = resampler.as_mut() {
+                    // Resample and downmix to 16kHz mono
+                    resampler.process(data)
+                } else {
+                    data.to_vec()
+                };
+                let data = match noise.as_mut() {
+                    Some(noise) => noise.process(&data),
+                    None => data,
+                };
+                if let Some(dump) = &dump {
+                    dump.send(&data);
+                }
 
-        let result = Self { stream };
+                for &sample in &data {
+                    if buffer.try_push(sample).is_err() {
+                        error!("Buffer full, dropping samples");
+                    }
+                }
+                process_frames(
+                    &mut buffer,
+                    &mut vad_state,
+                    model.as_mut(),
+                    &mut skip,
+                    coast_prob,
+                    &tx_audio,
+                    &callback_disarm,
+                );
+            },
+            err_fn,
+        )?));
+
+        let result = Self {
+            stream,
+            disarm,
+            warm_up,
+            cancel,
+            rx_disarmed,
+        };
 
         Ok(result)
     }
@@ -466,6 +721,8 @@ impl AudioRecorder {
     /// This function begins capturing audio from the input device and writing
     /// it to the WAV file.
     pub fn start_recording(&self) -> Result<()> {
+        self.disarm.arm();
+        self.warm_up.store(true, Ordering::Relaxed);
         self.stream.lock().unwrap().play()?;
         Ok(())
     }
@@ -478,6 +735,20 @@ impl AudioRecorder {
         self.stream.lock().unwrap().pause()?;
         Ok(())
     }
+
+    /// Stops listening, dropping the utterance in progress instead of
+    /// resuming it when listening starts again.
+    pub fn cancel_recording(&self) -> Result<()> {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.stop_recording()
+    }
+
+    /// Resolves when the recorder disarmed itself after an utterance.
+    pub async fn disarmed(&mut self) {
+        if self.rx_disarmed.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -488,10 +759,13 @@ mod tests {
 
     fn create_test_vad_state() -> VADState {
         VADState::new(
-            0.5, // threshold
-            0.1, // speech_duration (100ms)
-            0.1, // silence_duration (100ms)
-            0.1, // pre_buffer_duration (500ms)
+            0.5,  // threshold
+            0.1,  // speech_duration (100ms)
+            0.1,  // silence_duration (100ms)
+            0.1,  // pre_buffer_duration (500ms)
+            0.1,  // warmup_duration (100ms)
+            60.0, // max_utterance_duration
+            None, // max_chunk_duration
         )
     }
 
@@ -596,4 +870,352 @@ mod tests {
         assert_eq!(n, test_samples.len());
         assert_eq!(buffer, test_samples);
     }
+
+    #[test]
+    fn test_warmup_suppresses_speech() {
+        let mut state = create_test_vad_state();
+        // Only once listening starts
+        assert!(state.process_frame(0.6, &[0.0; N_SAMPLES]).is_none());
+        assert_eq!(state.state, VADStateEnum::SpeechDetected);
+
+        state.warm_up();
+        // 100ms, 4 frames of loud noise ignored
+        for _ in 0..4 {
+            assert!(state.process_frame(0.9, &[1.0; N_SAMPLES]).is_none());
+            assert_eq!(state.state, VADStateEnum::Silent);
+        }
+        assert!(state.process_frame(0.6, &[0.1; N_SAMPLES]).is_none());
+        assert_eq!(state.state, VADStateEnum::SpeechDetected);
+        let mut event = None;
+        while event.is_none() {
+            event = state.process_frame(0.6, &[0.1; N_SAMPLES]);
+        }
+        assert_eq!(event, Some(VADEvent::StartSpeech));
+        // The noise isn't part of the utterance
+        let mut samples = vec![0.0; state.audio_buffer.occupied_len()];
+        state.audio_buffer.pop_slice(&mut samples);
+        assert!(samples.iter().all(|&sample| sample == 0.1));
+    }
+
+    #[test]
+    fn test_cancel_drops_utterance() {
+        let speaking = || {
+            let mut state = create_test_vad_state();
+            state.speech_threshold_samples = N_SAMPLES;
+            state.silence_threshold_samples = N_SAMPLES;
+            assert_eq!(
+                state.process_frame(0.6, &[0.1; N_SAMPLES]),
+                Some(VADEvent::StartSpeech)
+            );
+            state
+        };
+        // Stopped then restarted, the utterance goes on and ends
+        let mut state = speaking();
+        state.warm_up();
+        assert!(matches!(
+            state.process_frame(0.4, &[0.0; N_SAMPLES]),
+            Some(VADEvent::EndSpeech(_))
+        ));
+
+        // Cancelled, nothing is left to end
+        let mut state = speaking();
+        state.reset();
+        state.warm_up();
+        for _ in 0..8 {
+            assert_eq!(state.process_frame(0.4, &[0.0; N_SAMPLES]), None);
+        }
+        assert_eq!(state.audio_buffer.occupied_len(), 0);
+    }
+
+    /// Records an utterance of `frames` speech frames and a silent one, with a
+    /// 4 frames audio buffer (instead of 60s) and a 1 frame pre-buffer
+    fn record_frames(state: &mut VADState, frames: usize) -> Result<Vec<f32>> {
+        state.audio_buffer = HeapRb::new(4 * N_SAMPLES);
+        state.pre_buffer = HeapRb::new(N_SAMPLES);
+        state.speech_threshold_samples = N_SAMPLES;
+        state.silence_threshold_samples = N_SAMPLES;
+        assert_eq!(
+            state.process_frame(0.6, &[0.0; N_SAMPLES]),
+            Some(VADEvent::StartSpeech)
+        );
+        for i in 1..frames {
+            assert_eq!(state.process_frame(0.6, &[i as f32; N_SAMPLES]), None);
+        }
+        match state.process_frame(0.4, &[-1.0; N_SAMPLES]) {
+            Some(VADEvent::EndSpeech(samples)) => Ok(samples),
+            event => Err(anyhow::anyhow!("Expected end of speech, got {event:?}")),
+        }
+    }
+
+    #[test]
+    fn test_split_point() {
+        let mut samples = vec![0.5; 8 * N_SAMPLES];
+        // Nothing quieter, the latest frame
+        assert_eq!(
+            split_point(&samples, 4 * N_SAMPLES),
+            7 * N_SAMPLES + N_SAMPLES / 2
+        );
+        // Too short to look for a frame
+        assert_eq!(split_point(&samples, N_SAMPLES - 1), 8 * N_SAMPLES);
+        assert_eq!(split_point(&samples[..10], N_SAMPLES), 10);
+
+        // The middle of the quiet frame
+        samples[5 * N_SAMPLES..6 * N_SAMPLES].fill(0.0);
+        assert_eq!(
+            split_point(&samples, 4 * N_SAMPLES),
+            5 * N_SAMPLES + N_SAMPLES / 2
+        );
+        // Even when not aligned with the end, the cut still falls in the pause
+        let unaligned = &samples[..samples.len() - 100];
+        let split = split_point(unaligned, 4 * N_SAMPLES);
+        assert!((5 * N_SAMPLES..6 * N_SAMPLES).contains(&split), "{split}");
+        // Only within the searched end
+        assert_eq!(
+            split_point(&samples, 2 * N_SAMPLES),
+            7 * N_SAMPLES + N_SAMPLES / 2
+        );
+    }
+
+    #[test]
+    fn test_chunks_continue_seamlessly() {
+        // 1s chunks, speech starting after 4 frames with a 0.1s pre-buffer
+        let mut state = VADState::new(0.5, 0.1, 0.1, 0.1, 0.0, 60.0, Some(1.0));
+        let mut heard = Vec::new();
+        let mut chunks = Vec::new();
+        let mut end = None;
+        let frames = (0..3 * 16_000 / N_SAMPLES)
+            .map(|i| (0.9, i))
+            .chain((0..4).map(|_| (0.1, 0)));
+        for (speech_prob, i) in frames {
+            let frame: [f32; N_SAMPLES] = std::array::from_fn(|j| {
+                if i == 25 || speech_prob < 0.5 {
+                    // A pause between words
+                    0.0
+                } else {
+                    0.1 + (i * N_SAMPLES + j) as f32 / 1e6
+                }
+            });
+            heard.extend_from_slice(&frame);
+            match state.process_frame(speech_prob, &frame) {
+                Some(VADEvent::Chunk(samples)) => chunks.push(samples),
+                Some(VADEvent::EndSpeech(samples)) => end = Some(samples),
+                _ => {}
+            }
+        }
+        // Between half and the whole of max_chunk_secs
+        assert!(chunks.len() >= 2);
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| (8_000..=16_000).contains(&chunk.len()))
+        );
+        // Cut in the middle of the pause
+        assert_eq!(chunks[0].last(), Some(&0.0));
+        assert_eq!(chunks[1].first(), Some(&0.0));
+        let pause = chunks[0]
+            .iter()
+            .rev()
+            .take_while(|&&sample| sample == 0.0)
+            .count();
+        assert_eq!(pause, N_SAMPLES / 2);
+
+        // Nothing dropped nor repeated, from the pre-buffer on
+        let mut recorded = chunks.concat();
+        recorded.extend(end.unwrap_or_default());
+        assert_eq!(recorded, heard[4 * N_SAMPLES - 1600..]);
+    }
+
+    #[test]
+    fn test_max_utterance_buffer() {
+        let state = |max_utterance_duration| {
+            VADState::new(0.5, 0.1, 0.1, 0.1, 0.0, max_utterance_duration, None)
+        };
+        assert_eq!(state(60.0).audio_buffer.capacity().get(), 16_000 * 60);
+        assert_eq!(state(2.5).audio_buffer.capacity().get(), 40_000);
+        // Never shorter than a frame
+        assert_eq!(state(0.001).audio_buffer.capacity().get(), N_SAMPLES);
+
+        // A longer utterance keeps its last second
+        let mut state = state(1.0);
+        let speech = (0..2 * 16_000 / N_SAMPLES).map(|i| (0.9, i as f32));
+        let silence = (0..4).map(|_| (0.1, -1.0));
+        let ends: Vec<Vec<f32>> = speech
+            .chain(silence)
+            .filter_map(
+                |(prob, sample)| match state.process_frame(prob, &[sample; N_SAMPLES]) {
+                    Some(VADEvent::EndSpeech(samples)) => Some(samples),
+                    _ => None,
+                },
+            )
+            .collect();
+        assert_eq!(ends.len(), 1);
+        assert_eq!(ends[0].len(), 16_000);
+        assert_eq!(ends[0][ends[0].len() - 1], -1.0);
+        assert!(state.overflowed);
+    }
+
+    #[test]
+    fn test_audio_buffer_exactly_full() -> Result<()> {
+        let mut state = create_test_vad_state();
+        // The pre-buffer, 2 frames and the final silent frame fill it exactly
+        let samples = record_frames(&mut state, 3)?;
+        assert_eq!(samples.len(), 4 * N_SAMPLES);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[samples.len() - 1], -1.0);
+        assert!(!state.overflowed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_buffer_overflow_drops_oldest() -> Result<()> {
+        let mut state = create_test_vad_state();
+        // One frame past capacity: the pre-buffer frame is dropped
+        let samples = record_frames(&mut state, 4)?;
+        assert_eq!(samples.len(), 4 * N_SAMPLES);
+        assert_eq!(samples[0], 1.0);
+        assert_eq!(samples[samples.len() - 1], -1.0);
+        assert!(state.overflowed);
+
+        // The next utterance starts over
+        let samples = record_frames(&mut state, 2)?;
+        assert_eq!(samples.len(), 3 * N_SAMPLES);
+        assert!(!state.overflowed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_larger_than_buffer() {
+        let mut buffer = HeapRb::new(N_SAMPLES / 2);
+        let _ = buffer.push_slice(&[-1.0; 10]);
+        let samples: Vec<f32> = (0..N_SAMPLES).map(|i| i as f32).collect();
+        assert_eq!(
+            push_dropping_oldest(&mut buffer, &samples),
+            10 + N_SAMPLES / 2
+        );
+        // Only the newest samples are kept
+        let mut kept = vec![0.0; N_SAMPLES];
+        let n = buffer.pop_slice(&mut kept);
+        assert_eq!(kept[..n], samples[N_SAMPLES / 2..]);
+    }
+
+    /// Loud frames are speech, failing on NaN.
+    struct Loudness;
+
+    impl SpeechProb for Loudness {
+        fn speech_prob(&mut self, frame: &[f32; N_SAMPLES]) -> Result<f32> {
+            if frame[0].is_nan() {
+                anyhow::bail!("Inference failed");
+            }
+            Ok(frame.iter().fold(0.0, |max: f32, s| max.max(s.abs())))
+        }
+    }
+
+    #[test]
+    fn test_process_frames_never_panics() {
+        let mut state = create_test_vad_state();
+        let mut buffer = HeapRb::new(16000);
+        let mut skip = SilentSkip::new(1);
+        let (disarm, mut rx_disarmed) = Disarm::new(true);
+        let (tx, rx) = unbounded_channel();
+        // The app shut down
+        drop(rx);
+        let mut feed = |sample: f32, frames: usize| {
+            let _ = buffer.push_slice(&vec![sample; frames * N_SAMPLES]);
+            process_frames(
+                &mut buffer,
+                &mut state,
+                &mut Loudness,
+                &mut skip,
+                0.0,
+                &tx,
+                &disarm,
+            );
+        };
+        // Inference fails, assumed silent
+        feed(f32::NAN, 2);
+        // An utterance nobody receives
+        feed(0.9, 10);
+        feed(0.0, 10);
+        // Still done, listening stops
+        assert!(rx_disarmed.try_recv().is_ok());
+        assert_eq!(state.state, VADStateEnum::Silent);
+    }
+
+    #[test]
+    fn test_long_pre_buffer() {
+        // More pre-buffer than the audio buffer holds, its oldest samples
+        // are dropped
+        let mut state = create_test_vad_state();
+        state.audio_buffer = HeapRb::new(N_SAMPLES);
+        state.pre_buffer = HeapRb::new(2 * N_SAMPLES);
+        state.speech_threshold_samples = 2 * N_SAMPLES;
+        assert_eq!(state.process_frame(0.6, &[0.1; N_SAMPLES]), None);
+        assert_eq!(
+            state.process_frame(0.6, &[0.2; N_SAMPLES]),
+            Some(VADEvent::StartSpeech)
+        );
+        let mut samples = vec![0.0; N_SAMPLES];
+        assert_eq!(state.audio_buffer.pop_slice(&mut samples), N_SAMPLES);
+        assert!(samples.iter().all(|&sample| sample == 0.2));
+    }
+
+    #[test]
+    fn test_silent_skip() {
+        let runs = |skip: &mut SilentSkip, state, frames| -> Vec<bool> {
+            (0..frames).map(|_| skip.run_model(state)).collect()
+        };
+        // Every frame by default
+        let mut skip = SilentSkip::new(1);
+        assert!(
+            runs(&mut skip, VADStateEnum::Silent, 5)
+                .iter()
+                .all(|&run| run)
+        );
+
+        // One silent frame in 3, starting with the first one
+        let mut skip = SilentSkip::new(3);
+        assert_eq!(
+            runs(&mut skip, VADStateEnum::Silent, 7),
+            [true, false, false, true, false, false, true]
+        );
+        // Always once speech may have started
+        for state in [
+            VADStateEnum::SpeechDetected,
+            VADStateEnum::Recording,
+            VADStateEnum::SilenceDetected,
+        ] {
+            assert!(runs(&mut skip, state, 5).iter().all(|&run| run));
+        }
+        // Back to silent, the next frame runs right away
+        assert_eq!(
+            runs(&mut skip, VADStateEnum::Silent, 4),
+            [true, false, false, true]
+        );
+
+        // Never 0
+        let mut skip = SilentSkip::new(0);
+        assert!(
+            runs(&mut skip, VADStateEnum::Silent, 3)
+                .iter()
+                .all(|&run| run)
+        );
+    }
+
+    #[test]
+    fn test_arm_once_disarms_after_utterance() {
+        let (disarm, mut rx) = Disarm::new(true);
+        assert!(disarm.is_armed());
+        disarm.utterance_done();
+        assert!(!disarm.is_armed());
+        assert!(rx.try_recv().is_ok());
+        // Pressing the keys again re-arms for the next utterance
+        disarm.arm();
+        assert!(disarm.is_armed());
+
+        // Continuous listening is never disarmed
+        let (disarm, mut rx) = Disarm::new(false);
+        disarm.utterance_done();
+        assert!(disarm.is_armed());
+        assert!(rx.try_recv().is_err());
+    }
 }
@@ -32,8 +32,8 @@ impl Silero {
         let session = Session::builder()?
             .with_execution_providers([provider])?
             .commit_from_file(model_path)?;
-        let state = ArrayD::<f32>::zeros([2, 1, 128].as_slice());
-        let sample_rate = Array::from_shape_vec([1], vec![sample_rate]).unwrap();
+        let state = initial_state();
+        let sample_rate = Array::from_elem([1], sample_rate);
         let frame = Array2::<f32>::zeros([1, N_SAMPLES]);
         Ok(Self {
             frame,
@@ -47,19 +47,39 @@ impl Silero {
         self.frame.iter_mut().zip(audio_frame).for_each(|(s, ns)| {
             *s = *ns;
         });
-        let inps = ort::inputs![
-            self.frame.clone(),
-            std::mem::take(&mut self.state),
-            self.sample_rate.clone(),
-        ]?;
+        let state = std::mem::take(&mut self.state);
+        let (state, level) = match self.run(state) {
+            Ok(output) => output,
+            Err(err) => {
+                // The next frame starts over instead of failing as well
+                self.state = initial_state();
+                return Err(err);
+            }
+        };
+        self.state = state;
+        Ok(level)
+    }
+
+    /// Runs the model on the frame from `state`, the next state and the speech
+    /// probability. Errors rather than panics, this runs in the audio callback.
+    fn run(&mut self, state: ArrayD<f32>) -> Result<(ArrayD<f32>, f32), ort::Error> {
+        let inps = ort::inputs![self.frame.clone(), state, self.sample_rate.clone()]?;
         let res = self.session.run(SessionInputs::ValueSlice::<3>(&inps))?;
-        self.state = res["stateN"].try_extract_tensor().unwrap().to_owned();
-        let output = *res["output"]
-            .try_extract_raw_tensor::<f32>()
-            .unwrap()
-            .1
+        let value = |name| {
+            res.get(name)
+                .ok_or_else(|| ort::Error::new(format!("The Silero model has no {name} output")))
+        };
+        let state = value("stateN")?.try_extract_tensor()?.to_owned();
+        let (_, output) = value("output")?.try_extract_raw_tensor::<f32>()?;
+        let level = output
             .first()
-            .unwrap();
-        Ok(output)
+            .copied()
+            .ok_or_else(|| ort::Error::new("The Silero model output no probability"))?;
+        Ok((state, level))
     }
 }
+
+/// The model's recurrent state before the first frame.
+fn initial_state() -> ArrayD<f32> {
+    ArrayD::<f32>::zeros([2, 1, 128].as_slice())
+}
@@ -3,30 +3,140 @@
 //! This module provides functionality for loading and managing application
 //! configuration, including audio recording settings and model parameters.
 
-use anyhow::{Context, Result};
-use log::error;
+use anyhow::{Context, Result, bail};
+use log::{error, info};
 use notify_rust::Notification;
 use rdev::Key;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
+use crate::formatting;
+
+/// How `audio.device` selects the input device.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Device {
+    /// `"C920"` or `{ name = "C920" }`: an exact name match wins, otherwise
+    /// a case-insensitive substring is accepted
+    Name(String),
+    /// `{ index = 2 }`: the position in `whispering list-devices`
+    Index(usize),
+}
+
+impl Serialize for Device {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        match self {
+            Self::Name(name) => serializer.serialize_str(name),
+            Self::Index(index) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("index", index)?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Device {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{self, MapAccess, Visitor};
+        use std::fmt;
+
+        struct DeviceVisitor;
+
+        impl<'de> Visitor<'de> for DeviceVisitor {
+            type Value = Device;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a device name, { name = \"...\" } or { index = 2 }")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Device, E> {
+                Ok(Device::Name(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Device, A::Error> {
+                let Some(field) = map.next_key::<String>()? else {
+                    return Err(de::Error::missing_field("name"));
+                };
+                let device = match field.as_str() {
+                    "name" => Device::Name(map.next_value()?),
+                    "index" => Device::Index(map.next_value()?),
+                    _ => return Err(de::Error::unknown_field(&field, &["name", "index"])),
+                };
+                if let Some(field) = map.next_key::<String>()? {
+                    return Err(de::Error::custom(format!(
+                        "unexpected `{field}`, set either `name` or `index`"
+                    )));
+                }
+                Ok(device)
+            }
+        }
+
+        deserializer.deserialize_any(DeviceVisitor)
+    }
+}
+
 /// Audio recording configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AudioConfig {
     /// Number of audio channels (1 for mono, 2 for stereo)
     pub channels: u16,
-    /// Sample rate in Hz
+    /// Sample rate in Hz requested from the device and used for the saved
+    /// recording. The VAD and Whisper always get 16kHz audio
     pub sample_rate: u32,
     /// Sample format (F32 or I16)
     pub sample_format: SampleFormat,
-    /// Audio input device name (e.g., "sysdefault:CARD=C920")
+    /// Formats tried in order when the device doesn't support
+    /// `sample_format`, before falling back to its default format
+    #[serde(default = "default_sample_format_preference")]
+    pub sample_format_preference: Vec<SampleFormat>,
+    /// Audio input device, by name (e.g., "sysdefault:CARD=C920") or by
+    /// index (`{ index = 2 }`), see [`Device`].
     /// If not specified, the default device will be used
-    pub device: Option<String>,
+    pub device: Option<Device>,
+    /// Warn (once) when the proportion of clipped samples in an audio chunk
+    /// exceeds this ratio (e.g. 0.01 for 1%). Disabled if not specified
+    #[serde(default)]
+    pub clipping_threshold: Option<f32>,
+    /// Devices never picked when falling back to the default input (no
+    /// `device` set), matched by case-insensitive substring
+    #[serde(default)]
+    pub device_blacklist: Vec<String>,
+    /// Also list the output devices as `"<name> (loopback)"`, to transcribe
+    /// the system audio through `device` (Windows only)
+    #[serde(default)]
+    pub loopback: bool,
+    /// Per channel weights used to downmix to mono, e.g. `[0.5, -0.5]` for
+    /// the side of a mid/side mic. Empty averages the channels
+    #[serde(default)]
+    pub downmix_weights: Vec<f32>,
+    /// How the channels are mixed down to mono
+    #[serde(default)]
+    pub downmix_mode: DownmixMode,
+    /// Remove steady background noise before VAD and transcription,
+    /// requires the `denoise` feature
+    #[serde(default)]
+    pub noise_suppression: bool,
+    /// Noise suppression strength, from 0.0 (none) to 1.0 (aggressive)
+    #[serde(default = "default_noise_suppression_strength")]
+    pub noise_suppression_strength: f32,
+    /// Mono streams within this many Hz of 16kHz (e.g. 16001Hz) are given
+    /// to the VAD and Whisper as is instead of being resampled
+    #[serde(default)]
+    pub sample_rate_tolerance: u32,
+}
+
+fn default_noise_suppression_strength() -> f32 {
+    0.5
+}
+
+fn default_sample_format_preference() -> Vec<SampleFormat> {
+    vec![SampleFormat::F32, SampleFormat::I16]
 }
 
 impl From<SampleFormat> for cpal::SampleFormat {
@@ -38,9 +148,22 @@ impl From<SampleFormat> for cpal::SampleFormat {
     }
 }
 
+/// How multi-channel audio is mixed down to mono.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownmixMode {
+    /// Weighted sum with `downmix_weights`, the mean without
+    #[default]
+    Average,
+    /// The loudest channel of each frame, keeps the level of speech picked
+    /// up by a single channel
+    Peak,
+    /// Only the first channel
+    First,
+}
+
 /// Sample format for audio recording.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum SampleFormat {
     /// 32-bit floating point samples
@@ -64,14 +187,22 @@ impl Default for AudioConfig {
             channels: 1,
             sample_rate: 16000,
             sample_format: SampleFormat::F32,
+            sample_format_preference: default_sample_format_preference(),
             device: None,
+            clipping_threshold: None,
+            device_blacklist: Vec::new(),
+            loopback: false,
+            downmix_weights: Vec::new(),
+            downmix_mode: DownmixMode::Average,
+            noise_suppression: false,
+            noise_suppression_strength: default_noise_suppression_strength(),
+            sample_rate_tolerance: 0,
         }
     }
 }
 
 /// Path configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct PathConfig {
     /// Cache directory for storing temporary files
@@ -80,14 +211,53 @@ pub struct PathConfig {
     pub recording_path: PathBuf,
 }
 
+impl PathConfig {
+    /// Validates `recording_path` and creates its parent directory.
+    ///
+    /// Catches a path pointing at a directory at startup rather than failing
+    /// with an obscure IO error when the first recording is written.
+    pub fn prepare_recording_path(&self) -> Result<()> {
+        let path = &self.recording_path;
+        if path.is_dir() {
+            bail!(
+                "recording_path {} is a directory, it must be a file such as {}",
+                path.display(),
+                path.join("recorded.wav").display()
+            );
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).context(format!(
+                "Creating the recording_path directory {}",
+                parent.display()
+            ))?;
+        }
+        Ok(())
+    }
+}
+
 /// Type of activation for recording control
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
 pub enum Trigger {
     /// Use keyboard shortcuts for activation
     /// Will send on release
-    PushToTalk,
+    ///
+    /// A unit variant before its options were added, build it with
+    /// [`Trigger::push_to_talk`] and match it with `Trigger::PushToTalk { .. }`.
+    PushToTalk {
+        /// Transcribe the pauses-separated segments while the keys are
+        /// still held, instead of everything on release
+        #[serde(default)]
+        streaming: bool,
+        /// Keep recording this long (in milliseconds) after the keys are
+        /// released, capturing the end of the last word
+        #[serde(default)]
+        release_grace_ms: u64,
+        /// Amount of audio to keep before the keys are pressed (in seconds),
+        /// capturing the first word. Keeps the microphone open
+        #[serde(default)]
+        pre_buffer_duration: f32,
+    },
     /// Press shortcut to start recording, press again to stop
     Toggle,
     /// Use keyboard shortcuts to start VAD
@@ -107,9 +277,80 @@ pub enum Trigger {
         /// Amount of audio to keep before voice detection (in seconds)
         #[serde(default = "default_1")]
         pre_buffer_duration: f32,
+        /// Stop listening after the first utterance, so every key press
+        /// records exactly one utterance
+        #[serde(default)]
+        arm_once: bool,
+        /// Speech probability assumed for the frames skipped (not run through
+        /// the model) while silent. Must stay below `threshold`, otherwise
+        /// skipped frames alone would start a recording
+        #[serde(default)]
+        coast_prob: f32,
+        /// While silent, run the model on one frame out of this many, the
+        /// others assumed to have `coast_prob`. 1 runs it on every frame
+        #[serde(default = "default_silent_skip_factor")]
+        silent_skip_factor: u32,
+        /// Start listening when the application launches, the keys (if any)
+        /// pause and resume
+        #[serde(default)]
+        auto_start: bool,
+        /// Time after listening starts (in seconds) during which speech can't
+        /// start a recording, while the microphone's gain control settles
+        #[serde(default)]
+        warmup_duration: f32,
+        /// Model telling speech from silence
+        #[serde(default)]
+        vad_model: VadModel,
+        /// RMS level above which a frame is speech for the energy VAD
+        #[serde(default = "default_energy_threshold")]
+        energy_threshold: f32,
+        /// Longest utterance kept (in seconds), the beginning of longer ones
+        /// is dropped
+        #[serde(default = "default_max_utterance_secs")]
+        max_utterance_secs: f32,
+        /// Transcribe continuous speech in chunks of at most this long (in
+        /// seconds), cut at a quiet moment. Whole utterances if not specified
+        #[serde(default)]
+        max_chunk_secs: Option<f32>,
     },
 }
 
+/// Voice activity detection model of the `toggle_vad` trigger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VadModel {
+    /// The Silero model, downloaded on first use
+    #[default]
+    Silero,
+    /// Silero, the energy VAD when it can't be loaded (offline)
+    SileroOrEnergy,
+    /// A frame is speech when louder than `energy_threshold`, much less
+    /// accurate but needs no model
+    Energy,
+}
+
+impl Trigger {
+    /// Push-to-talk with the default options, as `{ type = "push_to_talk" }`.
+    pub fn push_to_talk() -> Self {
+        Trigger::PushToTalk {
+            streaming: false,
+            release_grace_ms: 0,
+            pre_buffer_duration: 0.0,
+        }
+    }
+
+    /// Whether listening starts at launch, without a key press.
+    pub fn auto_starts(&self) -> bool {
+        matches!(
+            self,
+            Trigger::ToggleVad {
+                auto_start: true,
+                ..
+            }
+        )
+    }
+}
+
 fn default_2() -> f32 {
     2.0
 }
@@ -119,36 +360,312 @@ fn default_1() -> f32 {
 fn default_05() -> f32 {
     0.5
 }
+fn default_silent_skip_factor() -> u32 {
+    1
+}
+fn default_energy_threshold() -> f32 {
+    0.01
+}
+fn default_max_utterance_secs() -> f32 {
+    60.0
+}
 
 /// Recording activation configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ActivationConfig {
     /// Type of activation to use
     pub trigger: Trigger,
     /// Displays a notification about the capturing
     pub notify: bool,
+    /// Shows a notification while transcribing, replaced by the result
+    #[serde(default)]
+    pub notify_processing: bool,
     /// Automatically hit enter after sending the text
     pub autosend: bool,
-    /// Keys that need to be pressed in sequence
+    /// Minimum time between two autosend Return presses (in milliseconds),
+    /// for applications missing sends when VAD segments end in quick
+    /// succession
+    #[serde(default = "default_autosend_min_gap_ms")]
+    pub autosend_min_gap_ms: u64,
+    /// Wait before pasting (in milliseconds), for windows slow to accept
+    /// input after the notification stole the focus
+    #[serde(default)]
+    pub paste_delay_ms: u64,
+    /// Read the clipboard back before pasting, and type the text instead when
+    /// it doesn't hold it
+    #[serde(default)]
+    pub verify_paste: bool,
+    /// Where the transcript goes: pasted, typed key by key, or only copied to
+    /// the clipboard (no input simulated, not even `input_warmup`)
+    #[serde(default)]
+    pub output_mode: OutputMode,
+    /// MIME type of the pasted text on Wayland (`"text/plain;charset=utf-8"`),
+    /// autodetected if not specified
+    #[serde(default)]
+    pub clipboard_mime: Option<String>,
+    /// Keys that need to be pressed in sequence, by name (`"ControlLeft"`)
+    /// or by raw keycode (`{ code = 65 }`) for keys `rdev` doesn't name
+    #[serde(with = "activation_keys")]
     pub keys: HashSet<Key>,
+    /// Other key sets doing the same as `keys`, any of them triggers
+    #[serde(default, with = "activation_keys::sets")]
+    pub alternative_keys: Vec<HashSet<Key>>,
+    /// Keys pressed together during a recording to cancel it, its audio
+    /// being discarded instead of transcribed. Disabled when empty
+    #[serde(default, with = "activation_keys")]
+    pub cancel_keys: HashSet<Key>,
+    /// With the `push_to_talk` and `toggle` triggers, stop recording after
+    /// this long (in seconds) and transcribe, e.g. when a key is stuck.
+    /// Unlimited if not specified
+    #[serde(default)]
+    pub max_recording_secs: Option<f32>,
+    /// With autosend, wait for this key after pasting before hitting Return.
+    /// Without the confirmation the text is left for manual editing
+    #[serde(default)]
+    pub confirm_key: Option<Key>,
+    /// How long to wait for the confirm key, in milliseconds
+    #[serde(default = "default_confirm_timeout_ms")]
+    pub confirm_timeout_ms: u64,
+    /// Cycles the transcription language through `model.languages`
+    #[serde(default)]
+    pub language_key: Option<Key>,
+    /// Re-transcribes the last audio with `model.rerun_filename`, replacing
+    /// the previous paste
+    #[serde(default)]
+    pub rerun_key: Option<Key>,
+    /// Suppress a transcript identical to the previous `duplicate_limit`
+    /// ones (likely an echo), 0 disables the guard
+    #[serde(default)]
+    pub duplicate_limit: usize,
+    /// Only transcripts from the last `duplicate_window_ms` milliseconds count
+    /// as duplicates
+    #[serde(default = "default_duplicate_window_ms")]
+    pub duplicate_window_ms: u64,
+    /// Exit after this many seconds without dictation, releasing the
+    /// microphone and the model. Disabled if not specified
+    #[serde(default)]
+    pub idle_exit_timeout: Option<u64>,
+    /// Never record while the focused window's title contains one of these
+    /// (case-insensitive)
+    #[serde(default)]
+    pub blocked_apps: Vec<String>,
+    /// Input simulated at startup, so the first paste isn't lost
+    #[serde(default)]
+    pub input_warmup: InputWarmup,
+}
+
+/// Input simulated at startup to initialize synthetic input.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InputWarmup {
+    /// Press and release `key`, one of [`InputWarmup::KEYS`]
+    Key { key: Key },
+    /// No warmup, the first paste might be lost
+    None,
+}
+
+impl InputWarmup {
+    /// Keys without side effects on their own: tapping Meta opens launchers,
+    /// and Alt focuses menu bars.
+    pub const KEYS: &[Key] = &[
+        Key::ControlLeft,
+        Key::ControlRight,
+        Key::ShiftLeft,
+        Key::ShiftRight,
+    ];
+}
+
+impl Default for InputWarmup {
+    fn default() -> Self {
+        Self::Key {
+            key: Key::ControlLeft,
+        }
+    }
+}
+
+fn default_duplicate_window_ms() -> u64 {
+    30_000
+}
+
+fn default_confirm_timeout_ms() -> u64 {
+    2000
+}
+
+/// (De)serializes activation keys, accepting raw keycodes as a fallback.
+///
+/// Keys without a name in `rdev` are reported as `Key::Unknown(code)`, they
+/// are written `{ code = 65 }` in the config.
+mod activation_keys {
+    use rdev::Key;
+    use serde::de::{self, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashSet;
+    use std::fmt;
+
+    struct KeyDef(Key);
+
+    impl Serialize for KeyDef {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 {
+                Key::Unknown(code) => {
+                    let mut map = serializer.serialize_map(Some(1))?;
+                    map.serialize_entry("code", &code)?;
+                    map.end()
+                }
+                key => key.serialize(serializer),
+            }
+        }
+    }
+
+    struct KeyVisitor;
+
+    impl<'de> Visitor<'de> for KeyVisitor {
+        type Value = KeyDef;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a key name or a raw keycode like { code = 65 }")
+        }
+
+        fn visit_str<E: de::Error>(self, name: &str) -> Result<KeyDef, E> {
+            Key::deserialize(name.into_deserializer()).map(KeyDef)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<KeyDef, A::Error> {
+            let Some(field) = map.next_key::<String>()? else {
+                return Err(de::Error::missing_field("code"));
+            };
+            if field != "code" {
+                return Err(de::Error::unknown_field(&field, &["code"]));
+            }
+            Ok(KeyDef(Key::Unknown(map.next_value()?)))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for KeyDef {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(KeyVisitor)
+        }
+    }
+
+    pub fn serialize<S: Serializer>(keys: &HashSet<Key>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keys.iter().map(|&key| KeyDef(key)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashSet<Key>, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = HashSet<Key>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of keys")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut keys = HashSet::new();
+                while let Some(KeyDef(key)) = seq.next_element()? {
+                    keys.insert(key);
+                }
+                Ok(keys)
+            }
+        }
+
+        deserializer.deserialize_seq(KeysVisitor)
+    }
+
+    /// Several key sets, each (de)serialized like `keys`.
+    pub mod sets {
+        use super::KeyDef;
+        use rdev::Key;
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::collections::HashSet;
+
+        pub fn serialize<S: Serializer>(
+            sets: &[HashSet<Key>],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(
+                sets.iter()
+                    .map(|keys| keys.iter().map(|&key| KeyDef(key)).collect::<Vec<_>>()),
+            )
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<HashSet<Key>>, D::Error> {
+            let sets = Vec::<Vec<KeyDef>>::deserialize(deserializer)?;
+            Ok(sets
+                .into_iter()
+                .map(|keys| keys.into_iter().map(|KeyDef(key)| key).collect())
+                .collect())
+        }
+    }
+}
+
+impl ActivationConfig {
+    /// `keys` and the `alternative_keys`.
+    pub fn key_sets(&self) -> impl Iterator<Item = &HashSet<Key>> {
+        std::iter::once(&self.keys).chain(&self.alternative_keys)
+    }
+
+    /// Whether `key` is part of an activation key set.
+    pub fn is_activation_key(&self, key: &Key) -> bool {
+        self.key_sets().any(|keys| keys.contains(key))
+    }
+
+    /// Whether `pressed` is exactly one of the activation key sets. An empty
+    /// set never matches.
+    pub fn activated(&self, pressed: &HashSet<Key>) -> bool {
+        self.key_sets()
+            .any(|keys| !keys.is_empty() && keys == pressed)
+    }
+
+    /// Whether all the `cancel_keys` are in `pressed`. Never without cancel
+    /// keys.
+    pub fn cancelled(&self, pressed: &HashSet<Key>) -> bool {
+        !self.cancel_keys.is_empty() && self.cancel_keys.is_subset(pressed)
+    }
+}
+
+fn default_autosend_min_gap_ms() -> u64 {
+    150
 }
 
 impl Default for ActivationConfig {
     fn default() -> Self {
         Self {
-            trigger: Trigger::PushToTalk {},
+            trigger: Trigger::push_to_talk(),
             notify: true,
+            notify_processing: false,
             autosend: false,
+            autosend_min_gap_ms: default_autosend_min_gap_ms(),
+            paste_delay_ms: 0,
+            verify_paste: false,
+            output_mode: OutputMode::default(),
+            clipboard_mime: None,
             keys: HashSet::from([Key::ControlLeft, Key::Space]),
+            alternative_keys: Vec::new(),
+            cancel_keys: HashSet::new(),
+            max_recording_secs: None,
+            confirm_key: None,
+            confirm_timeout_ms: default_confirm_timeout_ms(),
+            language_key: None,
+            rerun_key: None,
+            duplicate_limit: 0,
+            duplicate_window_ms: default_duplicate_window_ms(),
+            idle_exit_timeout: None,
+            blocked_apps: Vec::new(),
+            input_warmup: InputWarmup::default(),
         }
     }
 }
 
 /// Main application configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// Audio recording settings
@@ -159,11 +676,269 @@ pub struct Config {
     pub model: ModelConfig,
     /// Recording activation configuration
     pub activation: ActivationConfig,
+    /// Transcript formatting configuration
+    #[serde(default)]
+    pub formatting: FormattingConfig,
+    /// Debugging and durability options
+    #[serde(default)]
+    pub debug: DebugConfig,
+    /// Commands run when recording starts and stops
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Recordings kept in memory after their transcription
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// Files the transcripts are written to
+    #[serde(default)]
+    pub output: OutputConfig,
+}
+
+/// Files the transcripts are written to, besides being pasted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Write the segments of each transcription as SRT subtitles to this
+    /// file. Disabled if not specified
+    #[serde(default)]
+    pub srt_path: Option<PathBuf>,
+    /// Whether each transcription replaces the SRT file or is appended to it
+    #[serde(default)]
+    pub srt_mode: SrtMode,
+}
+
+/// How each transcription is written to `output.srt_path`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SrtMode {
+    /// The file only holds the last transcription
+    #[default]
+    Overwrite,
+    /// The cues are added after the existing ones, numbered and timed after
+    /// them
+    Append,
+}
+
+/// Recordings kept in memory after their transcription, for re-running them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Number of recordings kept, the oldest are evicted first
+    #[serde(default = "default_history_entries")]
+    pub max_entries: usize,
+    /// Memory (in megabytes) the kept recordings can take, about 16 minutes
+    /// per 64MB
+    #[serde(default = "default_history_mb")]
+    pub max_mb: u64,
+}
+
+fn default_history_entries() -> usize {
+    1
+}
+
+fn default_history_mb() -> u64 {
+    64
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_history_entries(),
+            max_mb: default_history_mb(),
+        }
+    }
+}
+
+/// Commands run when recording starts and stops.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Shell command run when recording (or VAD listening) starts
+    #[serde(default)]
+    pub on_start: Option<String>,
+    /// Shell command run when recording (or VAD listening) stops
+    #[serde(default)]
+    pub on_stop: Option<String>,
+    /// Hooks still running after this long (in milliseconds) are killed
+    #[serde(default = "default_hook_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_hook_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_start: None,
+            on_stop: None,
+            timeout_ms: default_hook_timeout_ms(),
+        }
+    }
+}
+
+/// Debugging and durability options.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DebugConfig {
+    /// Save the audio being transcribed until it succeeds, and transcribe
+    /// audio left over by a crash on the next startup
+    #[serde(default)]
+    pub crash_recovery: bool,
+    /// Keep the audio of every transcription in `cache_dir/recordings`
+    #[serde(default)]
+    pub save_recordings: bool,
+    /// Keep at most this many saved recordings, deleting the oldest first.
+    /// Unlimited if not specified
+    #[serde(default)]
+    pub max_recordings: Option<usize>,
+    /// Keep at most this many megabytes of saved recordings, deleting the
+    /// oldest first. Unlimited if not specified
+    #[serde(default)]
+    pub max_recordings_mb: Option<u64>,
+    /// File format of the saved recordings
+    #[serde(default)]
+    pub recording_format: RecordingFormat,
+    /// Write everything the VAD listens to (not only the detected speech) to
+    /// `cache_dir/vad_stream.wav`
+    #[serde(default)]
+    pub vad_stream: bool,
+    /// Size of `vad_stream.wav` (in megabytes) after which it is moved to
+    /// `vad_stream.prev.wav` and a new one starts
+    #[serde(default = "default_vad_stream_max_mb")]
+    pub vad_stream_max_mb: u64,
+}
+
+fn default_vad_stream_max_mb() -> u64 {
+    64
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            crash_recovery: false,
+            save_recordings: false,
+            max_recordings: None,
+            max_recordings_mb: None,
+            recording_format: RecordingFormat::default(),
+            vad_stream: false,
+            vad_stream_max_mb: default_vad_stream_max_mb(),
+        }
+    }
+}
+
+/// What Whisper makes of the speech.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Task {
+    /// Text in the spoken language
+    #[default]
+    Transcribe,
+    /// English text, whatever the spoken language. Requires a multilingual
+    /// model
+    Translate,
+}
+
+/// Whether `filename` is an English-only model, e.g. `ggml-base.en.bin` or
+/// `ggml-tiny.en-q5_1.bin`.
+fn is_english_only(filename: &str) -> bool {
+    filename.contains(".en.") || filename.contains(".en-")
+}
+
+/// What is done with the transcript.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// Copy it to the clipboard and press the paste shortcut
+    #[default]
+    Paste,
+    /// Only copy it to the clipboard, no input is simulated
+    ClipboardOnly,
+    /// Type it key by key (US layout), for applications ignoring the paste
+    /// shortcut. Characters without a key are pasted.
+    Type,
+}
+
+/// What happens to a transcript below `model.min_confidence`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LowConfidence {
+    /// Notify to dictate again
+    #[default]
+    Prompt,
+    /// Drop it without notifying
+    Drop,
+}
+
+/// File formats of the saved recordings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// 32-bit float WAV, as transcribed
+    #[default]
+    Wav,
+    /// Lossless 16-bit FLAC, about a quarter of the WAV size. Requires the
+    /// `flac` feature
+    Flac,
+}
+
+/// Transcript formatting configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormattingConfig {
+    /// Turn spoken punctuation ("comma", "question mark", ...) into symbols
+    #[serde(default)]
+    pub spoken_punctuation: bool,
+    /// Unicode normalization form of the transcript, requires the
+    /// `formatting` feature. Disabled if not specified
+    #[serde(default)]
+    pub normalize: Option<Normalization>,
+    /// Strip diacritics ("café" becomes "cafe"), requires the `formatting`
+    /// feature
+    #[serde(default)]
+    pub ascii_fold: bool,
+    /// Replace smart quotes, dashes and ellipses with their ASCII forms
+    #[serde(default)]
+    pub straight_quotes: bool,
+    /// Remove symbols (emoji, music notes, currency, ...) and control
+    /// characters, requires the `formatting` feature
+    #[serde(default)]
+    pub strip_symbols: bool,
+    /// Symbols kept by `strip_symbols`, e.g. "$€"
+    #[serde(default)]
+    pub symbol_allowlist: String,
+    /// Code dictation: "dot" joins identifiers, no spaces around punctuation
+    /// and brackets, and capitalized words are lowercased except at the start
+    /// of a sentence
+    #[serde(default)]
+    pub code_mode: bool,
+    /// Text pasted before each transcript, with strftime placeholders
+    /// (`"[%H:%M] "`)
+    #[serde(default)]
+    pub prefix: String,
+    /// Text pasted after each transcript, with strftime placeholders
+    #[serde(default)]
+    pub suffix: String,
+    /// Start a new line between Whisper segments separated by a pause this
+    /// long (in seconds). Segments are joined with spaces if not specified
+    #[serde(default)]
+    pub paragraph_gap: Option<f32>,
+}
+
+/// Unicode normalization forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Canonical composition, e.g. "e" followed by a combining accent
+    /// becomes "é"
+    Nfc,
+    /// Compatibility composition, also folds ligatures ("ﬁ" becomes "fi"),
+    /// full-width forms, ...
+    Nfkc,
 }
 
 /// Type of prompt to use for the model
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PromptType {
     /// Use a list of vocabulary words joined by commas
@@ -174,6 +949,18 @@ pub enum PromptType {
     None,
 }
 
+/// Transcription engine
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Backend {
+    /// Whisper (whisper.cpp) running the model from `repo` and `filename`
+    #[default]
+    Whisper,
+    /// Transcribes any audio to `text`, to try the rest of the pipeline
+    /// (formatting, pasting, ...) without a model
+    Echo { text: String },
+}
+
 impl Default for PromptType {
     fn default() -> Self {
         Self::None
@@ -181,10 +968,12 @@ impl Default for PromptType {
 }
 
 /// Whisper model configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ModelConfig {
+    /// Transcription engine, Whisper unless set
+    #[serde(default)]
+    pub backend: Backend,
     /// Model repository on Hugging Face
     pub repo: String,
     /// Model filename
@@ -193,6 +982,220 @@ pub struct ModelConfig {
     pub prompt: PromptType,
     /// Map of text to replace with their replacements
     pub replacements: HashMap<String, String>,
+    /// Apply the replacements up to this many times, until nothing changes,
+    /// so a replacement can build on another one
+    #[serde(default = "default_replacement_passes")]
+    pub replacement_passes: usize,
+    /// Regex patterns replaced in order, after `replacements`
+    #[serde(default)]
+    pub regex_replacements: Vec<RegexReplacement>,
+    /// Force Whisper to output a single segment.
+    /// Best paired with short utterances (short VAD segments, voice commands)
+    /// to avoid over-segmentation and spurious leading/trailing tokens.
+    #[serde(default)]
+    pub single_segment: bool,
+    /// Suppress blank outputs at the beginning of the sampling
+    #[serde(default = "default_true")]
+    pub suppress_blank: bool,
+    /// Suppress non-speech tokens (music notes, brackets, ...), reduces hallucinations
+    #[serde(default)]
+    pub suppress_non_speech: bool,
+    /// Maximum number of tokens per segment, bounds runaway hallucinations.
+    /// 0 means no limit
+    #[serde(default)]
+    pub max_tokens: u32,
+    /// Threads decoding on the CPU, every available core if not specified
+    #[serde(default)]
+    pub n_threads: Option<usize>,
+    /// Sampling temperature of the first decoding, 0.0 being greedy
+    #[serde(default)]
+    pub temperature: f32,
+    /// Temperature added on each retry when a decoding fails Whisper's
+    /// quality checks (repetitions, low probability), up to 1.0. 0.0 never
+    /// retries
+    #[serde(default = "default_temperature_inc")]
+    pub temperature_inc: f32,
+    /// Compute per-token timestamps (logged with each transcript), for
+    /// word-level timing at some decoding cost
+    #[serde(default)]
+    pub token_timestamps: bool,
+    /// Token IDs the decoder never generates (the model's vocabulary indices)
+    #[serde(default)]
+    pub suppress_tokens: Vec<i32>,
+    /// Encoder context (in 20ms units, up to 1500 for 30s), smaller is faster
+    /// on short clips but audio past it is ignored. The full context if not
+    /// specified
+    #[serde(default)]
+    pub audio_ctx: Option<i32>,
+    /// Remove the prompt when Whisper echoes it at the very start of the
+    /// transcript
+    #[serde(default)]
+    pub strip_prompt_echo: bool,
+    /// Transcripts consisting of only one of these phrases (ignoring case
+    /// and punctuation) are Whisper hallucinations on silence, not pasted
+    #[serde(default = "default_hallucinations")]
+    pub hallucinations: Vec<String>,
+    /// Segments Whisper rates more likely than this (0.0 to 1.0) to hold no
+    /// speech are dropped, 1.0 keeps every segment
+    #[serde(default = "default_no_speech_threshold")]
+    pub no_speech_threshold: f32,
+    /// Transcripts whose mean token probability (0.0 to 1.0) is below this
+    /// aren't pasted, see `low_confidence`. Disabled if not specified
+    #[serde(default)]
+    pub min_confidence: Option<f32>,
+    /// What happens to the transcripts below `min_confidence`
+    #[serde(default)]
+    pub low_confidence: LowConfidence,
+    /// Load the model at startup instead of when recording starts, making the
+    /// first transcription faster at the cost of keeping the model in memory
+    #[serde(default)]
+    pub warm_on_start: bool,
+    /// Unload the model when no audio arrived for this long (in seconds)
+    /// after it was loaded, freeing its (GPU) memory. Kept loaded until the
+    /// next transcription if not specified
+    #[serde(default)]
+    pub unload_after_secs: Option<u64>,
+    /// Greedy `best_of` picked from the utterance duration: the first
+    /// breakpoint the utterance fits in wins, longer ones use `best_of = 1`
+    #[serde(default)]
+    pub adaptive_best_of: Vec<BestOfBreakpoint>,
+    /// Minimum trailing silence (in seconds) before transcription, padded
+    /// when missing. Whisper needs it to end sentences with punctuation
+    #[serde(default)]
+    pub min_trailing_silence: f32,
+    /// Transcription language (`"en"`, `"fr"`, ...), `"auto"` or not
+    /// specified to auto-detect it. Ignored when `languages` are set
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Transcribe in the spoken language, or translate to English
+    #[serde(default)]
+    pub task: Task,
+    /// Transcription languages (`"en"`, `"fr"`, ...) cycled with
+    /// `activation.language_key`, the first one is used at startup.
+    /// Empty uses `language`
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Larger model (filename in `repo`) re-transcribing the last audio on
+    /// `activation.rerun_key`. The last audio is kept in memory when set
+    #[serde(default)]
+    pub rerun_filename: Option<String>,
+    /// Abandon a transcription still running after this many seconds, so a
+    /// stuck one doesn't block the next utterances
+    #[serde(default = "default_run_timeout_secs")]
+    pub run_timeout_secs: u64,
+}
+
+fn default_run_timeout_secs() -> u64 {
+    600
+}
+
+/// Greedy `best_of` used for utterances up to `max_duration`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BestOfBreakpoint {
+    /// Longest utterance (in seconds) using this `best_of`
+    pub max_duration: f32,
+    /// Number of candidates sampled by the greedy decoder
+    pub best_of: i32,
+}
+
+fn default_replacement_passes() -> usize {
+    1
+}
+
+/// A `[pattern, replacement]` pair of `model.regex_replacements`, the
+/// pattern being compiled when the config is loaded.
+#[derive(Debug, Clone)]
+pub struct RegexReplacement {
+    pub regex: Regex,
+    /// May reference the pattern's groups, `$1` or `${name}`
+    pub replacement: String,
+}
+
+impl RegexReplacement {
+    pub fn new(pattern: &str, replacement: impl Into<String>) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .context(format!("Invalid regex_replacements pattern {pattern:?}"))?;
+        Ok(Self {
+            regex,
+            replacement: replacement.into(),
+        })
+    }
+}
+
+impl PartialEq for RegexReplacement {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.replacement == other.replacement
+    }
+}
+
+impl Serialize for RegexReplacement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.regex.as_str(), &self.replacement).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexReplacement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (pattern, replacement) = <(String, String)>::deserialize(deserializer)?;
+        Self::new(&pattern, replacement).map_err(|err| serde::de::Error::custom(format!("{err:#}")))
+    }
+}
+
+/// Bound of `replacement_passes`, cyclic replacements would go on forever.
+pub const MAX_REPLACEMENT_PASSES: usize = 10;
+
+/// Whisper's own default.
+fn default_temperature_inc() -> f32 {
+    0.2
+}
+
+fn default_no_speech_threshold() -> f32 {
+    0.6
+}
+
+/// Phrases Whisper is known to output on silence or noise.
+fn default_hallucinations() -> Vec<String> {
+    [
+        "you",
+        "thank you",
+        "thanks for watching",
+        "thank you for watching",
+        "bye",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+impl ModelConfig {
+    /// The transcription languages: `languages`, or the fixed `language`
+    /// without them. Empty auto-detects the language.
+    pub fn transcription_languages(&self) -> Vec<String> {
+        if !self.languages.is_empty() {
+            return self.languages.clone();
+        }
+        self.language
+            .iter()
+            .filter(|language| !language.eq_ignore_ascii_case("auto"))
+            .cloned()
+            .collect()
+    }
+
+    /// Greedy `best_of` for an utterance of `duration` seconds.
+    ///
+    /// Breakpoints don't need to be sorted, the tightest one wins.
+    pub fn best_of(&self, duration: f32) -> i32 {
+        self.adaptive_best_of
+            .iter()
+            .filter(|breakpoint| duration <= breakpoint.max_duration)
+            .min_by(|a, b| a.max_duration.total_cmp(&b.max_duration))
+            .map_or(1, |breakpoint| breakpoint.best_of.max(1))
+    }
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl PromptType {
@@ -212,10 +1215,37 @@ impl PromptType {
 impl Default for ModelConfig {
     fn default() -> Self {
         Self {
+            backend: Backend::Whisper,
             repo: "ggerganov/whisper.cpp".to_string(),
             filename: "ggml-base.en.bin".to_string(),
             prompt: PromptType::None,
             replacements: HashMap::new(),
+            replacement_passes: default_replacement_passes(),
+            regex_replacements: Vec::new(),
+            single_segment: false,
+            suppress_blank: true,
+            suppress_non_speech: false,
+            max_tokens: 0,
+            n_threads: None,
+            temperature: 0.0,
+            temperature_inc: default_temperature_inc(),
+            unload_after_secs: None,
+            token_timestamps: false,
+            suppress_tokens: Vec::new(),
+            audio_ctx: None,
+            strip_prompt_echo: false,
+            hallucinations: default_hallucinations(),
+            no_speech_threshold: default_no_speech_threshold(),
+            min_confidence: None,
+            low_confidence: LowConfidence::default(),
+            warm_on_start: false,
+            adaptive_best_of: Vec::new(),
+            min_trailing_silence: 0.0,
+            language: None,
+            task: Task::default(),
+            languages: Vec::new(),
+            rerun_filename: None,
+            run_timeout_secs: default_run_timeout_secs(),
         }
     }
 }
@@ -245,6 +1275,11 @@ impl Default for Config {
             },
             model: ModelConfig::default(),
             activation: ActivationConfig::default(),
+            formatting: FormattingConfig::default(),
+            debug: DebugConfig::default(),
+            hooks: HooksConfig::default(),
+            history: HistoryConfig::default(),
+            output: OutputConfig::default(),
         }
     }
 }
@@ -261,13 +1296,82 @@ impl Config {
         path
     }
 
-    /// Loads configuration from a TOML file.
+    /// Loads and validates configuration from a TOML file.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let contents = std::fs::read_to_string(path.as_ref())?;
         let config: Self = toml::from_str(&contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads and merges several TOML files, later files overriding earlier ones.
+    ///
+    /// Files are merged before being validated, so only the merged result
+    /// needs to be a complete configuration (e.g. a full base config followed
+    /// by a partial machine-local override). See [`merge_tables`] for the
+    /// merge semantics.
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut merged = toml::Table::new();
+        for path in paths {
+            let path = path.as_ref();
+            let contents = std::fs::read_to_string(path)
+                .context(format!("Reading config {}", path.display()))?;
+            let table: toml::Table =
+                toml::from_str(&contents).context(format!("Parsing config {}", path.display()))?;
+            merge_tables(&mut merged, table);
+        }
+        let config: Self = toml::Value::Table(merged)
+            .try_into()
+            .context("Merging config files")?;
+        config.validate()?;
         Ok(config)
     }
 
+    /// The files [`Config::load`] reads: `paths`, or the default location.
+    pub fn sources<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBuf> {
+        if paths.is_empty() {
+            vec![Self::default_config_path()]
+        } else {
+            paths
+                .iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect()
+        }
+    }
+
+    /// The settings differing from `other`, as `section.key` (e.g.
+    /// `"model.prompt"`), sorted.
+    pub fn changes(&self, other: &Config) -> Result<Vec<String>> {
+        let old = toml::Table::try_from(self)?;
+        let new = toml::Table::try_from(other)?;
+        let mut changes = Vec::new();
+        for section in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+            match (old.get(section), new.get(section)) {
+                (Some(toml::Value::Table(old)), Some(toml::Value::Table(new))) => {
+                    for key in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+                        if old.get(key) != new.get(key) {
+                            changes.push(format!("{section}.{key}"));
+                        }
+                    }
+                }
+                (old, new) if old != new => changes.push(section.clone()),
+                _ => {}
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Loads the configuration in effect: the merged `paths`, or the default
+    /// location (defaults if it doesn't exist) when none is given. Nothing is
+    /// written to disk.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        if paths.is_empty() {
+            Self::load_or_default(None)
+        } else {
+            Self::from_files(paths)
+        }
+    }
+
     /// Saves configuration to a TOML file.
     pub fn save_to_file(&self, path: &Path) -> Result<()> {
         let contents = toml::to_string(self)?;
@@ -275,6 +1379,22 @@ impl Config {
         Ok(())
     }
 
+    /// Loads configuration from the default location, falling back to in-memory
+    /// defaults if it doesn't exist. Nothing is ever written to disk.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self> {
+        let default_path = Self::default_config_path();
+        let path = path.unwrap_or(&default_path);
+        if path.exists() {
+            return Self::from_file(path)
+                .context(format!("Reading default config from {}", path.display()));
+        }
+        info!(
+            "No config found at {}, using defaults without writing them",
+            path.display()
+        );
+        Ok(Self::default())
+    }
+
     /// Loads configuration from the default location, creating it if it doesn't exist.
     pub fn load_or_write_default(path: Option<&Path>) -> Result<Self> {
         let default_path = Self::default_config_path();
@@ -310,36 +1430,399 @@ impl Config {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
-
-    #[test]
-    fn test_default_config() {
-        let config = Config::default();
-        assert_eq!(config.audio.channels, 1);
-        assert_eq!(config.audio.sample_rate, 16000);
-        assert_eq!(config.audio.sample_format, SampleFormat::F32);
-        assert_eq!(config.model.repo, "ggerganov/whisper.cpp");
-        assert_eq!(config.model.filename, "ggml-base.en.bin");
-        assert_eq!(config.model.prompt, PromptType::None);
-        assert!(config.model.replacements.is_empty());
-        assert_eq!(
-            config.activation.keys,
-            HashSet::from([Key::ControlLeft, Key::Space])
-        );
-        assert_eq!(config.activation.trigger, Trigger::PushToTalk);
+impl Config {
+    /// Starts building a configuration from the defaults.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
     }
 
-    #[test]
-    fn test_config_serialization() -> Result<()> {
-        let config = Config::default();
+    /// Checks values the type system doesn't enforce.
+    pub fn validate(&self) -> Result<()> {
+        if self.audio.channels == 0 {
+            bail!("audio.channels must be at least 1");
+        }
+        if self.audio.sample_rate == 0 {
+            bail!("audio.sample_rate must be positive");
+        }
+        if !(0.0..=1.0).contains(&self.audio.noise_suppression_strength) {
+            bail!("audio.noise_suppression_strength must be between 0.0 and 1.0");
+        }
+        let weights = self.audio.downmix_weights.len();
+        if weights != 0 && self.audio.downmix_mode != DownmixMode::Average {
+            bail!("audio.downmix_weights only apply to the average downmix_mode");
+        }
+        if weights != 0 && weights != self.audio.channels as usize {
+            bail!(
+                "audio.downmix_weights has {weights} weights for {} channels",
+                self.audio.channels
+            );
+        }
+        if self.model.repo.is_empty() || self.model.filename.is_empty() {
+            bail!("model.repo and model.filename must be set");
+        }
+        if self.activation.keys.is_empty() && !self.activation.trigger.auto_starts() {
+            bail!("activation.keys must contain at least one key");
+        }
+        if self
+            .activation
+            .alternative_keys
+            .iter()
+            .any(HashSet::is_empty)
+        {
+            bail!("activation.alternative_keys can't contain an empty key set");
+        }
+        if self
+            .activation
+            .clipboard_mime
+            .as_deref()
+            .is_some_and(|mime| !mime.contains('/') || mime.contains(char::is_whitespace))
+        {
+            bail!(
+                "activation.clipboard_mime must be a MIME type like \"text/plain;charset=utf-8\""
+            );
+        }
+        if let Some(token) = self.model.suppress_tokens.iter().find(|t| **t < 0) {
+            bail!("model.suppress_tokens has a negative token ID {token}");
+        }
+        if self.debug.vad_stream_max_mb == 0 {
+            bail!("debug.vad_stream_max_mb must be positive");
+        }
+        if self.debug.max_recordings == Some(0) || self.debug.max_recordings_mb == Some(0) {
+            bail!("debug.max_recordings and debug.max_recordings_mb must be positive");
+        }
+        if self.debug.recording_format == RecordingFormat::Flac && !cfg!(feature = "flac") {
+            bail!("debug.recording_format = \"flac\" requires the `flac` feature");
+        }
+        if self.audio.noise_suppression && !cfg!(feature = "denoise") {
+            bail!("audio.noise_suppression requires the `denoise` feature");
+        }
+        let unicode = [
+            ("normalize", self.formatting.normalize.is_some()),
+            ("ascii_fold", self.formatting.ascii_fold),
+            ("strip_symbols", self.formatting.strip_symbols),
+        ];
+        for (key, enabled) in unicode {
+            if enabled && !cfg!(feature = "formatting") {
+                bail!("formatting.{key} requires the `formatting` feature");
+            }
+        }
+        formatting::check_time_format(&self.formatting.prefix)
+            .context("Invalid formatting.prefix")?;
+        formatting::check_time_format(&self.formatting.suffix)
+            .context("Invalid formatting.suffix")?;
+        if self.formatting.paragraph_gap.is_some_and(|gap| gap <= 0.0) {
+            bail!("formatting.paragraph_gap must be positive");
+        }
+        if self
+            .activation
+            .blocked_apps
+            .iter()
+            .any(|app| app.is_empty())
+        {
+            bail!("activation.blocked_apps can't contain an empty name");
+        }
+        match self.activation.input_warmup {
+            InputWarmup::Key { key } if !InputWarmup::KEYS.contains(&key) => bail!(
+                "activation.input_warmup can't use {key:?}, pick one of {:?}",
+                InputWarmup::KEYS
+            ),
+            _ => (),
+        }
+        if self
+            .model
+            .audio_ctx
+            .is_some_and(|audio_ctx| !(1..=MAX_AUDIO_CTX).contains(&audio_ctx))
+        {
+            bail!("model.audio_ctx must be between 1 and {MAX_AUDIO_CTX}");
+        }
+        if self
+            .model
+            .min_confidence
+            .is_some_and(|min| !(0.0..=1.0).contains(&min))
+        {
+            bail!("model.min_confidence must be between 0.0 and 1.0");
+        }
+        if !(0.0..=1.0).contains(&self.model.no_speech_threshold) {
+            bail!("model.no_speech_threshold must be between 0.0 and 1.0");
+        }
+        if self.history.max_entries == 0 || self.history.max_mb == 0 {
+            bail!("history.max_entries and history.max_mb must be positive");
+        }
+        if self.hooks.timeout_ms == 0 {
+            bail!("hooks.timeout_ms must be positive");
+        }
+        if !(1..=MAX_REPLACEMENT_PASSES).contains(&self.model.replacement_passes) {
+            bail!("model.replacement_passes must be between 1 and {MAX_REPLACEMENT_PASSES}");
+        }
+        if self.model.run_timeout_secs == 0 {
+            bail!("model.run_timeout_secs must be positive");
+        }
+        if self.model.unload_after_secs == Some(0) {
+            bail!("model.unload_after_secs must be positive");
+        }
+        if !(0.0..=1.0).contains(&self.model.temperature)
+            || !(0.0..=1.0).contains(&self.model.temperature_inc)
+        {
+            bail!("model.temperature and model.temperature_inc must be between 0.0 and 1.0");
+        }
+        if self.model.n_threads == Some(0) {
+            bail!("model.n_threads must be positive");
+        }
+        if self.model.task == Task::Translate {
+            let mut filenames =
+                std::iter::once(&self.model.filename).chain(&self.model.rerun_filename);
+            if let Some(filename) = filenames.find(|f| is_english_only(f)) {
+                bail!(
+                    "model.task = \"translate\" requires a multilingual model, {filename} is English-only"
+                );
+            }
+        }
+        if !self.activation.cancel_keys.is_empty()
+            && self
+                .activation
+                .key_sets()
+                .any(|keys| self.activation.cancel_keys.is_subset(keys))
+        {
+            bail!("activation.cancel_keys can't be part of the activation keys");
+        }
+        if self
+            .activation
+            .max_recording_secs
+            .is_some_and(|secs| secs.is_nan() || secs <= 0.0)
+        {
+            bail!("activation.max_recording_secs must be positive");
+        }
+        if self.activation.rerun_key.is_some() && self.model.rerun_filename.is_none() {
+            bail!("activation.rerun_key requires model.rerun_filename");
+        }
+        if matches!(
+            self.activation.trigger,
+            Trigger::PushToTalk { pre_buffer_duration, .. } if pre_buffer_duration < 0.0
+        ) {
+            bail!("push_to_talk pre_buffer_duration must not be negative");
+        }
+        if let Trigger::ToggleVad {
+            threshold,
+            silence_duration,
+            speech_duration,
+            pre_buffer_duration,
+            coast_prob,
+            silent_skip_factor,
+            warmup_duration,
+            vad_model,
+            energy_threshold,
+            max_utterance_secs,
+            max_chunk_secs,
+            ..
+        } = self.activation.trigger
+        {
+            if silent_skip_factor == 0 {
+                bail!("VAD silent_skip_factor must be at least 1");
+            }
+            if !(0.0..=1.0).contains(&threshold) {
+                bail!("VAD threshold must be between 0.0 and 1.0, got {threshold}");
+            }
+            if silence_duration < 0.0
+                || speech_duration < 0.0
+                || pre_buffer_duration < 0.0
+                || warmup_duration < 0.0
+            {
+                bail!("VAD durations must not be negative");
+            }
+            if !(0.0..threshold).contains(&coast_prob) {
+                bail!(
+                    "VAD coast_prob must be between 0.0 and the threshold ({threshold}), got {coast_prob}"
+                );
+            }
+            if !(energy_threshold > 0.0 && energy_threshold <= 1.0) {
+                bail!("VAD energy_threshold must be in (0.0, 1.0], got {energy_threshold}");
+            }
+            // The energy VAD's probabilities are 0.0 or 1.0
+            if vad_model != VadModel::Silero && threshold >= 1.0 {
+                bail!("VAD threshold must be below 1.0 with the energy VAD");
+            }
+            // Also rejects NaN, the buffer is allocated up front
+            if !(max_utterance_secs > 0.0 && max_utterance_secs <= MAX_UTTERANCE_SECS) {
+                bail!(
+                    "VAD max_utterance_secs must be positive and at most {MAX_UTTERANCE_SECS}, got {max_utterance_secs}"
+                );
+            }
+            // Chunks are cut before the utterance buffer drops anything
+            if max_chunk_secs.is_some_and(|secs| !(1.0..max_utterance_secs).contains(&secs)) {
+                bail!(
+                    "VAD max_chunk_secs must be at least 1.0 and below max_utterance_secs ({max_utterance_secs})"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bound of the VAD's `max_utterance_secs`, 10 minutes take about 38 MB.
+const MAX_UTTERANCE_SECS: f32 = 600.0;
+
+/// Whisper's full encoder context, 30s in 20ms units.
+const MAX_AUDIO_CTX: i32 = 1500;
+
+/// Builds a [`Config`] programmatically, starting from the defaults.
+///
+/// ```
+/// use rdev::Key;
+/// use whispering::config::{Config, Trigger};
+///
+/// let config = Config::builder()
+///     .model("ggerganov/whisper.cpp", "ggml-small.en.bin")
+///     .trigger(Trigger::Toggle)
+///     .keys([Key::ControlLeft, Key::KeyR])
+///     .replacement("teh", "the")
+///     .build()?;
+/// assert_eq!(config.model.filename, "ggml-small.en.bin");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets the Hugging Face repository and filename of the model.
+    pub fn model(mut self, repo: impl Into<String>, filename: impl Into<String>) -> Self {
+        self.config.model.repo = repo.into();
+        self.config.model.filename = filename.into();
+        self
+    }
+
+    /// Sets the prompt given to the model.
+    pub fn prompt(mut self, prompt: PromptType) -> Self {
+        self.config.model.prompt = prompt;
+        self
+    }
+
+    /// Adds a replacement applied to the transcripts.
+    pub fn replacement(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.config
+            .model
+            .replacements
+            .insert(from.into(), to.into());
+        self
+    }
+
+    /// Sets the input device by name, see [`AudioConfig::device`].
+    pub fn device(mut self, device: impl Into<String>) -> Self {
+        self.config.audio.device = Some(Device::Name(device.into()));
+        self
+    }
+
+    /// Sets the recorded channels and sample rate.
+    pub fn audio_format(mut self, channels: u16, sample_rate: u32) -> Self {
+        self.config.audio.channels = channels;
+        self.config.audio.sample_rate = sample_rate;
+        self
+    }
+
+    /// Sets the cache directory and recording path.
+    pub fn paths(
+        mut self,
+        cache_dir: impl Into<PathBuf>,
+        recording_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.config.paths.cache_dir = cache_dir.into();
+        self.config.paths.recording_path = recording_path.into();
+        self
+    }
+
+    /// Sets how recording is activated.
+    pub fn trigger(mut self, trigger: Trigger) -> Self {
+        self.config.activation.trigger = trigger;
+        self
+    }
+
+    /// Sets the activation keys.
+    pub fn keys(mut self, keys: impl IntoIterator<Item = Key>) -> Self {
+        self.config.activation.keys = keys.into_iter().collect();
+        self
+    }
+
+    /// Presses Return after pasting.
+    pub fn autosend(mut self, autosend: bool) -> Self {
+        self.config.activation.autosend = autosend;
+        self
+    }
+
+    /// Shows desktop notifications.
+    pub fn notify(mut self, notify: bool) -> Self {
+        self.config.activation.notify = notify;
+        self
+    }
+
+    /// Validates and returns the configuration.
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Merges `overlay` into `base`.
+///
+/// - Tables (sections, `replacements`, ...) are merged key by key.
+/// - Scalars and arrays (e.g. `keys`) in `overlay` replace the base value.
+/// - Tagged tables (`trigger`, `prompt`) switching to another `type` are
+///   replaced entirely, so options of the previous variant don't leak in.
+pub fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table))
+                if base_table.get("type") == table.get("type") || table.get("type").is_none() =>
+            {
+                merge_tables(base_table, table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_default_config() {
+        let config = Config::default();
+        assert_eq!(config.audio.channels, 1);
+        assert_eq!(config.audio.sample_rate, 16000);
+        assert_eq!(config.audio.sample_format, SampleFormat::F32);
+        assert_eq!(config.model.repo, "ggerganov/whisper.cpp");
+        assert_eq!(config.model.filename, "ggml-base.en.bin");
+        assert_eq!(config.model.prompt, PromptType::None);
+        assert!(config.model.replacements.is_empty());
+        assert_eq!(
+            config.activation.keys,
+            HashSet::from([Key::ControlLeft, Key::Space])
+        );
+        assert_eq!(
+            config.activation.trigger,
+            Trigger::PushToTalk {
+                streaming: false,
+                release_grace_ms: 0,
+                pre_buffer_duration: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_serialization() -> Result<()> {
+        let config = Config::default();
         let toml = toml::to_string(&config)?;
         println!("TOML output:\n{}", toml);
         assert!(toml.contains("sample_format = \"f32\""));
         assert!(toml.contains("channels = 1"));
         assert!(toml.contains("sample_rate = 16000"));
+        assert!(toml.contains("suppress_blank = true"));
+        assert!(toml.contains("suppress_non_speech = false"));
+        assert!(toml.contains("suppress_tokens = []"));
         Ok(())
     }
 
@@ -402,7 +1885,42 @@ mod tests {
             config.activation.keys,
             HashSet::from([Key::ControlLeft, Key::Space]),
         );
-        assert_eq!(config.activation.trigger, Trigger::PushToTalk);
+        assert_eq!(
+            config.activation.trigger,
+            Trigger::PushToTalk {
+                streaming: false,
+                release_grace_ms: 0,
+                pre_buffer_duration: 0.0,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vad_auto_start() -> Result<()> {
+        let activation: ActivationConfig = toml::from_str(
+            r#"
+            trigger = { type = "toggle_vad", auto_start = true }
+            keys = []
+            notify = true
+            autosend = false
+            "#,
+        )?;
+        assert!(activation.trigger.auto_starts());
+        // No hotkey is needed when listening starts at launch
+        let config = Config {
+            activation,
+            ..Config::default()
+        };
+        config.validate()?;
+
+        // Off by default, keys required
+        let mut config = Config::default();
+        config.activation.trigger = toml::from_str(r#"type = "toggle_vad""#)?;
+        assert!(!config.activation.trigger.auto_starts());
+        config.activation.keys.clear();
+        assert!(config.validate().is_err());
+        assert!(!Trigger::Toggle.auto_starts());
         Ok(())
     }
 
@@ -438,12 +1956,192 @@ mod tests {
                 threshold: 0.7,
                 silence_duration: 1.5,
                 speech_duration: 0.4,
-                pre_buffer_duration: 0.3
+                pre_buffer_duration: 0.3,
+                arm_once: false,
+                coast_prob: 0.0,
+                silent_skip_factor: 1,
+                auto_start: false,
+                warmup_duration: 0.0,
+                vad_model: VadModel::Silero,
+                energy_threshold: 0.01,
+                max_utterance_secs: 60.0,
+                max_chunk_secs: None,
             }
         );
         Ok(())
     }
 
+    #[test]
+    fn test_push_to_talk_defaults() -> Result<()> {
+        let activation: ActivationConfig = toml::from_str(
+            r#"
+            trigger = { type = "push_to_talk" }
+            keys = ["ControlLeft", "Space"]
+            notify = true
+            autosend = false
+            "#,
+        )?;
+        assert_eq!(activation.trigger, Trigger::push_to_talk());
+        Config::builder().trigger(Trigger::push_to_talk()).build()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_segment_config() -> Result<()> {
+        let mut config = Config::default();
+        assert!(!config.model.single_segment);
+
+        config.model.single_segment = true;
+        let toml = toml::to_string(&config)?;
+        assert!(toml.contains("single_segment = true"));
+        let deserialized: Config = toml::from_str(&toml)?;
+        assert!(deserialized.model.single_segment);
+        Ok(())
+    }
+
+    #[test]
+    fn test_language() -> Result<()> {
+        let mut config = Config::default();
+        assert!(config.model.transcription_languages().is_empty());
+
+        config.model.language = Some("fr".to_string());
+        let toml = toml::to_string(&config)?;
+        assert!(toml.contains("language = \"fr\""));
+        let round_trip: Config = toml::from_str(&toml)?;
+        assert_eq!(round_trip, config);
+        assert_eq!(round_trip.model.transcription_languages(), ["fr"]);
+
+        // Same as not specifying it
+        config.model.language = Some("auto".to_string());
+        assert!(config.model.transcription_languages().is_empty());
+        // Cycled languages win
+        config.model.languages = vec!["en".to_string(), "de".to_string()];
+        assert_eq!(config.model.transcription_languages(), ["en", "de"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_n_threads() -> Result<()> {
+        let model: ModelConfig = toml::from_str(
+            r#"
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            n_threads = 8
+            "#,
+        )?;
+        assert_eq!(model.n_threads, Some(8));
+        assert_eq!(ModelConfig::default().n_threads, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temperature() -> Result<()> {
+        let model: ModelConfig = toml::from_str(
+            r#"
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            temperature = 0.4
+            temperature_inc = 0.1
+            "#,
+        )?;
+        assert_eq!(model.temperature, 0.4);
+        assert_eq!(model.temperature_inc, 0.1);
+
+        // Greedy with whisper's fallback, as before these options
+        let model: ModelConfig = toml::from_str(
+            r#"
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            "#,
+        )?;
+        assert_eq!(model.temperature, 0.0);
+        assert_eq!(model.temperature_inc, 0.2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_regex_replacements_config() -> Result<()> {
+        let toml = r#"
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            regex_replacements = [['(?i)\bteh\b', "the"], ['(\d+) percent', "$1%"]]
+        "#;
+        let model: ModelConfig = toml::from_str(toml)?;
+        let patterns: Vec<&str> = model
+            .regex_replacements
+            .iter()
+            .map(|replacement| replacement.regex.as_str())
+            .collect();
+        assert_eq!(patterns, [r"(?i)\bteh\b", r"(\d+) percent"]);
+        assert_eq!(model.regex_replacements[1].replacement, "$1%");
+        let round_trip: ModelConfig = toml::from_str(&toml::to_string(&model)?)?;
+        assert_eq!(round_trip, model);
+
+        // The error points at the bad pattern
+        let err = toml::from_str::<ModelConfig>(&toml.replace("(\\d+)", "(\\d+"))
+            .err()
+            .map(|err| err.to_string())
+            .unwrap_or_default();
+        assert!(
+            err.contains(r#"Invalid regex_replacements pattern "(\\d+ percent""#),
+            "{err}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_suppress_config() -> Result<()> {
+        let toml = r#"
+            [audio]
+            channels = 1
+            sample_rate = 16000
+            sample_format = "f32"
+
+            [model]
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.en.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            suppress_blank = false
+            suppress_non_speech = true
+            max_tokens = 64
+            token_timestamps = true
+            suppress_tokens = [1009, 2497]
+            audio_ctx = 768
+
+            [paths]
+            cache_dir = "~/.cache/whispering"
+            recording_path = "~/.cache/whispering/recorded.wav"
+
+            [activation]
+            trigger.type = "push_to_talk"
+            keys = ["ControlLeft", "Space"]
+            notify = true
+            autosend = false
+        "#;
+
+        let config: Config = toml::from_str(toml)?;
+        assert!(!config.model.suppress_blank);
+        assert!(config.model.suppress_non_speech);
+        assert_eq!(config.model.max_tokens, 64);
+        assert!(config.model.token_timestamps);
+        assert_eq!(config.model.suppress_tokens, [1009, 2497]);
+        assert_eq!(config.model.audio_ctx, Some(768));
+        config.validate()?;
+
+        let round_trip: Config = toml::from_str(&toml::to_string(&config)?)?;
+        assert_eq!(round_trip, config);
+        Ok(())
+    }
+
     #[test]
     fn test_prompt_type() {
         // Test Vocabulary variant
@@ -534,7 +2232,14 @@ mod tests {
             config.activation.keys,
             HashSet::from([Key::ControlLeft, Key::Space])
         );
-        assert_eq!(config.activation.trigger, Trigger::PushToTalk);
+        assert_eq!(
+            config.activation.trigger,
+            Trigger::PushToTalk {
+                streaming: false,
+                release_grace_ms: 0,
+                pre_buffer_duration: 0.0,
+            }
+        );
         Ok(())
     }
 
@@ -555,7 +2260,11 @@ mod tests {
         };
         config.paths.cache_dir = PathBuf::from("/tmp/test");
         config.paths.recording_path = PathBuf::from("/tmp/test/recorded.wav");
-        config.activation.trigger = Trigger::PushToTalk;
+        config.activation.trigger = Trigger::PushToTalk {
+            streaming: false,
+            release_grace_ms: 0,
+            pre_buffer_duration: 0.0,
+        };
         config.activation.keys = HashSet::from([Key::ControlLeft, Key::Alt, Key::Space]);
 
         // Save config to file
@@ -604,6 +2313,762 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_merge_config_files() -> Result<()> {
+        let dir = tempdir()?;
+        let mut base = Config::default();
+        base.model
+            .replacements
+            .insert("teh".to_string(), "the".to_string());
+        let base_path = dir.path().join("base.toml");
+        base.save_to_file(&base_path)?;
+
+        let local_path = dir.path().join("local.toml");
+        std::fs::write(
+            &local_path,
+            r#"
+            [audio]
+            sample_rate = 48000
+
+            [model.replacements]
+            "recieve" = "receive"
+
+            [activation]
+            keys = ["Alt"]
+            "#,
+        )?;
+
+        let config = Config::from_files(&[&base_path, &local_path])?;
+        // Scalars are overridden, untouched fields are kept
+        assert_eq!(config.audio.sample_rate, 48000);
+        assert_eq!(config.audio.channels, base.audio.channels);
+        assert_eq!(config.model.filename, base.model.filename);
+        // Maps are merged
+        assert_eq!(config.model.replacements.len(), 2);
+        assert_eq!(config.model.replacements["teh"], "the");
+        assert_eq!(config.model.replacements["recieve"], "receive");
+        // Arrays are replaced
+        assert_eq!(config.activation.keys, HashSet::from([Key::Alt]));
+
+        // Later files win
+        let config = Config::from_files(&[&local_path, &base_path])?;
+        assert_eq!(config.audio.sample_rate, base.audio.sample_rate);
+
+        // A partial file alone is not a complete config
+        assert!(Config::from_files(&[&local_path]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loaded_config_validated() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        let mut config = toml::Table::try_from(Config::default())?;
+        config.insert(
+            "activation".into(),
+            toml::Value::try_from(ActivationConfig {
+                trigger: Trigger::ToggleVad {
+                    threshold: 0.5,
+                    silence_duration: 1.0,
+                    speech_duration: 0.3,
+                    pre_buffer_duration: 0.0,
+                    arm_once: false,
+                    // Skipped frames alone would start a recording
+                    coast_prob: 0.6,
+                    silent_skip_factor: 1,
+                    auto_start: false,
+                    warmup_duration: 0.0,
+                    vad_model: VadModel::Silero,
+                    energy_threshold: 0.01,
+                    max_utterance_secs: 60.0,
+                    max_chunk_secs: None,
+                },
+                ..ActivationConfig::default()
+            })?,
+        );
+        std::fs::write(&path, toml::to_string(&config)?)?;
+
+        for result in [
+            Config::from_file(&path),
+            Config::from_files(&[&path]),
+            Config::load(&[&path]),
+            Config::load_or_default(Some(&path)),
+        ] {
+            let err = result
+                .err()
+                .map(|err| format!("{err:#}"))
+                .unwrap_or_default();
+            assert!(err.contains("coast_prob"), "{err}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_loaded_time_format_validated() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        let mut config = Config::default();
+        config.formatting.prefix = "%K ".into();
+        config.save_to_file(&path)?;
+        let err = Config::from_file(&path)
+            .err()
+            .map(|err| format!("{err:#}"))
+            .unwrap_or_default();
+        assert!(err.contains("formatting.prefix"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_tagged_tables() -> Result<()> {
+        let mut base: toml::Table = toml::from_str(
+            r#"
+            [activation]
+            trigger = { type = "toggle_vad", threshold = 0.5, silence_duration = 1.0, speech_duration = 0.3 }
+            "#,
+        )?;
+        let same_type: toml::Table = toml::from_str(
+            r#"
+            [activation]
+            trigger = { type = "toggle_vad", threshold = 0.8 }
+            "#,
+        )?;
+        merge_tables(&mut base, same_type);
+        let trigger = &base["activation"]["trigger"];
+        assert_eq!(trigger["threshold"].as_float(), Some(0.8));
+        assert_eq!(trigger["silence_duration"].as_float(), Some(1.0));
+
+        let other_type: toml::Table = toml::from_str(
+            r#"
+            [activation]
+            trigger = { type = "push_to_talk" }
+            "#,
+        )?;
+        merge_tables(&mut base, other_type);
+        let trigger = &base["activation"]["trigger"];
+        assert_eq!(trigger.get("threshold"), None);
+        assert_eq!(trigger["type"].as_str(), Some("push_to_talk"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_keycode_keys() -> Result<()> {
+        let toml = r#"
+            trigger.type = "push_to_talk"
+            notify = false
+            autosend = false
+            keys = ["ControlLeft", { code = 65 }]
+        "#;
+        let activation: ActivationConfig = toml::from_str(toml)?;
+        assert_eq!(
+            activation.keys,
+            HashSet::from([Key::ControlLeft, Key::Unknown(65)])
+        );
+
+        // Round trip keeps the raw keycode
+        let serialized = toml::to_string(&activation)?;
+        assert!(serialized.contains("code = 65"), "{serialized}");
+        let deserialized: ActivationConfig = toml::from_str(&serialized)?;
+        assert_eq!(activation, deserialized);
+
+        // Unknown key names and fields are still rejected
+        let invalid = toml.replace("\"ControlLeft\"", "\"NotAKey\"");
+        assert!(toml::from_str::<ActivationConfig>(&invalid).is_err());
+        let invalid = toml.replace("code", "scancode");
+        assert!(toml::from_str::<ActivationConfig>(&invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_mode() -> Result<()> {
+        let toml = r#"
+            trigger.type = "push_to_talk"
+            notify = false
+            autosend = false
+            keys = ["ControlLeft", "Space"]
+            output_mode = "clipboard_only"
+        "#;
+        let activation: ActivationConfig = toml::from_str(toml)?;
+        assert_eq!(activation.output_mode, OutputMode::ClipboardOnly);
+        let serialized = toml::to_string(&activation)?;
+        assert!(serialized.contains(r#"output_mode = "clipboard_only""#));
+        let deserialized: ActivationConfig = toml::from_str(&serialized)?;
+        assert_eq!(activation, deserialized);
+
+        // Pasting by default
+        let activation: ActivationConfig =
+            toml::from_str(&toml.replace(r#"output_mode = "clipboard_only""#, ""))?;
+        assert_eq!(activation.output_mode, OutputMode::Paste);
+        let serialized = toml::to_string(&activation)?;
+        assert!(serialized.contains(r#"output_mode = "paste""#));
+        let activation: ActivationConfig = toml::from_str(&toml.replace("clipboard_only", "type"))?;
+        assert_eq!(activation.output_mode, OutputMode::Type);
+        assert!(
+            toml::from_str::<ActivationConfig>(&toml.replace("clipboard_only", "typed")).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alternative_keys() -> Result<()> {
+        let toml = r#"
+            trigger.type = "push_to_talk"
+            notify = false
+            autosend = false
+            keys = ["ControlLeft", "Space"]
+            alternative_keys = [["F12"], ["Alt", { code = 65 }]]
+        "#;
+        let activation: ActivationConfig = toml::from_str(toml)?;
+        assert_eq!(
+            activation.alternative_keys,
+            [
+                HashSet::from([Key::F12]),
+                HashSet::from([Key::Alt, Key::Unknown(65)])
+            ]
+        );
+        let deserialized: ActivationConfig = toml::from_str(&toml::to_string(&activation)?)?;
+        assert_eq!(activation, deserialized);
+
+        // Either combination triggers
+        assert!(activation.activated(&HashSet::from([Key::ControlLeft, Key::Space])));
+        assert!(activation.activated(&HashSet::from([Key::F12])));
+        assert!(activation.activated(&HashSet::from([Key::Alt, Key::Unknown(65)])));
+        assert!(activation.is_activation_key(&Key::F12));
+        // Unrelated or mixed combinations don't
+        assert!(!activation.is_activation_key(&Key::KeyA));
+        assert!(!activation.activated(&HashSet::from([Key::ControlLeft, Key::F12])));
+        assert!(!activation.activated(&HashSet::from([Key::Alt])));
+        assert!(!activation.activated(&HashSet::new()));
+
+        // The single set form still works
+        let single: ActivationConfig =
+            toml::from_str(&toml.replace("alternative_keys", "# alternative_keys"))?;
+        assert!(single.alternative_keys.is_empty());
+        assert!(single.activated(&HashSet::from([Key::ControlLeft, Key::Space])));
+        assert!(!single.activated(&HashSet::from([Key::F12])));
+
+        let mut config = Config::default();
+        config.activation.alternative_keys = vec![HashSet::new()];
+        assert!(config.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_keys() -> Result<()> {
+        let toml = r#"
+            trigger.type = "push_to_talk"
+            notify = false
+            autosend = false
+            keys = ["ControlLeft", "Space"]
+            cancel_keys = ["Escape"]
+        "#;
+        let activation: ActivationConfig = toml::from_str(toml)?;
+        assert_eq!(activation.cancel_keys, HashSet::from([Key::Escape]));
+        // Pressed along with the held activation keys
+        assert!(activation.cancelled(&HashSet::from([Key::ControlLeft, Key::Space, Key::Escape])));
+        assert!(!activation.cancelled(&HashSet::from([Key::ControlLeft, Key::Space])));
+        let disabled: ActivationConfig =
+            toml::from_str(&toml.replace("cancel_keys", "# cancel_keys"))?;
+        assert!(!disabled.cancelled(&HashSet::from([Key::Escape])));
+        assert!(!disabled.cancelled(&HashSet::new()));
+
+        // Pressing the activation keys would cancel right away
+        let mut config = Config::default();
+        config.activation = activation;
+        assert!(config.validate().is_ok());
+        config.activation.cancel_keys = HashSet::from([Key::Space]);
+        assert!(config.validate().is_err());
+        config.activation.alternative_keys = vec![HashSet::from([Key::Escape])];
+        config.activation.cancel_keys = HashSet::from([Key::Escape]);
+        assert!(config.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recording_path_is_directory() -> Result<()> {
+        let dir = tempdir()?;
+        let mut paths = Config::default().paths;
+        paths.recording_path = dir.path().to_path_buf();
+        let err = paths
+            .prepare_recording_path()
+            .err()
+            .map(|err| err.to_string())
+            .unwrap_or_default();
+        assert!(err.contains("is a directory"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_recording_path_parent_created() -> Result<()> {
+        let dir = tempdir()?;
+        let mut paths = Config::default().paths;
+        paths.recording_path = dir.path().join("nested").join("recorded.wav");
+        paths.prepare_recording_path()?;
+        assert!(dir.path().join("nested").is_dir());
+        assert!(!paths.recording_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_builder() -> Result<()> {
+        let config = Config::builder()
+            .model("ggerganov/whisper.cpp", "ggml-small.en.bin")
+            .device("C920")
+            .trigger(Trigger::Toggle)
+            .keys([Key::ControlLeft, Key::KeyR])
+            .notify(false)
+            .build()?;
+        assert_eq!(config.model.filename, "ggml-small.en.bin");
+        assert_eq!(config.audio.device, Some(Device::Name("C920".into())));
+        assert_eq!(config.activation.trigger, Trigger::Toggle);
+        assert_eq!(
+            config.activation.keys,
+            HashSet::from([Key::ControlLeft, Key::KeyR])
+        );
+        assert!(!config.activation.notify);
+        // Untouched fields keep their defaults
+        assert_eq!(config.audio, {
+            let mut audio = AudioConfig::default();
+            audio.device = Some(Device::Name("C920".to_string()));
+            audio
+        });
+
+        assert_eq!(Config::builder().build()?, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes() -> Result<()> {
+        let config = Config::default();
+        assert!(config.changes(&config)?.is_empty());
+        let mut changed = config.clone();
+        changed.model.prompt = PromptType::Raw {
+            prompt: "Hello.".into(),
+        };
+        changed.audio.sample_rate = 48000;
+        changed.audio.device = Some(Device::Index(1));
+        changed.activation.autosend = true;
+        assert_eq!(
+            config.changes(&changed)?,
+            [
+                "activation.autosend",
+                "audio.device",
+                "audio.sample_rate",
+                "model.prompt"
+            ]
+        );
+        // Either way
+        assert_eq!(changed.changes(&config)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_device() -> Result<()> {
+        #[derive(Deserialize)]
+        struct Audio {
+            device: Device,
+        }
+        let parse = |device: &str| -> Result<Device> {
+            let audio: Audio = toml::from_str(&format!("device = {device}"))?;
+            Ok(audio.device)
+        };
+        assert_eq!(parse(r#""C920""#)?, Device::Name("C920".into()));
+        assert_eq!(parse(r#"{ name = "C920" }"#)?, Device::Name("C920".into()));
+        assert_eq!(parse("{ index = 2 }")?, Device::Index(2));
+        for invalid in [
+            "{ index = -1 }",
+            r#"{ index = "2" }"#,
+            "{ id = 2 }",
+            "{}",
+            r#"{ name = "C920", index = 2 }"#,
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+
+        // Both round trip, names as plain strings
+        for device in [Device::Name("C920".into()), Device::Index(2)] {
+            let mut config = Config::default();
+            config.audio.device = Some(device);
+            let round_trip: Config = toml::from_str(&toml::to_string(&config)?)?;
+            assert_eq!(round_trip, config);
+        }
+        let mut config = Config::default();
+        config.audio.device = Some(Device::Name("C920".into()));
+        assert!(toml::to_string(&config)?.contains(r#"device = "C920""#));
+        Ok(())
+    }
+
+    /// The message `config` fails validation with, empty when it's valid.
+    fn validation_error(config: &Config) -> String {
+        config
+            .validate()
+            .err()
+            .map(|err| format!("{err:#}"))
+            .unwrap_or_default()
+    }
+
+    /// A `toggle_vad` trigger with the defaults, but for the TOML `fields`.
+    fn toggle_vad(fields: &str) -> Result<Trigger> {
+        let table: toml::Table = toml::from_str(&format!("type = \"toggle_vad\"\n{fields}"))?;
+        Ok(table.try_into()?)
+    }
+
+    #[test]
+    fn test_config_builder_validation() -> Result<()> {
+        let error = |builder: ConfigBuilder| {
+            builder
+                .build()
+                .err()
+                .map(|err| format!("{err:#}"))
+                .unwrap_or_default()
+        };
+        assert!(error(Config::builder().keys([])).contains("activation.keys"));
+        assert!(error(Config::builder().audio_format(0, 16000)).contains("audio.channels"));
+        assert!(error(Config::builder().model("", "model.bin")).contains("model.repo"));
+        let vad = toggle_vad("threshold = 1.5")?;
+        assert!(error(Config::builder().trigger(vad)).contains("VAD threshold"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_downmix_weights_validation() {
+        let mut config = Config::default();
+        config.audio.downmix_weights = vec![0.7, 0.3];
+        assert!(validation_error(&config).contains("2 weights for 1 channels"));
+        config.audio.channels = 2;
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_feature_validation() {
+        let mut config = Config::default();
+        config.audio.noise_suppression = true;
+        let error = validation_error(&config);
+        assert_eq!(
+            error.contains("`denoise` feature"),
+            !cfg!(feature = "denoise")
+        );
+
+        let mut config = Config::default();
+        config.formatting.strip_symbols = true;
+        let error = validation_error(&config);
+        assert_eq!(
+            error.contains("formatting.strip_symbols requires the `formatting` feature"),
+            !cfg!(feature = "formatting")
+        );
+    }
+
+    #[test]
+    fn test_rerun_key_validation() {
+        let mut config = Config::default();
+        config.activation.rerun_key = Some(Key::F10);
+        assert!(validation_error(&config).contains("requires model.rerun_filename"));
+        config.model.rerun_filename = Some("ggml-medium.en.bin".into());
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_suppress_tokens_validation() {
+        let mut config = Config::default();
+        config.model.suppress_tokens = vec![1009, -1];
+        assert!(validation_error(&config).contains("negative token ID -1"));
+    }
+
+    #[test]
+    fn test_max_recordings_validation() {
+        let mut config = Config::default();
+        config.debug.max_recordings = Some(0);
+        assert!(validation_error(&config).contains("debug.max_recordings"));
+    }
+
+    #[test]
+    fn test_audio_ctx_validation() {
+        let mut config = Config::default();
+        for audio_ctx in [0, MAX_AUDIO_CTX + 1] {
+            config.model.audio_ctx = Some(audio_ctx);
+            assert!(validation_error(&config).contains("model.audio_ctx"));
+        }
+        config.model.audio_ctx = Some(MAX_AUDIO_CTX);
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_min_confidence_validation() {
+        let mut config = Config::default();
+        config.model.min_confidence = Some(1.5);
+        assert!(validation_error(&config).contains("model.min_confidence"));
+        config.model.min_confidence = Some(0.4);
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_no_speech_threshold_validation() {
+        let mut config = Config::default();
+        config.model.no_speech_threshold = -0.1;
+        assert!(validation_error(&config).contains("model.no_speech_threshold"));
+        config.model.no_speech_threshold = 1.0;
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_n_threads_validation() {
+        let mut config = Config::default();
+        config.model.n_threads = Some(0);
+        assert!(validation_error(&config).contains("model.n_threads"));
+    }
+
+    #[test]
+    fn test_temperature_validation() {
+        let mut config = Config::default();
+        config.model.temperature = 1.5;
+        assert!(validation_error(&config).contains("model.temperature"));
+
+        let mut config = Config::default();
+        config.model.temperature_inc = -0.2;
+        assert!(validation_error(&config).contains("model.temperature_inc"));
+    }
+
+    #[test]
+    fn test_unload_after_secs_validation() {
+        let mut config = Config::default();
+        config.model.unload_after_secs = Some(0);
+        assert!(validation_error(&config).contains("model.unload_after_secs"));
+    }
+
+    #[test]
+    fn test_hooks_timeout_validation() {
+        let mut config = Config::default();
+        config.hooks.timeout_ms = 0;
+        assert!(validation_error(&config).contains("hooks.timeout_ms"));
+    }
+
+    #[test]
+    fn test_history_max_entries_validation() {
+        let mut config = Config::default();
+        config.history.max_entries = 0;
+        assert!(validation_error(&config).contains("history.max_entries"));
+    }
+
+    #[test]
+    fn test_max_recording_secs_validation() {
+        let mut config = Config::default();
+        for secs in [0.0, f32::NAN] {
+            config.activation.max_recording_secs = Some(secs);
+            assert!(validation_error(&config).contains("activation.max_recording_secs"));
+        }
+        config.activation.max_recording_secs = Some(120.0);
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_translate_validation() {
+        let mut config = Config::default();
+        config.model.task = Task::Translate;
+        config.model.filename = "ggml-base.bin".to_string();
+        config.model.rerun_filename = Some("ggml-large-v3.bin".to_string());
+        assert_eq!(validation_error(&config), "");
+        config.model.rerun_filename = Some("ggml-large.en-q5_0.bin".to_string());
+        assert!(validation_error(&config).contains("ggml-large.en-q5_0.bin is English-only"));
+
+        let mut config = Config::default();
+        config.model.filename = "ggml-base.en.bin".to_string();
+        assert_eq!(validation_error(&config), "");
+        config.model.task = Task::Translate;
+        assert!(validation_error(&config).contains("ggml-base.en.bin is English-only"));
+    }
+
+    #[test]
+    fn test_paragraph_gap_validation() {
+        let mut config = Config::default();
+        config.formatting.paragraph_gap = Some(0.0);
+        assert!(validation_error(&config).contains("formatting.paragraph_gap"));
+    }
+
+    #[test]
+    fn test_input_warmup_validation() {
+        let mut config = Config::default();
+        config.activation.input_warmup = InputWarmup::Key { key: Key::KeyA };
+        assert!(validation_error(&config).contains("activation.input_warmup can't use KeyA"));
+    }
+
+    #[test]
+    fn test_replacement_passes_validation() {
+        let mut config = Config::default();
+        for passes in [0, MAX_REPLACEMENT_PASSES + 1] {
+            config.model.replacement_passes = passes;
+            assert!(validation_error(&config).contains("model.replacement_passes"));
+        }
+        config.model.replacement_passes = MAX_REPLACEMENT_PASSES;
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_clipboard_mime_validation() {
+        let mut config = Config::default();
+        config.activation.clipboard_mime = Some("text plain".into());
+        assert!(validation_error(&config).contains("activation.clipboard_mime"));
+        config.activation.clipboard_mime = Some("text/plain;charset=utf-8".into());
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_push_to_talk_pre_buffer_validation() {
+        let mut config = Config::default();
+        config.activation.trigger = Trigger::PushToTalk {
+            streaming: false,
+            release_grace_ms: 0,
+            pre_buffer_duration: -0.1,
+        };
+        assert!(validation_error(&config).contains("pre_buffer_duration must not be negative"));
+        config.activation.trigger = Trigger::PushToTalk {
+            streaming: false,
+            release_grace_ms: 0,
+            pre_buffer_duration: 0.3,
+        };
+        assert_eq!(validation_error(&config), "");
+    }
+
+    #[test]
+    fn test_vad_coast_prob_validation() -> Result<()> {
+        let vad = |threshold, coast_prob| Trigger::ToggleVad {
+            threshold,
+            silence_duration: 2.0,
+            speech_duration: 1.0,
+            pre_buffer_duration: 1.0,
+            arm_once: false,
+            coast_prob,
+            silent_skip_factor: 1,
+            auto_start: false,
+            warmup_duration: 0.0,
+            vad_model: VadModel::Silero,
+            energy_threshold: 0.01,
+            max_utterance_secs: 60.0,
+            max_chunk_secs: None,
+        };
+        Config::builder().trigger(vad(0.5, 0.0)).build()?;
+        Config::builder().trigger(vad(0.5, 0.4)).build()?;
+        // Skipped frames alone would reach the threshold
+        assert!(Config::builder().trigger(vad(0.5, 0.5)).build().is_err());
+        assert!(Config::builder().trigger(vad(0.3, 0.4)).build().is_err());
+        assert!(Config::builder().trigger(vad(0.5, -0.1)).build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vad_silent_skip_factor_validation() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.trigger = toggle_vad("silent_skip_factor = 0")?;
+        assert!(validation_error(&config).contains("silent_skip_factor must be at least 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_vad_model() -> Result<()> {
+        let activation: ActivationConfig = toml::from_str(
+            r#"
+            trigger = { type = "toggle_vad", vad_model = "silero_or_energy" }
+            keys = []
+            notify = true
+            autosend = false
+            "#,
+        )?;
+        let Trigger::ToggleVad {
+            vad_model,
+            energy_threshold,
+            ..
+        } = activation.trigger
+        else {
+            bail!("Expected toggle_vad");
+        };
+        assert_eq!(vad_model, VadModel::SileroOrEnergy);
+        assert_eq!(energy_threshold, 0.01);
+
+        let vad = |threshold, vad_model, energy_threshold| Trigger::ToggleVad {
+            threshold,
+            silence_duration: 1.0,
+            speech_duration: 0.3,
+            pre_buffer_duration: 0.0,
+            arm_once: false,
+            coast_prob: 0.0,
+            silent_skip_factor: 1,
+            auto_start: false,
+            warmup_duration: 0.0,
+            vad_model,
+            energy_threshold,
+            max_utterance_secs: 60.0,
+            max_chunk_secs: None,
+        };
+        Config::builder()
+            .trigger(vad(0.5, VadModel::Energy, 0.02))
+            .build()?;
+        assert!(
+            Config::builder()
+                .trigger(vad(0.5, VadModel::Energy, 0.0))
+                .build()
+                .is_err()
+        );
+        // Energy frames are 0.0 or 1.0, never above the threshold
+        Config::builder()
+            .trigger(vad(1.0, VadModel::Silero, 0.01))
+            .build()?;
+        assert!(
+            Config::builder()
+                .trigger(vad(1.0, VadModel::SileroOrEnergy, 0.01))
+                .build()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vad_max_utterance_validation() -> Result<()> {
+        let mut config = Config::default();
+        for secs in ["0.0", "nan", "inf", "601.0"] {
+            config.activation.trigger = toggle_vad(&format!("max_utterance_secs = {secs}"))?;
+            assert!(validation_error(&config).contains("max_utterance_secs must be positive"));
+        }
+        config.activation.trigger = toggle_vad("max_utterance_secs = 600.0")?;
+        assert_eq!(validation_error(&config), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_vad_max_chunk_validation() -> Result<()> {
+        let mut config = Config::default();
+        config.activation.trigger = toggle_vad("max_chunk_secs = 20.0")?;
+        assert_eq!(validation_error(&config), "");
+        config.activation.trigger = toggle_vad("max_chunk_secs = 0.5")?;
+        assert!(validation_error(&config).contains("max_chunk_secs must be at least 1.0"));
+        // The utterance buffer would drop samples before the cut
+        config.activation.trigger = toggle_vad("max_chunk_secs = 60.0")?;
+        assert!(validation_error(&config).contains("below max_utterance_secs (60)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_best_of() -> Result<()> {
+        let model: ModelConfig = toml::from_str(
+            r#"
+            repo = "ggerganov/whisper.cpp"
+            filename = "ggml-base.en.bin"
+            prompt = { type = "none" }
+            replacements = {}
+            adaptive_best_of = [
+                { max_duration = 15.0, best_of = 2 },
+                { max_duration = 5.0, best_of = 5 },
+            ]
+            "#,
+        )?;
+        assert_eq!(model.best_of(0.5), 5);
+        assert_eq!(model.best_of(5.0), 5);
+        assert_eq!(model.best_of(5.1), 2);
+        assert_eq!(model.best_of(15.0), 2);
+        assert_eq!(model.best_of(60.0), 1);
+
+        // Disabled by default
+        assert_eq!(ModelConfig::default().best_of(0.5), 1);
+        Ok(())
+    }
+
     #[test]
     fn test_invalid_config() {
         let toml = r#"
@@ -617,6 +3082,33 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_config_no_write() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("whispering").join("config.toml");
+
+        // Load config (should not touch the disk)
+        let config = Config::load_or_default(Some(&config_path))?;
+
+        assert_eq!(config, Config::default());
+        // Neither the file nor its directory created
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_no_write_existing() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join("config.toml");
+        let mut config = Config::default();
+        config.audio.channels = 2;
+        config.save_to_file(&config_path)?;
+
+        let loaded = Config::load_or_default(Some(&config_path))?;
+        assert_eq!(loaded.audio.channels, 2);
+        Ok(())
+    }
+
     #[test]
     fn test_config_creation() -> Result<()> {
         let temp_dir = tempdir()?;
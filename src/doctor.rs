@@ -0,0 +1,221 @@
+//! `whispering doctor`: checks that transcriptions can reach the focused window.
+//!
+//! Dictation "doing nothing" usually means the platform silently blocks the
+//! clipboard or synthetic input, so each backend used by [`crate::keyboard`]
+//! is exercised and reported separately. The audio input stream is opened
+//! briefly as well, for recordings coming out silent.
+
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow, bail};
+use rdev::{EventType, Key, listen, simulate};
+
+use crate::audio::describe_stream;
+use crate::config::Config;
+
+/// Outcome of a single diagnostic.
+struct Check {
+    name: &'static str,
+    result: Result<String>,
+}
+
+/// Runs every diagnostic and prints a report, failing if any check failed.
+pub fn run(config: &Config) -> Result<()> {
+    let sentinel = format!(
+        "whispering doctor {}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
+    );
+    let mut checks = clipboard_checks(&sentinel);
+    checks.push(Check {
+        name: "synthetic input",
+        result: synthetic_input(),
+    });
+    checks.push(Check {
+        name: "audio input",
+        result: describe_stream(&config.audio),
+    });
+
+    let (report, ok) = report(&checks);
+    println!("{report}");
+    if !ok {
+        bail!("Some checks failed");
+    }
+    Ok(())
+}
+
+/// Formats the report, returns whether all checks passed.
+fn report(checks: &[Check]) -> (String, bool) {
+    let mut ok = true;
+    let lines: Vec<String> = checks
+        .iter()
+        .map(|check| match &check.result {
+            Ok(detail) => format!("[ok]     {}: {detail}", check.name),
+            Err(err) => {
+                ok = false;
+                format!("[failed] {}: {err:#}", check.name)
+            }
+        })
+        .collect();
+    (lines.join("\n"), ok)
+}
+
+/// Sets `text` on a clipboard and reads it back.
+fn clipboard_round_trip(
+    text: &str,
+    set: impl FnOnce(&str) -> Result<()>,
+    get: impl FnOnce() -> Result<String>,
+) -> Result<String> {
+    set(text).map_err(|err| anyhow!("Cannot set the clipboard: {err:#}"))?;
+    let read = get().map_err(|err| anyhow!("Cannot read the clipboard: {err:#}"))?;
+    if read.trim_end() != text {
+        bail!("Clipboard returned {read:?} instead of {text:?}");
+    }
+    Ok("clipboard set and read back".to_string())
+}
+
+#[cfg(any(not(target_os = "linux"), feature = "x11"))]
+fn arboard_check(text: &str) -> Check {
+    let result = arboard::Clipboard::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut clipboard| {
+            let mut reader = arboard::Clipboard::new()?;
+            clipboard_round_trip(
+                text,
+                |text| Ok(clipboard.set_text(text)?),
+                || Ok(reader.get_text()?),
+            )
+        });
+    Check {
+        name: "clipboard (arboard)",
+        result,
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+fn wayland_check(text: &str) -> Check {
+    use std::io::Read;
+    use wl_clipboard_rs::{copy, paste};
+
+    let result = clipboard_round_trip(
+        text,
+        |text| {
+            copy::Options::new().copy(
+                copy::Source::Bytes(text.as_bytes().into()),
+                copy::MimeType::Text,
+            )?;
+            Ok(())
+        },
+        || {
+            let (mut pipe, _) = paste::get_contents(
+                paste::ClipboardType::Regular,
+                paste::Seat::Unspecified,
+                paste::MimeType::Text,
+            )?;
+            let mut read = String::new();
+            pipe.read_to_string(&mut read)?;
+            Ok(read)
+        },
+    );
+    Check {
+        name: "clipboard (wl-clipboard)",
+        result,
+    }
+}
+
+/// The clipboard backends used to paste on this platform.
+fn clipboard_checks(text: &str) -> Vec<Check> {
+    vec![
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
+        wayland_check(text),
+        #[cfg(any(not(target_os = "linux"), feature = "x11"))]
+        arboard_check(text),
+    ]
+}
+
+/// Simulates a harmless Shift tap and waits for the keyboard listener to
+/// observe it.
+///
+/// Pasting into a real window would type into whatever has focus, so this
+/// only checks that synthetic events are accepted and delivered.
+fn synthetic_input() -> Result<String> {
+    let (tx, rx) = mpsc::channel();
+    let listen_tx = tx.clone();
+    std::thread::spawn(move || {
+        let result = listen(move |event| {
+            if let EventType::KeyPress(key) = event.event_type {
+                let _ = listen_tx.send(Ok(key));
+            }
+        });
+        if let Err(err) = result {
+            let _ = tx.send(Err(anyhow!("Cannot listen for keyboard events: {err:?}")));
+        }
+    });
+    // Give the listener time to attach.
+    std::thread::sleep(Duration::from_millis(300));
+
+    simulate(&EventType::KeyPress(Key::ShiftLeft))
+        .map_err(|err| anyhow!("Synthetic key press rejected: {err:?}"))?;
+    std::thread::sleep(Duration::from_millis(2));
+    simulate(&EventType::KeyRelease(Key::ShiftLeft))
+        .map_err(|err| anyhow!("Synthetic key release rejected: {err:?}"))?;
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(1);
+    loop {
+        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(Key::ShiftLeft)) => {
+                return Ok("simulated key press was delivered".to_string());
+            }
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => bail!(
+                "Simulated key press was accepted but never observed, synthetic input may be blocked"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_round_trip() {
+        let result = clipboard_round_trip("hello", |_| Ok(()), || Ok("hello\n".to_string()));
+        assert!(result.is_ok());
+
+        let result = clipboard_round_trip("hello", |_| Ok(()), || Ok("stale".to_string()));
+        assert!(result.is_err());
+
+        let result = clipboard_round_trip("hello", |_| bail!("no display"), || Ok(String::new()));
+        let err = result.err().map(|err| err.to_string()).unwrap_or_default();
+        assert!(
+            err.contains("Cannot set the clipboard: no display"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let checks = vec![
+            Check {
+                name: "clipboard (arboard)",
+                result: Ok("clipboard set and read back".to_string()),
+            },
+            Check {
+                name: "synthetic input",
+                result: Err(anyhow!("blocked")),
+            },
+        ];
+        let (text, ok) = report(&checks);
+        assert!(!ok);
+        assert_eq!(
+            text,
+            "[ok]     clipboard (arboard): clipboard set and read back\n\
+             [failed] synthetic input: blocked"
+        );
+        let (_, ok) = report(&checks[..1]);
+        assert!(ok);
+    }
+}
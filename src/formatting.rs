@@ -0,0 +1,486 @@
+//! Transcript post-processing.
+//!
+//! Optional transforms applied to the Whisper output after replacements,
+//! configured through the `[formatting]` section.
+
+use anyhow::{Context, Result};
+use jiff::Zoned;
+use jiff::fmt::strtime;
+
+use crate::config::{FormattingConfig, Normalization};
+
+/// Spoken punctuation names and the symbol they stand for.
+/// Multi-word names come first so they win over their prefixes.
+const SPOKEN_PUNCTUATION: &[(&str, &str)] = &[
+    ("question mark", "?"),
+    ("exclamation mark", "!"),
+    ("exclamation point", "!"),
+    ("full stop", "."),
+    ("period", "."),
+    ("comma", ","),
+    ("semicolon", ";"),
+    ("colon", ":"),
+];
+
+/// Typographic characters and their ASCII forms.
+const STRAIGHT_QUOTES: &[(char, &str)] = &[
+    ('\u{2018}', "'"),
+    ('\u{2019}', "'"),
+    ('\u{201A}', "'"),
+    ('\u{201B}', "'"),
+    ('\u{2032}', "'"),
+    ('\u{201C}', "\""),
+    ('\u{201D}', "\""),
+    ('\u{201E}', "\""),
+    ('\u{201F}', "\""),
+    ('\u{2033}', "\""),
+    ('\u{00AB}', "\""),
+    ('\u{00BB}', "\""),
+    ('\u{2010}', "-"),
+    ('\u{2011}', "-"),
+    ('\u{2012}', "-"),
+    ('\u{2013}', "-"),
+    ('\u{2014}', "-"),
+    ('\u{2015}', "-"),
+    ('\u{2026}', "..."),
+    ('\u{00A0}', " "),
+];
+
+/// Applies the enabled formatting transforms to a transcript.
+pub fn format_transcript(text: String, config: &FormattingConfig) -> String {
+    let mut text = text;
+    if config.spoken_punctuation {
+        text = spoken_punctuation(&text);
+    }
+    if let Some(form) = config.normalize {
+        text = normalize(&text, form);
+    }
+    if config.ascii_fold {
+        text = ascii_fold(&text);
+    }
+    if config.straight_quotes {
+        text = straight_quotes(&text);
+    }
+    if config.code_mode {
+        text = code_mode(&text);
+    }
+    if config.strip_symbols {
+        text = strip_symbols(&text, &config.symbol_allowlist);
+    }
+    text
+}
+
+/// Wraps the transcript in the configured `prefix` and `suffix`, their
+/// strftime placeholders (`%H:%M`, `%Y-%m-%d`, ...) rendered at `now`. The
+/// transcript is left plain when they can't be rendered, rather than lost.
+pub fn decorate(text: &str, config: &FormattingConfig, now: &Zoned) -> String {
+    if config.prefix.is_empty() && config.suffix.is_empty() {
+        return text.to_string();
+    }
+    let rendered = strtime::format(&config.prefix, now)
+        .context("Rendering formatting.prefix")
+        .and_then(|prefix| {
+            let suffix =
+                strtime::format(&config.suffix, now).context("Rendering formatting.suffix")?;
+            Ok(format!("{prefix}{text}{suffix}"))
+        });
+    rendered.unwrap_or_else(|err| {
+        log::warn!("Pasting the transcript without its prefix and suffix: {err:#}");
+        text.to_string()
+    })
+}
+
+/// Checks that `format` only uses strftime placeholders that can be rendered.
+pub fn check_time_format(format: &str) -> Result<()> {
+    let now = jiff::Timestamp::UNIX_EPOCH.to_zoned(jiff::tz::TimeZone::UTC);
+    strtime::format(format, &now)?;
+    Ok(())
+}
+
+/// Normalizes `text` to the Unicode normalization `form`.
+#[cfg(feature = "formatting")]
+fn normalize(text: &str, form: Normalization) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    match form {
+        Normalization::Nfc => text.nfc().collect(),
+        Normalization::Nfkc => text.nfkc().collect(),
+    }
+}
+
+// Never enabled without the `formatting` feature, `Config::validate` rejects
+// it, as for the stubs below.
+#[cfg(not(feature = "formatting"))]
+fn normalize(text: &str, _form: Normalization) -> String {
+    text.to_string()
+}
+
+/// Removes diacritics: letters are decomposed and their combining marks
+/// dropped. Other non-ASCII characters are kept.
+#[cfg(feature = "formatting")]
+fn ascii_fold(text: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    use unicode_normalization::char::is_combining_mark;
+    text.nfkd()
+        .filter(|&c| !is_combining_mark(c))
+        .nfc()
+        .collect()
+}
+
+#[cfg(not(feature = "formatting"))]
+fn ascii_fold(text: &str) -> String {
+    text.to_string()
+}
+
+/// Whether `c` is a symbol or control character removed by `strip_symbols`.
+///
+/// Math symbols (`+`, `<`, `|`, ...) and ASCII modifiers (`^`, `` ` ``) are
+/// kept for code dictation. Emoji joiners, variation selectors and skin tone
+/// modifiers go with the emoji they belong to.
+#[cfg(feature = "formatting")]
+fn is_stripped_symbol(c: char) -> bool {
+    use unicode_general_category::{GeneralCategory, get_general_category};
+    match get_general_category(c) {
+        GeneralCategory::OtherSymbol
+        | GeneralCategory::CurrencySymbol
+        | GeneralCategory::EnclosingMark
+        | GeneralCategory::Format
+        | GeneralCategory::PrivateUse
+        | GeneralCategory::Unassigned => true,
+        GeneralCategory::ModifierSymbol => !c.is_ascii(),
+        GeneralCategory::Control => !c.is_whitespace(),
+        // Variation selectors, e.g. the emoji presentation of "❤️"
+        GeneralCategory::NonspacingMark => ('\u{FE00}'..='\u{FE0F}').contains(&c),
+        _ => false,
+    }
+}
+
+/// Removes symbols and control characters except the allowlisted ones,
+/// collapsing the spaces they leave.
+#[cfg(feature = "formatting")]
+fn strip_symbols(text: &str, allowlist: &str) -> String {
+    let stripped: String = text
+        .chars()
+        .filter(|&c| allowlist.contains(c) || !is_stripped_symbol(c))
+        .collect();
+    if stripped.len() == text.len() {
+        return stripped;
+    }
+    stripped
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(not(feature = "formatting"))]
+fn strip_symbols(text: &str, _allowlist: &str) -> String {
+    text.to_string()
+}
+
+/// Replaces smart quotes, dashes and ellipses with their ASCII forms.
+fn straight_quotes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match STRAIGHT_QUOTES.iter().find(|(from, _)| *from == c) {
+            Some((_, to)) => output.push_str(to),
+            None => output.push(c),
+        }
+    }
+    output
+}
+
+/// Punctuation written without a space before it in code mode.
+const CLOSING: &str = ",.;:!?)]}";
+
+/// Brackets written without a space after them in code mode.
+const OPENING: &str = "([{";
+
+/// Keywords keeping the space before a bracket, unlike function calls.
+const KEYWORDS: &[&str] = &["if", "for", "while", "match", "return", "switch", "in"];
+
+/// Code-friendly transforms for dictating identifiers, line by line.
+///
+/// A spoken "dot" (or a lone ".") joins its neighbours, "self dot value"
+/// becomes "self.value". Spaces before closing punctuation and around
+/// brackets are removed, "print ( x , y )" becomes "print(x, y)" (but
+/// "if (x)" keeps its space). Words Whisper capitalized are lowercased
+/// unless they start a sentence; words with other capitals (`HTTP`,
+/// `getValue`) are kept.
+fn code_mode(text: &str) -> String {
+    text.split('\n')
+        .map(code_mode_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn code_mode_line(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut output = String::with_capacity(text.len());
+    // The next word is glued to the output
+    let mut glued = false;
+    for (i, &word) in words.iter().enumerate() {
+        let last = output.chars().last();
+        let dot = (word == "." || word.eq_ignore_ascii_case("dot"))
+            && last.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == ')' || c == ']')
+            && i + 1 < words.len();
+        if dot {
+            output.push('.');
+            glued = true;
+            continue;
+        }
+        let first = word.chars().next();
+        let keyword = i > 0 && KEYWORDS.contains(&words[i - 1].to_lowercase().as_str());
+        let call = first.is_some_and(|c| c == '(' || c == '[')
+            && last.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            && !keyword;
+        let closing = first.is_some_and(|c| CLOSING.contains(c));
+        let opened = last.is_some_and(|c| OPENING.contains(c));
+        if !output.is_empty() && !glued && !call && !closing && !opened {
+            output.push(' ');
+        }
+        // A sentence starts after a period followed by a space
+        let sentence = !glued && last.is_some_and(|c| ".!?".contains(c));
+        if sentence {
+            output.push_str(word);
+        } else {
+            output.push_str(&lowercase_capitalized(word));
+        }
+        glued = false;
+    }
+    output
+}
+
+/// `word` with its first letter lowercased when it is the only capital.
+fn lowercase_capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) if first.is_uppercase() && !chars.any(char::is_uppercase) => {
+            first.to_lowercase().chain(word.chars().skip(1)).collect()
+        }
+        _ => word.to_string(),
+    }
+}
+
+/// Lowercased word without the punctuation Whisper may have attached to it.
+fn bare_word(word: &str) -> String {
+    word.trim_matches(|c: char| c.is_ascii_punctuation())
+        .to_lowercase()
+}
+
+/// Number of words matched by `name` at the start of `words`, if any.
+fn match_name(words: &[&str], name: &str) -> Option<usize> {
+    let parts: Vec<&str> = name.split(' ').collect();
+    if words.len() < parts.len() {
+        return None;
+    }
+    let matches = words
+        .iter()
+        .zip(&parts)
+        .all(|(word, part)| bare_word(word) == *part);
+    matches.then_some(parts.len())
+}
+
+/// Replaces spoken punctuation ("comma", "question mark", ...) with symbols.
+///
+/// Only standalone words are replaced, so "commander" or "periodic" are left
+/// alone. The symbol is attached to the previous word, dropping any
+/// punctuation Whisper already added around the spoken name. Line breaks
+/// are kept.
+fn spoken_punctuation(text: &str) -> String {
+    text.split('\n')
+        .map(spoken_punctuation_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn spoken_punctuation_line(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut output = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < words.len() {
+        let replacement = SPOKEN_PUNCTUATION
+            .iter()
+            .find_map(|(name, symbol)| Some((match_name(&words[i..], name)?, symbol)));
+        if let Some((n, symbol)) = replacement {
+            // Drop the punctuation Whisper put before the spoken name.
+            let trimmed =
+                output.trim_end_matches(|c: char| c.is_whitespace() || ",.;:!?".contains(c));
+            output.truncate(trimmed.len());
+            output.push_str(symbol);
+            i += n;
+        } else {
+            if !output.is_empty() {
+                output.push(' ');
+            }
+            output.push_str(words[i]);
+            i += 1;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spoken_punctuation() {
+        assert_eq!(
+            spoken_punctuation("Hello comma how are you question mark"),
+            "Hello, how are you?"
+        );
+        assert_eq!(spoken_punctuation("Wait exclamation point"), "Wait!");
+        assert_eq!(
+            spoken_punctuation("First colon apples semicolon pears full stop"),
+            "First: apples; pears."
+        );
+    }
+
+    #[test]
+    fn test_spoken_punctuation_keeps_lines() {
+        assert_eq!(
+            spoken_punctuation("First comma done period\nSecond  line question mark"),
+            "First, done.\nSecond line?"
+        );
+    }
+
+    #[test]
+    fn test_spoken_punctuation_whisper_punctuated() {
+        // Whisper often adds its own punctuation around the spoken names.
+        assert_eq!(
+            spoken_punctuation("Hello, comma, world. Period."),
+            "Hello, world."
+        );
+        assert_eq!(spoken_punctuation("Is it Question mark?"), "Is it?");
+    }
+
+    #[test]
+    fn test_straight_quotes() {
+        assert_eq!(
+            straight_quotes("\u{201C}It\u{2019}s fine\u{201D} \u{2014} 1\u{2013}2\u{2026}"),
+            "\"It's fine\" - 1-2..."
+        );
+        assert_eq!(straight_quotes("plain 'text'"), "plain 'text'");
+    }
+
+    #[cfg(feature = "formatting")]
+    #[test]
+    fn test_normalize() {
+        // "e" followed by a combining acute accent
+        let decomposed = "cafe\u{301}";
+        assert_eq!(decomposed.chars().count(), 5);
+        let composed = normalize(decomposed, Normalization::Nfc);
+        assert_eq!(composed, "caf\u{e9}");
+        assert_eq!(composed.chars().count(), 4);
+        // Only NFKC folds compatibility characters
+        assert_eq!(normalize("\u{FB01}ne", Normalization::Nfc), "\u{FB01}ne");
+        assert_eq!(normalize("\u{FB01}ne", Normalization::Nfkc), "fine");
+    }
+
+    #[cfg(feature = "formatting")]
+    #[test]
+    fn test_ascii_fold() {
+        assert_eq!(ascii_fold("Cr\u{e8}me br\u{fb}l\u{e9}e"), "Creme brulee");
+        assert_eq!(ascii_fold("cafe\u{301}"), "cafe");
+        // Characters without an ASCII base are kept
+        assert_eq!(ascii_fold("\u{65e5}\u{672c}"), "\u{65e5}\u{672c}");
+    }
+
+    #[cfg(feature = "formatting")]
+    #[test]
+    fn test_strip_symbols() {
+        let text = "Ship it \u{1F680} for $5 \u{266A}";
+        assert_eq!(strip_symbols(text, ""), "Ship it for 5");
+        assert_eq!(strip_symbols(text, "$"), "Ship it for $5");
+        // Multi code point emoji, control characters
+        assert_eq!(
+            strip_symbols("Love \u{2764}\u{FE0F} \u{1F44D}\u{1F3FD}\u{200D}x\u{7}", ""),
+            "Love x"
+        );
+        // Code stays intact
+        let code = "if a <= b && c != 'd' { x ^= `y` | z + 1; }";
+        assert_eq!(strip_symbols(code, ""), code);
+        assert_eq!(
+            strip_symbols("Caf\u{e9} na\u{ef}ve", ""),
+            "Caf\u{e9} na\u{ef}ve"
+        );
+    }
+
+    #[test]
+    fn test_spoken_punctuation_false_positives() {
+        let text = "The commander periodically questioned the markings";
+        assert_eq!(spoken_punctuation(text), text);
+        let text = "A question about the colonel";
+        assert_eq!(spoken_punctuation(text), text);
+    }
+
+    #[test]
+    fn test_format_transcript_disabled() {
+        let config = FormattingConfig::default();
+        let text = "Hello comma world".to_string();
+        assert_eq!(format_transcript(text.clone(), &config), text);
+
+        let config = FormattingConfig {
+            spoken_punctuation: true,
+            ..FormattingConfig::default()
+        };
+        assert_eq!(format_transcript(text, &config), "Hello, world");
+    }
+
+    #[test]
+    fn test_code_mode() {
+        // Spoken and transcribed dots join identifiers
+        assert_eq!(code_mode("Self dot value"), "self.value");
+        assert_eq!(code_mode("os . path . join"), "os.path.join");
+        assert_eq!(code_mode("Config dot Load"), "config.load");
+        // Spaces before punctuation and around brackets
+        assert_eq!(code_mode("Print ( x , y )"), "print(x, y)");
+        assert_eq!(code_mode("items [ 0 ] ;"), "items[0];");
+        assert_eq!(code_mode("if ( ready ) {"), "if (ready) {");
+        // Capitals other than the first one are kept
+        assert_eq!(code_mode("Call getValue on HTTP"), "call getValue on HTTP");
+        // Sentences keep their first capital
+        assert_eq!(code_mode("Done. Next step"), "done. Next step");
+        // A trailing dot ends the sentence, a leading one is kept as is
+        assert_eq!(code_mode("Rename it dot"), "rename it dot");
+        assert_eq!(code_mode("dot files"), "dot files");
+        assert_eq!(code_mode("Foo bar\nBaz qux"), "foo bar\nbaz qux");
+        assert_eq!(code_mode(""), "");
+    }
+
+    #[test]
+    fn test_code_mode_after_spoken_punctuation() {
+        let config = FormattingConfig {
+            spoken_punctuation: true,
+            code_mode: true,
+            ..FormattingConfig::default()
+        };
+        assert_eq!(
+            format_transcript("Args dot Len comma Max ( A , B )".into(), &config),
+            "args.len, max(a, b)"
+        );
+    }
+
+    #[test]
+    fn test_decorate() -> Result<()> {
+        let now: Zoned = "2024-03-09T14:32:05[UTC]".parse()?;
+        let mut config = FormattingConfig::default();
+        assert_eq!(decorate("Buy milk", &config, &now), "Buy milk");
+
+        config.prefix = "[%H:%M] ".to_string();
+        assert_eq!(decorate("Buy milk", &config, &now), "[14:32] Buy milk");
+        config.prefix = String::new();
+        config.suffix = " (%Y-%m-%d, 100%%)".to_string();
+        assert_eq!(
+            decorate("Buy milk", &config, &now),
+            "Buy milk (2024-03-09, 100%)"
+        );
+        // Unrenderable, the transcript is kept plain
+        config.prefix = "%K".to_string();
+        assert_eq!(decorate("Buy milk", &config, &now), "Buy milk");
+
+        assert!(check_time_format("[%H:%M] ").is_ok());
+        assert!(check_time_format("%K").is_err());
+        Ok(())
+    }
+}
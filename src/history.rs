@@ -0,0 +1,121 @@
+//! Bounded in-memory history of the last transcribed recordings.
+//!
+//! Features working on past audio (re-running it with `rerun_key`) share this
+//! store instead of keeping their own copies. It holds at most
+//! `history.max_entries` recordings taking at most `history.max_mb`, the
+//! oldest being evicted first.
+
+use std::collections::VecDeque;
+
+use log::debug;
+
+use crate::config::HistoryConfig;
+
+/// A recording kept in the history, with what was made of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<T> {
+    /// 16kHz mono samples
+    pub samples: Vec<f32>,
+    pub data: T,
+}
+
+impl<T> Entry<T> {
+    /// Memory taken by the samples.
+    fn bytes(&self) -> usize {
+        self.samples.len() * size_of::<f32>()
+    }
+}
+
+/// The last recordings, oldest first.
+#[derive(Debug)]
+pub struct AudioHistory<T> {
+    entries: VecDeque<Entry<T>>,
+}
+
+impl<T> Default for AudioHistory<T> {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> AudioHistory<T> {
+    /// Adds a recording, then evicts the oldest ones beyond the limits. A
+    /// recording larger than `max_mb` on its own isn't kept.
+    pub fn push(&mut self, samples: Vec<f32>, data: T, config: &HistoryConfig) {
+        self.entries.push_back(Entry { samples, data });
+        let max_bytes =
+            usize::try_from(config.max_mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX);
+        while self.entries.len() > config.max_entries || self.bytes() > max_bytes {
+            if let Some(evicted) = self.entries.pop_front() {
+                debug!(
+                    "Evicted {:.1}s of audio from the history",
+                    evicted.samples.len() as f32 / 16000.0
+                );
+            }
+        }
+    }
+
+    /// The most recent recording.
+    pub fn last_mut(&mut self) -> Option<&mut Entry<T>> {
+        self.entries.back_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Memory taken by the kept samples.
+    pub fn bytes(&self) -> usize {
+        self.entries.iter().map(Entry::bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_oldest() {
+        let config = HistoryConfig {
+            max_entries: 2,
+            ..HistoryConfig::default()
+        };
+        let mut history = AudioHistory::default();
+        assert!(history.last_mut().is_none());
+        history.push(vec![0.1; 16], "first", &config);
+        history.push(vec![0.2; 16], "second", &config);
+        history.push(vec![0.3; 16], "third", &config);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.entries[0].data, "second");
+        assert_eq!(history.last_mut().map(|entry| entry.data), Some("third"));
+    }
+
+    #[test]
+    fn test_memory_cap() {
+        let config = HistoryConfig {
+            max_entries: 10,
+            max_mb: 1,
+        };
+        // 0.4MB each
+        let samples = || vec![0.0; 100 * 1024];
+        let mut history = AudioHistory::default();
+        for data in 0..3 {
+            history.push(samples(), data, &config);
+        }
+        // The third one only fits without the first
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.entries[0].data, 1);
+        assert!(history.bytes() <= 1024 * 1024);
+
+        // Too large on its own, not kept
+        history.push(vec![0.0; 300 * 1024], 3, &config);
+        assert!(history.is_empty());
+        assert_eq!(history.bytes(), 0);
+    }
+}
@@ -0,0 +1,170 @@
+//! Commands run when recording starts and stops, see `[hooks]`.
+//!
+//! Hooks run in the background through the shell (`sh -c`, `cmd /C` on
+//! Windows) and are killed when still running after `timeout_ms`. Failures
+//! are logged, never fatal.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+
+use crate::config::HooksConfig;
+
+/// How often a running hook is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A change of the recording state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transition {
+    Start,
+    Stop,
+}
+
+/// Runs the hooks once per recording transition.
+#[derive(Debug, Default)]
+pub struct Hooks {
+    recording: bool,
+}
+
+impl Hooks {
+    /// The transition to `recording`, `None` when it didn't change.
+    fn transition(&mut self, recording: bool) -> Option<Transition> {
+        if recording == self.recording {
+            return None;
+        }
+        self.recording = recording;
+        Some(if recording {
+            Transition::Start
+        } else {
+            Transition::Stop
+        })
+    }
+
+    /// Runs the hook of the transition to `recording` in the background, a
+    /// no-op when recording didn't start or stop.
+    pub fn update(&mut self, recording: bool, config: &HooksConfig) {
+        let Some(transition) = self.transition(recording) else {
+            return;
+        };
+        let command = match transition {
+            Transition::Start => &config.on_start,
+            Transition::Stop => &config.on_stop,
+        };
+        let Some(command) = command.clone() else {
+            return;
+        };
+        let timeout = Duration::from_millis(config.timeout_ms);
+        std::thread::spawn(move || {
+            debug!("Running the {transition:?} hook: {command}");
+            if let Err(err) = run(&command, timeout) {
+                warn!("The {transition:?} hook failed: {err:#}");
+            }
+        });
+    }
+}
+
+/// `command` run by the shell.
+fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Runs `command`, killing it when still running after `timeout`.
+fn run(command: &str, timeout: Duration) -> Result<()> {
+    let mut child = shell(command)
+        .stdin(Stdio::null())
+        .spawn()
+        .context(format!("Running {command}"))?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("{command} exited with {status}");
+            }
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            bail!("{command} still running after {timeout:?}, killed");
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let mut hooks = Hooks::default();
+        assert_eq!(hooks.transition(false), None);
+        assert_eq!(hooks.transition(true), Some(Transition::Start));
+        // Still recording, e.g. the keys repeating
+        assert_eq!(hooks.transition(true), None);
+        assert_eq!(hooks.transition(false), Some(Transition::Stop));
+        assert_eq!(hooks.transition(false), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ran");
+        run(&format!("touch {}", path.display()), Duration::from_secs(5))?;
+        assert!(path.exists());
+        assert!(run("exit 3", Duration::from_secs(5)).is_err());
+
+        let start = Instant::now();
+        assert!(run("sleep 5", Duration::from_millis(100)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_on_transitions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("hooks.log");
+        let config = HooksConfig {
+            on_start: Some(format!("echo start >> {}", log.display())),
+            on_stop: Some(format!("echo stop >> {}", log.display())),
+            ..HooksConfig::default()
+        };
+        let mut hooks = Hooks::default();
+        hooks.update(true, &config);
+        hooks.update(true, &config);
+        hooks.update(false, &config);
+        hooks.update(false, &config);
+
+        let start = Instant::now();
+        let mut lines = Vec::new();
+        while lines.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(POLL_INTERVAL);
+            lines = std::fs::read_to_string(&log)
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect();
+        }
+        // Once per transition, the hooks running concurrently
+        lines.sort();
+        assert_eq!(lines, ["start", "stop"]);
+        Ok(())
+    }
+}
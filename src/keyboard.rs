@@ -1,44 +1,278 @@
 //! Keyboard input simulation functionality.
 //!
 //! This module provides utilities for simulating keyboard input, including
-//! character-to-key mapping and text pasting functionality.
+//! character-to-key mapping and text pasting functionality. The events and
+//! the clipboard go through a [`Keyboard`], so tests can record them instead
+//! of sending them to the OS.
 
 use std::time::Duration;
 
-use anyhow::Result;
-use log::{debug, info};
-use rdev::{EventType, Key, simulate};
+use anyhow::{Result, anyhow, bail};
+use log::{debug, info, warn};
+use rdev::{EventType, Key};
 
-/// Simulates typing the given text by generating keyboard events.
-///
-/// This function takes a string and simulates typing it by generating
-/// appropriate key press and release events. It handles both regular
-/// characters and special characters that require the shift key.
-pub fn paste(output: String) -> Result<()> {
-    info!("Simulating keyboard input: {}", output);
+use crate::config::{ActivationConfig, InputWarmup, OutputMode};
+
+/// The input events and clipboard used to paste and type.
+pub trait Keyboard {
+    /// Sends a synthetic input event.
+    fn simulate(&mut self, event: &EventType) -> Result<()>;
+
+    /// Puts `text` in the clipboard.
+    fn set_clipboard(&mut self, text: String) -> Result<()>;
+
+    /// Reads the clipboard back.
+    fn clipboard_text(&mut self) -> Result<String>;
+}
+
+/// The OS keyboard and clipboard.
+#[derive(Debug, Default)]
+pub struct System {
+    /// MIME type of the Wayland clipboard, autodetected if not specified
+    clipboard_mime: Option<String>,
+}
+
+impl System {
+    pub fn new(config: &ActivationConfig) -> Self {
+        Self {
+            clipboard_mime: config.clipboard_mime.clone(),
+        }
+    }
+}
+
+impl Keyboard for System {
+    fn simulate(&mut self, event: &EventType) -> Result<()> {
+        Ok(rdev::simulate(event)?)
+    }
+
+    fn set_clipboard(&mut self, text: String) -> Result<()> {
+        set_clipboard(text, self.clipboard_mime.as_deref())
+    }
+
+    fn clipboard_text(&mut self) -> Result<String> {
+        clipboard_text()
+    }
+}
+
+/// Presses and releases `key`.
+pub fn tap(keyboard: &mut impl Keyboard, key: Key) -> Result<()> {
+    keyboard.simulate(&EventType::KeyPress(key))?;
+    std::thread::sleep(Duration::from_millis(2));
+    keyboard.simulate(&EventType::KeyRelease(key))?;
+    std::thread::sleep(Duration::from_millis(2));
+    Ok(())
+}
+
+/// Simulates the startup input, see [`InputWarmup`].
+pub fn warm_up(keyboard: &mut impl Keyboard, warmup: InputWarmup) -> Result<()> {
+    match warmup {
+        InputWarmup::Key { key } => {
+            debug!("Warming synthetic input up with {key:?}");
+            tap(keyboard, key)
+        }
+        InputWarmup::None => Ok(()),
+    }
+}
+
+/// Erases the `count` characters before the cursor with Backspace, to
+/// replace the last paste.
+pub fn erase(keyboard: &mut impl Keyboard, count: usize) -> Result<()> {
+    debug!("Erasing {count} characters");
+    for _ in 0..count {
+        tap(keyboard, Key::Backspace)?;
+    }
+    Ok(())
+}
+
+/// Keys typing `c` on a US layout, with whether Shift is held.
+fn char_key(c: char) -> Option<(Key, bool)> {
+    const LETTERS: [Key; 26] = [
+        Key::KeyA,
+        Key::KeyB,
+        Key::KeyC,
+        Key::KeyD,
+        Key::KeyE,
+        Key::KeyF,
+        Key::KeyG,
+        Key::KeyH,
+        Key::KeyI,
+        Key::KeyJ,
+        Key::KeyK,
+        Key::KeyL,
+        Key::KeyM,
+        Key::KeyN,
+        Key::KeyO,
+        Key::KeyP,
+        Key::KeyQ,
+        Key::KeyR,
+        Key::KeyS,
+        Key::KeyT,
+        Key::KeyU,
+        Key::KeyV,
+        Key::KeyW,
+        Key::KeyX,
+        Key::KeyY,
+        Key::KeyZ,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Num0,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+    ];
+    /// The characters typed with Shift and a digit, from 0 to 9
+    const SHIFTED_DIGITS: &str = ")!@#$%^&*(";
+    /// Other keys, typed without and with Shift
+    const SYMBOLS: &[(Key, char, char)] = &[
+        (Key::Space, ' ', ' '),
+        (Key::Return, '\n', '\n'),
+        (Key::Tab, '\t', '\t'),
+        (Key::Minus, '-', '_'),
+        (Key::Equal, '=', '+'),
+        (Key::LeftBracket, '[', '{'),
+        (Key::RightBracket, ']', '}'),
+        (Key::BackSlash, '\\', '|'),
+        (Key::SemiColon, ';', ':'),
+        (Key::Quote, '\'', '"'),
+        (Key::Comma, ',', '<'),
+        (Key::Dot, '.', '>'),
+        (Key::Slash, '/', '?'),
+        (Key::BackQuote, '`', '~'),
+    ];
+
+    if c.is_ascii_lowercase() {
+        return Some((LETTERS[(c as u8 - b'a') as usize], false));
+    }
+    if c.is_ascii_uppercase() {
+        return Some((LETTERS[(c as u8 - b'A') as usize], true));
+    }
+    if c.is_ascii_digit() {
+        return Some((DIGITS[(c as u8 - b'0') as usize], false));
+    }
+    if let Some(digit) = SHIFTED_DIGITS.find(c) {
+        return Some((DIGITS[digit], true));
+    }
+    SYMBOLS.iter().find_map(|&(key, plain, shifted)| {
+        if c == plain {
+            Some((key, false))
+        } else if c == shifted {
+            Some((key, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// Keystrokes typing `text` on a US layout, fails on characters without a
+/// key (accents, symbols, ...) before anything is typed.
+fn keystrokes(text: &str) -> Result<Vec<(Key, bool)>> {
+    text.chars()
+        .map(|c| char_key(c).ok_or(anyhow!("Cannot type {c:?} with a US keyboard layout")))
+        .collect()
+}
+
+/// Types `text` key by key, for when pasting doesn't work. Assumes a US
+/// keyboard layout.
+pub fn type_text(keyboard: &mut impl Keyboard, text: &str) -> Result<()> {
+    debug!("Typing {} characters", text.chars().count());
+    for (key, shift) in keystrokes(text)? {
+        if shift {
+            keyboard.simulate(&EventType::KeyPress(Key::ShiftLeft))?;
+        }
+        keyboard.simulate(&EventType::KeyPress(key))?;
+        std::thread::sleep(Duration::from_millis(2));
+        keyboard.simulate(&EventType::KeyRelease(key))?;
+        if shift {
+            keyboard.simulate(&EventType::KeyRelease(Key::ShiftLeft))?;
+        }
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    Ok(())
+}
+
+/// Splits `text` into runs of characters typed key by key and runs of
+/// characters without a key, with whether the run is typed.
+fn typed_runs(text: &str) -> Vec<(&str, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut typed = None;
+    for (i, c) in text.char_indices() {
+        let key = char_key(c).is_some();
+        if let Some(typed) = typed.filter(|&typed| typed != key) {
+            runs.push((&text[start..i], typed));
+            start = i;
+        }
+        typed = Some(key);
+    }
+    if let Some(typed) = typed {
+        runs.push((&text[start..], typed));
+    }
+    runs
+}
+
+/// Types the characters of `text` that have a key, for when pasting doesn't
+/// work. Fails once they're typed when the others (accents, emoji, ...) were
+/// skipped, naming them.
+fn type_what_it_can(keyboard: &mut impl Keyboard, text: &str) -> Result<()> {
+    let (typed, skipped): (String, String) = text.chars().partition(|&c| char_key(c).is_some());
+    type_text(keyboard, &typed)?;
+    if !skipped.is_empty() {
+        bail!("Typed the transcript without {skipped:?}, no key types them on a US layout");
+    }
+    Ok(())
+}
+
+/// Types `text` key by key, pasting the runs of characters without a key
+/// (accents, emoji, ...) through the clipboard instead.
+fn type_or_paste(keyboard: &mut impl Keyboard, text: &str) -> Result<()> {
+    info!("Typing: {text}");
+    for (run, typed) in typed_runs(text) {
+        if typed {
+            type_text(keyboard, run)?;
+        } else {
+            debug!("No key types {run:?}, pasting it");
+            keyboard.set_clipboard(run.to_string())?;
+            press_paste(keyboard)?;
+        }
+    }
+    Ok(())
+}
+
+/// The MIME type offered on the Wayland clipboard.
+#[cfg(feature = "wayland")]
+fn wayland_mime_type(mime: Option<&str>) -> wl_clipboard_rs::copy::MimeType {
+    use wl_clipboard_rs::copy::MimeType;
+    match mime {
+        Some(mime) => MimeType::Specific(mime.to_string()),
+        None => MimeType::Autodetect,
+    }
+}
+
+/// Puts `output` in the clipboard, offered as `mime` on Wayland
+/// (autodetected if not specified).
+#[cfg_attr(not(feature = "wayland"), allow(unused_variables))]
+fn set_clipboard(output: String, mime: Option<&str>) -> Result<()> {
     debug!("Getting clipboard");
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
     {
         let mut clipboard = arboard::Clipboard::new()?;
         clipboard.set_text(output)?;
-        simulate(&EventType::KeyPress(Key::MetaLeft))?;
-        std::thread::sleep(Duration::from_millis(2));
-        simulate(&EventType::KeyPress(Key::KeyV))?;
-        std::thread::sleep(Duration::from_millis(2));
-        simulate(&EventType::KeyRelease(Key::KeyV))?;
-        std::thread::sleep(Duration::from_millis(2));
-        simulate(&EventType::KeyRelease(Key::MetaLeft))?;
-        std::thread::sleep(Duration::from_millis(2));
     }
     #[cfg(target_os = "linux")]
     {
         #[cfg(feature = "wayland")]
         {
-            use wl_clipboard_rs::copy::{MimeType, Options, Source};
+            use wl_clipboard_rs::copy::{Options, Source};
             let opts = Options::new();
             opts.copy(
-                Source::Bytes(output.into_bytes().into()),
-                MimeType::Autodetect,
+                Source::Bytes(output.clone().into_bytes().into()),
+                wayland_mime_type(mime),
             )?;
         }
         #[cfg(feature = "x11")]
@@ -51,24 +285,308 @@ pub fn paste(output: String) -> Result<()> {
             compile_error!("Wayland or x11 must be active");
         }
         debug!("Clipboard set");
+    }
+    Ok(())
+}
+
+/// Reads the clipboard back.
+fn clipboard_text() -> Result<String> {
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        use std::io::Read;
+        use wl_clipboard_rs::paste::{ClipboardType, MimeType, Seat, get_contents};
+        let (mut pipe, _) =
+            get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Text)?;
+        let mut text = String::new();
+        pipe.read_to_string(&mut text)?;
+        return Ok(text);
+    }
+    Ok(arboard::Clipboard::new()?.get_text()?)
+}
+
+/// Whether the clipboard holds `output`. Assumed when it can't be read, so a
+/// working paste isn't typed twice.
+fn clipboard_holds(keyboard: &mut impl Keyboard, output: &str) -> bool {
+    match keyboard.clipboard_text() {
+        Ok(text) => text == output,
+        Err(err) => {
+            warn!("Cannot read the clipboard back, assuming the paste worked: {err}");
+            true
+        }
+    }
+}
+
+/// Presses the paste shortcut.
+fn press_paste(keyboard: &mut impl Keyboard) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        keyboard.simulate(&EventType::KeyPress(Key::MetaLeft))?;
+        std::thread::sleep(Duration::from_millis(2));
+        keyboard.simulate(&EventType::KeyPress(Key::KeyV))?;
+        std::thread::sleep(Duration::from_millis(2));
+        keyboard.simulate(&EventType::KeyRelease(Key::KeyV))?;
+        std::thread::sleep(Duration::from_millis(2));
+        keyboard.simulate(&EventType::KeyRelease(Key::MetaLeft))?;
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    #[cfg(target_os = "linux")]
+    {
         std::thread::sleep(Duration::from_millis(5));
-        simulate(&EventType::KeyPress(Key::ControlLeft))?;
+        keyboard.simulate(&EventType::KeyPress(Key::ControlLeft))?;
         debug!("Event ok");
-        simulate(&EventType::KeyPress(Key::ShiftLeft))?;
-        simulate(&EventType::KeyPress(Key::KeyV))?;
-        simulate(&EventType::KeyRelease(Key::KeyV))?;
-        simulate(&EventType::KeyRelease(Key::ShiftLeft))?;
-        simulate(&EventType::KeyRelease(Key::ControlLeft))?;
+        keyboard.simulate(&EventType::KeyPress(Key::ShiftLeft))?;
+        keyboard.simulate(&EventType::KeyPress(Key::KeyV))?;
+        keyboard.simulate(&EventType::KeyRelease(Key::KeyV))?;
+        keyboard.simulate(&EventType::KeyRelease(Key::ShiftLeft))?;
+        keyboard.simulate(&EventType::KeyRelease(Key::ControlLeft))?;
         debug!("Events simulated");
     }
     #[cfg(target_os = "windows")]
     {
-        let mut clipboard = arboard::Clipboard::new()?;
-        clipboard.set_text(output)?;
-        simulate(&EventType::KeyPress(Key::ControlLeft))?;
-        simulate(&EventType::KeyPress(Key::KeyV))?;
-        simulate(&EventType::KeyRelease(Key::KeyV))?;
-        simulate(&EventType::KeyRelease(Key::ControlLeft))?;
+        keyboard.simulate(&EventType::KeyPress(Key::ControlLeft))?;
+        keyboard.simulate(&EventType::KeyPress(Key::KeyV))?;
+        keyboard.simulate(&EventType::KeyRelease(Key::KeyV))?;
+        keyboard.simulate(&EventType::KeyRelease(Key::ControlLeft))?;
     }
     Ok(())
 }
+
+/// Pastes the given text through the clipboard.
+///
+/// With `verify`, the clipboard is read back before pressing the paste
+/// shortcut: when it doesn't hold the text (the copy silently failed, mostly
+/// on Wayland), the text is typed instead, once, without the characters
+/// lacking a key (reported in the error). With
+/// [`OutputMode::ClipboardOnly`] the text is only copied, with
+/// [`OutputMode::Type`] it is typed, only the characters without a key
+/// being pasted (not verified).
+pub fn paste(
+    keyboard: &mut impl Keyboard,
+    output: String,
+    mode: OutputMode,
+    verify: bool,
+) -> Result<()> {
+    match mode {
+        OutputMode::Paste => {}
+        OutputMode::ClipboardOnly => {
+            info!("Copying to the clipboard: {output}");
+            return keyboard.set_clipboard(output);
+        }
+        OutputMode::Type => return type_or_paste(keyboard, &output),
+    }
+    info!("Simulating keyboard input: {}", output);
+    keyboard.set_clipboard(output.clone())?;
+    if verify && !clipboard_holds(keyboard, &output) {
+        warn!("The clipboard doesn't hold the transcript, typing it instead");
+        return type_what_it_can(keyboard, &output);
+    }
+    press_paste(keyboard)
+}
+
+/// Records the events and the clipboard instead of using the OS ones.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    pub(crate) events: Vec<EventType>,
+    pub(crate) clipboard: Option<String>,
+    /// Setting the clipboard silently does nothing
+    pub(crate) broken_clipboard: bool,
+}
+
+#[cfg(test)]
+impl Keyboard for Recorder {
+    fn simulate(&mut self, event: &EventType) -> Result<()> {
+        self.events.push(*event);
+        Ok(())
+    }
+
+    fn set_clipboard(&mut self, text: String) -> Result<()> {
+        if !self.broken_clipboard {
+            self.clipboard = Some(text);
+        }
+        Ok(())
+    }
+
+    fn clipboard_text(&mut self) -> Result<String> {
+        Ok(self.clipboard.clone().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EventType::{KeyPress, KeyRelease};
+
+    #[test]
+    fn test_char_key() {
+        assert_eq!(char_key('a'), Some((Key::KeyA, false)));
+        assert_eq!(char_key('m'), Some((Key::KeyM, false)));
+        assert_eq!(char_key('Z'), Some((Key::KeyZ, true)));
+        assert_eq!(char_key('0'), Some((Key::Num0, false)));
+        assert_eq!(char_key('7'), Some((Key::Num7, false)));
+        assert_eq!(char_key('('), Some((Key::Num9, true)));
+        assert_eq!(char_key('@'), Some((Key::Num2, true)));
+        assert_eq!(char_key('.'), Some((Key::Dot, false)));
+        assert_eq!(char_key('{'), Some((Key::LeftBracket, true)));
+        assert_eq!(char_key('~'), Some((Key::BackQuote, true)));
+        assert_eq!(char_key('?'), Some((Key::Slash, true)));
+        assert_eq!(char_key('\''), Some((Key::Quote, false)));
+        assert_eq!(char_key('\n'), Some((Key::Return, false)));
+        assert_eq!(char_key('\u{e9}'), None);
+        assert_eq!(char_key('\u{1f600}'), None);
+    }
+
+    #[test]
+    fn test_typed_runs() {
+        assert!(typed_runs("").is_empty());
+        assert_eq!(typed_runs("Hi!"), [("Hi!", true)]);
+        assert_eq!(
+            typed_runs("caf\u{e9}s \u{e0}\u{e9} ok"),
+            [
+                ("caf", true),
+                ("\u{e9}", false),
+                ("s ", true),
+                ("\u{e0}\u{e9}", false),
+                (" ok", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keystrokes() -> Result<()> {
+        assert_eq!(
+            keystrokes("Hi!")?,
+            [(Key::KeyH, true), (Key::KeyI, false), (Key::Num1, true)]
+        );
+        // Nothing is typed when a character can't be
+        assert!(keystrokes("caf\u{e9}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_and_erase() -> Result<()> {
+        let mut keyboard = Recorder::default();
+        type_text(&mut keyboard, "Hi")?;
+        erase(&mut keyboard, 1)?;
+        assert_eq!(
+            keyboard.events,
+            [
+                KeyPress(Key::ShiftLeft),
+                KeyPress(Key::KeyH),
+                KeyRelease(Key::KeyH),
+                KeyRelease(Key::ShiftLeft),
+                KeyPress(Key::KeyI),
+                KeyRelease(Key::KeyI),
+                KeyPress(Key::Backspace),
+                KeyRelease(Key::Backspace),
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "wayland")]
+    #[test]
+    fn test_wayland_mime_type() {
+        use wl_clipboard_rs::copy::MimeType;
+        assert_eq!(wayland_mime_type(None), MimeType::Autodetect);
+        assert_eq!(
+            wayland_mime_type(Some("text/plain;charset=utf-8")),
+            MimeType::Specific("text/plain;charset=utf-8".into())
+        );
+        let config = ActivationConfig {
+            clipboard_mime: Some("text/plain".into()),
+            ..ActivationConfig::default()
+        };
+        let system = System::new(&config);
+        assert_eq!(
+            wayland_mime_type(system.clipboard_mime.as_deref()),
+            MimeType::Specific("text/plain".into())
+        );
+    }
+
+    #[test]
+    fn test_warm_up() -> Result<()> {
+        let mut keyboard = Recorder::default();
+        warm_up(&mut keyboard, InputWarmup::default())?;
+        assert_eq!(
+            keyboard.events,
+            [KeyPress(Key::ControlLeft), KeyRelease(Key::ControlLeft)]
+        );
+        let mut keyboard = Recorder::default();
+        warm_up(
+            &mut keyboard,
+            InputWarmup::Key {
+                key: Key::ShiftRight,
+            },
+        )?;
+        assert_eq!(
+            keyboard.events,
+            [KeyPress(Key::ShiftRight), KeyRelease(Key::ShiftRight)]
+        );
+        let mut keyboard = Recorder::default();
+        warm_up(&mut keyboard, InputWarmup::None)?;
+        assert!(keyboard.events.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_paste() -> Result<()> {
+        let mut keyboard = Recorder::default();
+        paste(&mut keyboard, "hello".into(), OutputMode::Paste, true)?;
+        assert_eq!(keyboard.clipboard.as_deref(), Some("hello"));
+        let mut shortcut = Recorder::default();
+        press_paste(&mut shortcut)?;
+        assert_eq!(keyboard.events, shortcut.events);
+        assert!(keyboard.events.contains(&KeyPress(Key::KeyV)));
+
+        // Typed when the clipboard didn't take it
+        let mut keyboard = Recorder {
+            broken_clipboard: true,
+            ..Default::default()
+        };
+        paste(&mut keyboard, "ok".into(), OutputMode::Paste, true)?;
+        let mut typed = Recorder::default();
+        type_text(&mut typed, "ok")?;
+        assert_eq!(keyboard.events, typed.events);
+        // What can be typed is, the rest reported
+        let mut keyboard = Recorder {
+            broken_clipboard: true,
+            ..Default::default()
+        };
+        let result = paste(
+            &mut keyboard,
+            "caf\u{e9} ok".into(),
+            OutputMode::Paste,
+            true,
+        );
+        let err = result.err().map(|err| err.to_string()).unwrap_or_default();
+        assert!(err.contains("\"\u{e9}\""), "{err}");
+        let mut typed = Recorder::default();
+        type_text(&mut typed, "caf ok")?;
+        assert_eq!(keyboard.events, typed.events);
+        // Not checked without verify_paste
+        let mut keyboard = Recorder {
+            broken_clipboard: true,
+            ..Default::default()
+        };
+        paste(&mut keyboard, "ok".into(), OutputMode::Paste, false)?;
+        assert_eq!(keyboard.events, shortcut.events);
+
+        // Only copied, nothing pressed nor typed
+        let mut keyboard = Recorder::default();
+        paste(&mut keyboard, "hi".into(), OutputMode::ClipboardOnly, true)?;
+        assert_eq!(keyboard.clipboard.as_deref(), Some("hi"));
+        assert!(keyboard.events.is_empty());
+
+        // Typed, the accent pasted
+        let mut keyboard = Recorder::default();
+        paste(&mut keyboard, "a\u{e9}b".into(), OutputMode::Type, true)?;
+        let mut expected = Recorder::default();
+        type_text(&mut expected, "a")?;
+        press_paste(&mut expected)?;
+        type_text(&mut expected, "b")?;
+        assert_eq!(keyboard.events, expected.events);
+        assert_eq!(keyboard.clipboard.as_deref(), Some("\u{e9}"));
+        Ok(())
+    }
+}
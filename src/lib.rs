@@ -1,8 +1,18 @@
 #![doc = include_str!("../README.md")]
+#![deny(clippy::panic, clippy::unwrap_used, clippy::expect_used)]
 pub mod app;
 pub mod asr;
 pub mod audio;
 pub mod config;
+pub mod doctor;
 pub mod error;
+pub mod formatting;
+pub mod history;
+pub mod hooks;
 pub mod keyboard;
 pub mod logging;
+pub mod output;
+pub mod recordings;
+pub mod recovery;
+pub mod status;
+pub mod window;
@@ -1,20 +1,155 @@
+use anyhow::Result;
 use env_logger::Builder;
 use log::{LevelFilter, info};
 use whisper_rs::install_logging_hooks;
 
-pub fn init_logging() {
-    install_logging_hooks();
+/// Where log records (transcripts, errors, ...) are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogTarget {
+    /// The terminal (stderr)
+    #[default]
+    Console,
+    /// The system log
+    #[cfg(feature = "syslog")]
+    Syslog,
+    /// Both the terminal and the system log
+    #[cfg(feature = "syslog")]
+    Both,
+}
 
+/// Installs the logger for `target`.
+///
+/// The level defaults to debug in debug builds and info otherwise, and can be
+/// overridden with `RUST_LOG`. It applies to every target.
+pub fn init_logging(target: LogTarget) -> Result<()> {
+    install_logging_hooks();
     #[cfg(debug_assertions)]
     let default_level = LevelFilter::Debug;
     #[cfg(not(debug_assertions))]
     let default_level = LevelFilter::Info;
-    Builder::from_default_env()
+    let console = Builder::from_default_env()
         .filter_level(LevelFilter::Off)
         .filter_module("whispering", default_level)
         .format_timestamp_secs()
         .format_module_path(false)
-        .init();
-
+        .build();
+    let max_level = console.filter();
+    let logger: Box<dyn log::Log> = match target {
+        LogTarget::Console => Box::new(console),
+        #[cfg(feature = "syslog")]
+        LogTarget::Syslog => Box::new(system_log::Filtered::new(console, false)?),
+        #[cfg(feature = "syslog")]
+        LogTarget::Both => Box::new(system_log::Filtered::new(console, true)?),
+    };
+    log::set_boxed_logger(logger)?;
+    log::set_max_level(max_level);
     info!("Logging system initialized");
+    Ok(())
+}
+
+#[cfg(feature = "syslog")]
+mod system_log {
+    use anyhow::{Result, anyhow};
+    use log::{Log, Metadata, Record};
+    use syslog::{BasicLogger, Facility, Formatter3164};
+
+    /// Sends the records accepted by the console filter to `inner`, and
+    /// optionally to the console as well.
+    pub struct Filtered<L> {
+        console: env_logger::Logger,
+        echo: bool,
+        inner: L,
+    }
+
+    impl Filtered<BasicLogger> {
+        /// Connects to the local syslog daemon.
+        pub fn new(console: env_logger::Logger, echo: bool) -> Result<Self> {
+            let formatter = Formatter3164 {
+                facility: Facility::LOG_USER,
+                hostname: None,
+                process: "whispering".into(),
+                pid: std::process::id(),
+            };
+            let logger = syslog::unix(formatter)
+                .map_err(|err| anyhow!("Cannot connect to syslog: {err}"))?;
+            Ok(Self {
+                console,
+                echo,
+                inner: BasicLogger::new(logger),
+            })
+        }
+    }
+
+    impl<L: Log> Log for Filtered<L> {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.console.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.console.matches(record) {
+                return;
+            }
+            if self.echo {
+                self.console.log(record);
+            }
+            self.inner.log(record);
+        }
+
+        fn flush(&self) {
+            self.console.flush();
+            self.inner.flush();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use log::{Level, LevelFilter};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorded(Mutex<Vec<String>>);
+
+        impl Log for Recorded {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record) {
+                if let Ok(mut lines) = self.0.lock() {
+                    lines.push(record.args().to_string());
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        #[test]
+        fn test_respects_level() -> Result<()> {
+            let console = env_logger::Builder::new()
+                .filter_module("whispering", LevelFilter::Info)
+                .build();
+            let logger = Filtered {
+                console,
+                echo: false,
+                inner: Recorded::default(),
+            };
+            for (level, target, message) in [
+                (Level::Info, "whispering::app", "Transcribed: hello"),
+                (Level::Debug, "whispering::app", "too verbose"),
+                (Level::Error, "whisper_rs", "other crate"),
+            ] {
+                logger.log(
+                    &Record::builder()
+                        .level(level)
+                        .target(target)
+                        .args(format_args!("{message}"))
+                        .build(),
+                );
+            }
+            let lines = logger.inner.0.lock().map_err(|e| anyhow!("{e}"))?;
+            assert_eq!(*lines, vec!["Transcribed: hello".to_string()]);
+            Ok(())
+        }
+    }
 }
@@ -7,6 +7,9 @@
 //! - `keyboard`: Manages keyboard shortcuts and user input
 //! - `whisper`: Provides speech recognition using the Whisper model
 //! - `app`: Contains the main application logic and state management
+//! - `status`: Optional terminal status line
+//! - `doctor`: Clipboard, synthetic input and audio input diagnostics
+//! - `formatting`: Transcript post-processing
 //!
 //! # Configuration
 //! The application can be configured through a TOML file named `config.toml` in the current directory:
@@ -31,23 +34,57 @@
 #![deny(clippy::panic, clippy::unwrap_used, clippy::expect_used)]
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-
-mod app;
-mod asr;
-mod audio;
-mod config;
-mod keyboard;
-mod logging;
+use whispering::config::Config;
+use whispering::logging::LogTarget;
+use whispering::{app, audio, doctor, logging};
 
 /// Command line arguments for the Whispering application
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the configuration file
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the configuration file, can be repeated to layer several
+    /// files (later files override earlier ones)
     #[arg(short, long)]
-    config: Option<PathBuf>,
+    config: Vec<PathBuf>,
+
+    /// Use in-memory defaults instead of writing a default config file when none exists
+    #[arg(long)]
+    no_write_config: bool,
+
+    /// Show the current state (idle/recording/transcribing) on a status line in the terminal
+    #[arg(long)]
+    tty_status: bool,
+
+    /// Exit after the first transcription, also printed to stdout
+    #[arg(long)]
+    once: bool,
+
+    /// Where to write logs, including transcripts and errors
+    #[arg(long, value_enum, default_value_t)]
+    log_target: LogTarget,
+}
+
+/// Subcommands, running the dictation loop when none is given
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that the clipboard, synthetic input and audio input work on
+    /// this machine
+    Doctor,
+    /// List the input devices `audio.device` can select, with their
+    /// supported formats
+    ListDevices,
+    /// Inspect the configuration
+    Config {
+        /// Print the configuration in effect (after merging every `--config`
+        /// file) as TOML
+        #[arg(long, required = true)]
+        show: bool,
+    },
 }
 
 /// Main entry point for the Whispering application.
@@ -60,10 +97,31 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     // Initialize logging
-    logging::init_logging();
+    logging::init_logging(args.log_target)?;
+
+    match args.command {
+        Some(Command::Doctor) => return doctor::run(&Config::load(&args.config)?),
+        Some(Command::ListDevices) => {
+            let config = Config::load(&args.config)?;
+            println!("{}", audio::list_devices(&config.audio)?);
+            return Ok(());
+        }
+        Some(Command::Config { .. }) => {
+            let config = Config::load(&args.config)?;
+            print!("{}", toml::to_string(&config)?);
+            return Ok(());
+        }
+        None => {}
+    }
 
     // Create and run the application
-    let mut app = app::App::new(args.config).await?;
+    let mut app = app::App::new(
+        args.config,
+        !args.no_write_config,
+        args.tty_status,
+        args.once,
+    )
+    .await?;
     app.run().await?;
 
     Ok(())
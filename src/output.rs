@@ -0,0 +1,191 @@
+//! Transcripts written to files, see `[output]`.
+//!
+//! With `output.srt_path`, the segments of every pasted transcription are
+//! written as SRT subtitles: numbered cues with their `HH:MM:SS,mmm` start
+//! and end. In `append` mode the cues go after the existing ones, numbered
+//! and timed after the last one, so the file stays a valid subtitle track.
+
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Write as _};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::debug;
+
+use crate::asr::{Segment, post_process};
+use crate::config::{Config, SrtMode};
+
+/// Writes the segments to `output.srt_path`, a no-op when it isn't set or
+/// nothing was timed.
+pub fn write_srt(segments: &[Segment], config: &Config) -> Result<()> {
+    let Some(path) = &config.output.srt_path else {
+        return Ok(());
+    };
+    if segments.is_empty() {
+        debug!("No timed segments to write to {}", path.display());
+        return Ok(());
+    }
+    let segments: Vec<Segment> = segments
+        .iter()
+        .map(|segment| {
+            let text = post_process(&segment.text, config);
+            Segment::new(text, segment.start_ms, segment.end_ms)
+        })
+        .collect();
+    match config.output.srt_mode {
+        SrtMode::Overwrite => std::fs::write(path, srt(&segments, 1, 0)),
+        SrtMode::Append => append(path, &segments),
+    }
+    .context(format!("Writing {}", path.display()))?;
+    debug!("Wrote {} cues to {}", segments.len(), path.display());
+    Ok(())
+}
+
+/// Appends the cues after the last one of the file.
+fn append(path: &Path, segments: &[Segment]) -> std::io::Result<()> {
+    let existing = match std::fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let (last_index, last_end) = last_cue(&existing).unwrap_or((0, 0));
+    // Cues are separated by a blank line
+    let separator = if existing.trim().is_empty() || existing.ends_with("\n\n") {
+        ""
+    } else if existing.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    write!(
+        file,
+        "{separator}{}",
+        srt(segments, last_index + 1, last_end)
+    )
+}
+
+/// The segments as SRT cues numbered from `first_index`, shifted by
+/// `offset_ms`. Segments without text are skipped.
+fn srt(segments: &[Segment], first_index: usize, offset_ms: i64) -> String {
+    let mut srt = String::new();
+    let cues = segments
+        .iter()
+        .filter(|segment| !segment.text.trim().is_empty());
+    for (index, segment) in (first_index..).zip(cues) {
+        // Writing to a String can't fail
+        let _ = write!(
+            srt,
+            "{index}\n{} --> {}\n{}\n\n",
+            timestamp(offset_ms + segment.start_ms),
+            timestamp(offset_ms + segment.end_ms),
+            segment.text.trim()
+        );
+    }
+    srt
+}
+
+/// `HH:MM:SS,mmm`, hours going past 99 when needed.
+fn timestamp(ms: i64) -> String {
+    let ms = ms.max(0);
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Milliseconds of an `HH:MM:SS,mmm` timestamp.
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let (hms, ms) = timestamp.trim().split_once(',')?;
+    let mut parts = hms.split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + ms.parse::<i64>().ok()?)
+}
+
+/// Index and end (in milliseconds) of the last cue of an SRT file.
+fn last_cue(srt: &str) -> Option<(usize, i64)> {
+    let lines: Vec<&str> = srt.lines().map(str::trim).collect();
+    let timing = lines.iter().rposition(|line| line.contains("-->"))?;
+    let index = lines.get(timing.checked_sub(1)?)?.parse().ok()?;
+    let (_, end) = lines[timing].split_once("-->")?;
+    Some((index, parse_timestamp(end)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputConfig;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "00:00:00,000");
+        assert_eq!(timestamp(7), "00:00:00,007");
+        assert_eq!(timestamp(999), "00:00:00,999");
+        assert_eq!(timestamp(61_500), "00:01:01,500");
+        // 3h25m7.089s
+        assert_eq!(timestamp(12_307_089), "03:25:07,089");
+        assert_eq!(timestamp(100 * 3_600_000), "100:00:00,000");
+        assert_eq!(timestamp(-20), "00:00:00,000");
+        for ms in [0, 999, 61_500, 12_307_089] {
+            assert_eq!(parse_timestamp(&timestamp(ms)), Some(ms));
+        }
+        assert_eq!(parse_timestamp("00:01,500"), None);
+    }
+
+    #[test]
+    fn test_srt() {
+        let segments = [
+            Segment::new(" Hello there.", 0, 1500),
+            Segment::new(" ", 1500, 1600),
+            Segment::new(" General Kenobi.", 1600, 3250),
+        ];
+        let srt = srt(&segments, 1, 0);
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,500\nHello there.\n\n\
+             2\n00:00:01,600 --> 00:00:03,250\nGeneral Kenobi.\n\n"
+        );
+        assert_eq!(last_cue(&srt), Some((2, 3250)));
+        assert_eq!(last_cue(""), None);
+    }
+
+    #[test]
+    fn test_write_srt() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("out.srt");
+        let mut config = Config::default();
+        let segments = [Segment::new(" first.", 0, 1000)];
+        write_srt(&segments, &config)?;
+        assert!(!path.exists());
+
+        config.output = OutputConfig {
+            srt_path: Some(path.clone()),
+            srt_mode: SrtMode::Overwrite,
+        };
+        write_srt(&segments, &config)?;
+        write_srt(&segments, &config)?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "1\n00:00:00,000 --> 00:00:01,000\nfirst.\n\n"
+        );
+
+        // Numbered and timed after the existing cues
+        config.output.srt_mode = SrtMode::Append;
+        write_srt(&[Segment::new(" second.", 200, 2000)], &config)?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "1\n00:00:00,000 --> 00:00:01,000\nfirst.\n\n\
+             2\n00:00:01,200 --> 00:00:03,000\nsecond.\n\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! Saved recordings, for inspecting what was transcribed.
+//!
+//! With `[debug] save_recordings`, the audio of every transcription is kept
+//! as a timestamped WAV (or FLAC, see `recording_format`) file in
+//! `cache_dir/recordings`. After each save, the oldest files are deleted to
+//! stay within `max_recordings` and `max_recordings_mb`.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "flac")]
+use anyhow::anyhow;
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{debug, warn};
+
+use crate::config::{Config, RecordingFormat};
+
+const RECORDINGS_DIR: &str = "recordings";
+
+/// Extensions of the recordings, whatever format saved them.
+const EXTENSIONS: &[&str] = &["wav", "flac"];
+
+/// Writes 16kHz mono samples to a file.
+type Encode = fn(&Path, &[f32]) -> Result<()>;
+
+/// A saved recording, as seen by the cleanup pass.
+#[derive(Debug, Clone, PartialEq)]
+struct Saved {
+    path: PathBuf,
+    modified: SystemTime,
+    bytes: u64,
+}
+
+/// Where recordings are saved, and how many are kept.
+#[derive(Debug, Clone)]
+pub struct Recordings {
+    dir: PathBuf,
+    format: RecordingFormat,
+    max_files: Option<usize>,
+    max_bytes: Option<u64>,
+}
+
+impl Recordings {
+    /// Returns `None` when saving recordings is disabled.
+    pub fn new(config: &Config) -> Option<Self> {
+        let debug = &config.debug;
+        debug.save_recordings.then(|| Self {
+            dir: config.paths.cache_dir.join(RECORDINGS_DIR),
+            format: debug.recording_format,
+            max_files: debug.max_recordings,
+            max_bytes: debug.max_recordings_mb.map(|mb| mb * 1024 * 1024),
+        })
+    }
+
+    /// Saves the samples (16kHz mono), then deletes the oldest recordings
+    /// beyond the limits.
+    pub fn save(&self, samples: &[f32]) -> Result<PathBuf> {
+        let (encode, extension) = encoder(self.format)?;
+        std::fs::create_dir_all(&self.dir).context(format!("Creating {}", self.dir.display()))?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = self.dir.join(format!("recording-{millis}.{extension}"));
+        encode(&path, samples).context(format!("Saving the recording to {}", path.display()))?;
+        debug!("Saved the recording to {}", path.display());
+        self.cleanup()?;
+        Ok(path)
+    }
+
+    /// Deletes the oldest recordings beyond the limits.
+    fn cleanup(&self) -> Result<()> {
+        for path in expired(list(&self.dir)?, self.max_files, self.max_bytes) {
+            debug!("Deleting the old recording {}", path.display());
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!("Could not delete {}: {err}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The encoder of `format`, and the extension of its files.
+fn encoder(format: RecordingFormat) -> Result<(Encode, &'static str)> {
+    match format {
+        RecordingFormat::Wav => Ok((write_wav, "wav")),
+        #[cfg(feature = "flac")]
+        RecordingFormat::Flac => Ok((write_flac, "flac")),
+        #[cfg(not(feature = "flac"))]
+        RecordingFormat::Flac => anyhow::bail!("FLAC recordings require the `flac` feature"),
+    }
+}
+
+fn write_wav(path: &Path, samples: &[f32]) -> Result<()> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Writes 16-bit FLAC, the samples being clamped to [-1, 1].
+#[cfg(feature = "flac")]
+fn write_flac(path: &Path, samples: &[f32]) -> Result<()> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    let samples: Vec<i32> = samples
+        .iter()
+        .map(|&sample| (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i32)
+        .collect();
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, err)| anyhow!("Invalid FLAC encoder config: {err:?}"))?;
+    let source = flacenc::source::MemSource::from_samples(&samples, 1, 16, 16000);
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|err| anyhow!("Encoding FLAC: {err:?}"))?;
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|err| anyhow!("Encoding FLAC: {err:?}"))?;
+    std::fs::write(path, sink.as_slice())?;
+    Ok(())
+}
+
+/// The recordings of `dir`.
+fn list(dir: &Path) -> Result<Vec<Saved>> {
+    let mut saved = Vec::new();
+    for entry in std::fs::read_dir(dir).context(format!("Listing {}", dir.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        let recording = path
+            .extension()
+            .is_some_and(|ext| EXTENSIONS.iter().any(|known| ext == *known));
+        if !recording {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        saved.push(Saved {
+            path,
+            modified: metadata.modified()?,
+            bytes: metadata.len(),
+        });
+    }
+    Ok(saved)
+}
+
+/// The recordings to delete, oldest first, so that at most `max_files`
+/// remain taking at most `max_bytes`. The newest one is always kept.
+fn expired(
+    mut saved: Vec<Saved>,
+    max_files: Option<usize>,
+    max_bytes: Option<u64>,
+) -> Vec<PathBuf> {
+    // Newest first, names (timestamps) break ties
+    saved.sort_by(|a, b| (b.modified, &b.path).cmp(&(a.modified, &a.path)));
+    let mut total = 0;
+    let kept = saved
+        .iter()
+        .enumerate()
+        .take_while(|(i, recording)| {
+            total += recording.bytes;
+            *i == 0
+                || (max_files.is_none_or(|max| *i < max)
+                    && max_bytes.is_none_or(|max| total <= max))
+        })
+        .count();
+    saved
+        .drain(kept..)
+        .rev()
+        .map(|recording| recording.path)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::time::Duration;
+
+    fn saved(name: &str, age_secs: u64, bytes: u64) -> Saved {
+        Saved {
+            path: PathBuf::from(name),
+            modified: UNIX_EPOCH + Duration::from_secs(1000 - age_secs),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_expired_oldest_first() {
+        let recordings = vec![
+            saved("b.wav", 20, 100),
+            saved("d.wav", 0, 100),
+            saved("a.wav", 30, 100),
+            saved("c.wav", 10, 100),
+        ];
+        assert!(expired(recordings.clone(), None, None).is_empty());
+        assert_eq!(
+            expired(recordings.clone(), Some(2), None),
+            [PathBuf::from("a.wav"), PathBuf::from("b.wav")]
+        );
+        // 300 bytes fit the three newest
+        assert_eq!(
+            expired(recordings.clone(), None, Some(350)),
+            [PathBuf::from("a.wav")]
+        );
+        // The tighter limit wins
+        assert_eq!(
+            expired(recordings.clone(), Some(3), Some(250)),
+            [PathBuf::from("a.wav"), PathBuf::from("b.wav")]
+        );
+        // A recording larger than the limit is kept until the next one
+        assert_eq!(
+            expired(vec![saved("big.wav", 0, 500)], Some(1), Some(100)),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    fn test_encoder() -> Result<()> {
+        let (_, extension) = encoder(RecordingFormat::Wav)?;
+        assert_eq!(extension, "wav");
+        let flac = encoder(RecordingFormat::Flac).map(|(_, extension)| extension);
+        if cfg!(feature = "flac") {
+            assert_eq!(flac?, "flac");
+        } else {
+            assert!(flac.is_err());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn test_save_flac() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config::default();
+        config.paths.cache_dir = dir.path().to_path_buf();
+        config.debug.save_recordings = true;
+        config.debug.recording_format = RecordingFormat::Flac;
+        let recordings = Recordings::new(&config).ok_or(anyhow!("Saving is enabled"))?;
+        let path = recordings.save(&[0.1; 1600])?;
+        assert_eq!(path.extension().and_then(|ext| ext.to_str()), Some("flac"));
+        assert!(std::fs::read(&path)?.starts_with(b"fLaC"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_cleanup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config::default();
+        config.paths.cache_dir = dir.path().to_path_buf();
+        assert!(Recordings::new(&config).is_none());
+        config.debug.save_recordings = true;
+        config.debug.max_recordings = Some(2);
+        let recordings = Recordings::new(&config).ok_or(anyhow!("Saving is enabled"))?;
+
+        let first = recordings.save(&[0.1; 160])?;
+        std::thread::sleep(Duration::from_millis(5));
+        recordings.save(&[0.2; 160])?;
+        std::thread::sleep(Duration::from_millis(5));
+        let last = recordings.save(&[0.3; 160])?;
+        let left = list(&dir.path().join(RECORDINGS_DIR))?;
+        assert_eq!(left.len(), 2);
+        assert!(!first.exists());
+        assert!(last.exists());
+        Ok(())
+    }
+}
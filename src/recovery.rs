@@ -0,0 +1,140 @@
+//! Crash recovery of in-flight audio.
+//!
+//! With `[debug] crash_recovery`, the samples being transcribed are saved to
+//! the cache directory first and removed once the transcription succeeded. A
+//! file left over by a crash is transcribed on the next startup.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use log::{debug, info};
+
+use crate::config::Config;
+
+const PENDING_FILE: &str = "pending.wav";
+
+/// Location of the audio saved while a transcription is running.
+#[derive(Debug, Clone)]
+pub struct CrashRecovery {
+    path: PathBuf,
+}
+
+impl CrashRecovery {
+    /// Returns `None` when crash recovery is disabled.
+    pub fn new(config: &Config) -> Option<Self> {
+        config.debug.crash_recovery.then(|| Self {
+            path: config.paths.cache_dir.join(PENDING_FILE),
+        })
+    }
+
+    /// Saves the samples (16kHz mono) before transcribing them.
+    pub fn save(&self, samples: &[f32]) -> Result<()> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(&self.path, spec)
+            .context(format!("Saving pending audio to {}", self.path.display()))?;
+        for &sample in samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        debug!("Saved pending audio to {}", self.path.display());
+        Ok(())
+    }
+
+    /// Removes the saved samples.
+    pub fn clear(&self) -> Result<()> {
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)
+                .context(format!("Removing pending audio {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Takes the audio left over by a previous crash, if any.
+    ///
+    /// The file is removed right away, so audio which crashes the
+    /// transcription isn't retried on every startup.
+    pub fn take_leftover(&self) -> Result<Option<Vec<f32>>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        info!("Found audio left over by a crash: {}", self.path.display());
+        let reader = WavReader::open(&self.path)
+            .context(format!("Reading pending audio {}", self.path.display()));
+        let samples = reader.map(|reader| {
+            reader
+                .into_samples::<f32>()
+                .map(|s| s.unwrap_or(0.0))
+                .collect()
+        });
+        self.clear()?;
+        samples.map(Some)
+    }
+}
+
+/// Runs `transcribe` on `samples`, keeping a copy on disk until it succeeds.
+pub fn protect<T>(
+    recovery: Option<&CrashRecovery>,
+    samples: Vec<f32>,
+    transcribe: impl FnOnce(Vec<f32>) -> Result<T>,
+) -> Result<T> {
+    let Some(recovery) = recovery else {
+        return transcribe(samples);
+    };
+    recovery.save(&samples)?;
+    let result = transcribe(samples)?;
+    recovery.clear()?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, bail};
+
+    fn recovery(dir: &std::path::Path) -> Result<CrashRecovery> {
+        let mut config = Config::default();
+        assert!(CrashRecovery::new(&config).is_none());
+        config.paths.cache_dir = dir.to_path_buf();
+        config.debug.crash_recovery = true;
+        CrashRecovery::new(&config).ok_or(anyhow!("Crash recovery is enabled"))
+    }
+
+    #[test]
+    fn test_pending_audio_removed_after_success() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recovery = recovery(dir.path())?;
+        let pending = dir.path().join(PENDING_FILE);
+
+        let text = protect(Some(&recovery), vec![0.1, 0.2], |samples| {
+            // Saved before the transcription runs
+            assert!(pending.exists());
+            Ok(format!("{} samples", samples.len()))
+        })?;
+        assert_eq!(text, "2 samples");
+        assert!(!pending.exists());
+        assert!(recovery.take_leftover()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_audio_kept_on_failure() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recovery = recovery(dir.path())?;
+
+        let result = protect(Some(&recovery), vec![0.1, 0.2], |_| -> Result<()> {
+            bail!("crashed")
+        });
+        assert!(result.is_err());
+
+        assert_eq!(recovery.take_leftover()?, Some(vec![0.1, 0.2]));
+        // Only recovered once
+        assert!(recovery.take_leftover()?.is_none());
+        Ok(())
+    }
+}
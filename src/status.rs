@@ -0,0 +1,116 @@
+//! Terminal status line.
+//!
+//! Renders the current state (idle, recording, transcribing) on a single line
+//! updated in place with carriage returns. Only active when requested and
+//! stdout is a terminal, so piped output is left untouched.
+
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::info;
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// What the application is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Waiting for the activation keys
+    Idle,
+    /// Recording audio (push to talk or toggle)
+    Recording,
+    /// VAD is armed and waiting for speech
+    Listening,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Idle => write!(f, "idle"),
+            Status::Recording => write!(f, "recording"),
+            Status::Listening => write!(f, "listening"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    status: Status,
+    transcribing: bool,
+    frame: usize,
+}
+
+impl Inner {
+    fn render(&self) {
+        let line = if self.transcribing {
+            format!("{} transcribing", SPINNER[self.frame % SPINNER.len()])
+        } else if self.status == Status::Idle {
+            format!("  {}", self.status)
+        } else {
+            format!("{} {}", SPINNER[self.frame % SPINNER.len()], self.status)
+        };
+        let mut stdout = std::io::stdout().lock();
+        // Clear the line, then draw the status without a newline.
+        let _ = write!(stdout, "\r\x1b[2K{line}");
+        let _ = stdout.flush();
+    }
+}
+
+/// Handle to the status line, cheap to clone and share with tasks.
+#[derive(Debug, Clone)]
+pub struct StatusLine {
+    inner: Option<Arc<Mutex<Inner>>>,
+}
+
+impl StatusLine {
+    /// Creates the status line, disabled unless `enabled` and stdout is a TTY.
+    ///
+    /// When active, a background task animates the spinner.
+    pub fn new(enabled: bool) -> Self {
+        if !enabled {
+            return Self { inner: None };
+        }
+        if !std::io::stdout().is_terminal() {
+            info!("stdout is not a terminal, disabling the status line");
+            return Self { inner: None };
+        }
+        let inner = Arc::new(Mutex::new(Inner {
+            status: Status::Idle,
+            transcribing: false,
+            frame: 0,
+        }));
+        let ticker = inner.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(250));
+            loop {
+                interval.tick().await;
+                if let Ok(mut inner) = ticker.lock() {
+                    inner.frame += 1;
+                    inner.render();
+                }
+            }
+        });
+        Self { inner: Some(inner) }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Inner)) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        if let Ok(mut inner) = inner.lock() {
+            f(&mut inner);
+            inner.render();
+        }
+    }
+
+    /// Sets the recording state.
+    pub fn set(&self, status: Status) {
+        self.update(|inner| inner.status = status);
+    }
+
+    /// Marks a transcription as running or done.
+    pub fn transcribing(&self, transcribing: bool) {
+        self.update(|inner| inner.transcribing = transcribing);
+    }
+}
@@ -0,0 +1,83 @@
+//! Focused window detection, to keep dictation away from some applications.
+//!
+//! Detection relies on platform tools (`xdotool` on X11, `osascript` on
+//! macOS). When it isn't available, dictation is allowed and a warning is
+//! logged once.
+
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+
+/// Detection failures were already reported.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Title of the focused window (the frontmost application on macOS).
+pub fn focused_window() -> Result<String> {
+    #[cfg(target_os = "macos")]
+    return command(
+        "osascript",
+        &[
+            "-e",
+            "tell application \"System Events\" to get name of first application process whose frontmost is true",
+        ],
+    );
+    #[cfg(not(target_os = "macos"))]
+    return command("xdotool", &["getactivewindow", "getwindowname"]);
+}
+
+/// Runs `program`, returns its trimmed output.
+fn command(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .context(format!("Running {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether the `focused` window matches one of `blocked_apps`
+/// (case-insensitive substrings). Fails open: an undetected window isn't
+/// blocked.
+pub fn is_blocked(focused: Result<String>, blocked_apps: &[String]) -> bool {
+    let name = match focused {
+        Ok(name) => name.to_lowercase(),
+        Err(err) => {
+            if WARNED.swap(true, Ordering::Relaxed) {
+                debug!("Cannot detect the focused window: {err:#}");
+            } else {
+                warn!("Cannot detect the focused window, blocked_apps are ignored: {err:#}");
+            }
+            return false;
+        }
+    };
+    blocked_apps
+        .iter()
+        .any(|app| name.contains(&app.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_is_blocked() {
+        let blocked = vec!["KeePassXC".to_string(), "1password".to_string()];
+        assert!(is_blocked(
+            Ok("Passwords.kdbx - KeePassXC".to_string()),
+            &blocked
+        ));
+        assert!(is_blocked(Ok("1Password".to_string()), &blocked));
+        assert!(!is_blocked(Ok("Inbox - Thunderbird".to_string()), &blocked));
+        assert!(!is_blocked(Ok("KeePassXC".to_string()), &[]));
+        // Fails open
+        assert!(!is_blocked(Err(anyhow!("xdotool not found")), &blocked));
+    }
+}
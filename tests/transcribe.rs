@@ -0,0 +1,87 @@
+//! End-to-end transcription with a real Whisper model.
+//!
+//! Downloads `ggml-tiny.en` (about 75 MB) on first run, so it is behind the
+//! `model-tests` feature and ignored by default:
+//!
+//! ```bash
+//! cargo test --features model-tests -- --ignored
+//! ```
+//!
+//! See `tests/fixtures/README.md` for the fixture's provenance.
+#![cfg(feature = "model-tests")]
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use whispering::asr::{Asr, Transcriber, download_model};
+use whispering::config::Config;
+
+/// The committed 16kHz mono clip.
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/jfk.wav")
+}
+
+/// A 48kHz stereo copy of the `fixture` at `path`, each sample repeated on
+/// both channels and three times over, like a typical device records.
+fn resampled_copy(path: &Path) -> Result<()> {
+    let mut reader = hound::WavReader::open(fixture())?;
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 3 * reader.spec().sample_rate,
+        ..reader.spec()
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in reader.samples::<i16>() {
+        let sample = sample?;
+        for _ in 0..3 * 2 {
+            writer.write_sample(sample)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "downloads a model"]
+async fn test_transcribe_fixture() -> Result<()> {
+    let config = Config::builder()
+        .model("ggerganov/whisper.cpp", "ggml-tiny.en.bin")
+        .notify(false)
+        .build()?;
+    let model_path = download_model(&config).await?;
+    let mut asr = Asr::new(&model_path)?;
+    asr.load()?;
+
+    let samples = asr.samples_from_file(&fixture())?;
+    let text = asr.run(samples.clone(), &config)?.to_lowercase();
+
+    // The tiny model isn't word perfect, only look for the key words
+    for word in ["ask not", "country", "can do for you"] {
+        assert!(text.contains(word), "{word:?} not found in {text:?}");
+    }
+
+    // The model is dropped after each run
+    asr.load()?;
+    let segments = asr.run_segments(samples.clone(), &config)?;
+    assert!(!segments.is_empty());
+    // Ordered milliseconds within the 11s clip
+    let mut previous_end = 0;
+    for segment in &segments {
+        assert!(previous_end <= segment.start_ms, "{segments:?}");
+        assert!(segment.start_ms <= segment.end_ms, "{segments:?}");
+        previous_end = segment.end_ms;
+    }
+    assert!(previous_end > 5000 && previous_end <= 11500, "{segments:?}");
+
+    // The same words from a device's format, downmixed and resampled
+    let dir = tempfile::tempdir()?;
+    let stereo = dir.path().join("jfk-48k-stereo.wav");
+    resampled_copy(&stereo)?;
+    asr.load()?;
+    let resampled = asr.samples_from_file(&stereo)?;
+    assert!(resampled.len().abs_diff(samples.len()) < 16000 / 10);
+    let text = asr.run(resampled, &config)?.to_lowercase();
+    for word in ["ask not", "country", "can do for you"] {
+        assert!(text.contains(word), "{word:?} not found in {text:?}");
+    }
+    Ok(())
+}